@@ -0,0 +1,49 @@
+#[macro_use]
+extern crate criterion;
+extern crate radix_trie;
+
+use criterion::{BatchSize, Criterion};
+use radix_trie::Trie;
+use std::collections::BTreeMap;
+
+// File paths, which share long prefixes, so per-key inserts walk several nodes each.
+fn paths() -> BTreeMap<String, usize> {
+    let mut map = BTreeMap::new();
+    for i in 0..20_000 {
+        let path = format!(
+            "/home/user/projects/crate-{}/src/module_{}/file_{}.rs",
+            i / 1000,
+            i / 50 % 20,
+            i % 50
+        );
+        map.insert(path, i);
+    }
+    map
+}
+
+fn from_btree_map(c: &mut Criterion) {
+    let map = paths();
+    c.bench_function("from_btree_map", |b| {
+        b.iter_batched(|| map.clone(), Trie::from, BatchSize::LargeInput)
+    });
+}
+
+fn insert_each(c: &mut Criterion) {
+    let map = paths();
+    c.bench_function("insert_each_from_btree_map", |b| {
+        b.iter_batched(
+            || map.clone(),
+            |map| {
+                let mut trie = Trie::new();
+                for (k, v) in map {
+                    trie.insert(k, v);
+                }
+                trie
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, from_btree_map, insert_each);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate criterion;
+extern crate radix_trie;
+
+use criterion::{black_box, Criterion};
+use radix_trie::Trie;
+
+const KEYS: usize = 10_000;
+const LOOKUPS: usize = 10_000;
+
+fn keys() -> Vec<String> {
+    (0..KEYS)
+        .map(|i| format!("/api/v1/tenants/{}/resources/{}", i % 100, i))
+        .collect()
+}
+
+// Indices into `keys()` with a Zipf(1) distribution, from a fixed-seed generator, arranged
+// in short bursts of repeated keys.
+fn zipf_workload() -> Vec<usize> {
+    let harmonic: Vec<f64> = (1..=KEYS)
+        .scan(0.0, |sum, k| {
+            *sum += 1.0 / k as f64;
+            Some(*sum)
+        })
+        .collect();
+    let total = harmonic[KEYS - 1];
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut workload = Vec::with_capacity(LOOKUPS);
+    while workload.len() < LOOKUPS {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let u = (state >> 11) as f64 / (1u64 << 53) as f64 * total;
+        let idx = harmonic.partition_point(|&h| h < u).min(KEYS - 1);
+        let burst = 1 + (state % 4) as usize;
+        workload.extend((0..burst).map(|_| idx));
+    }
+    workload.truncate(LOOKUPS);
+    workload
+}
+
+fn zipf_lookups(c: &mut Criterion) {
+    let keys = keys();
+    let workload = zipf_workload();
+    let trie: Trie<String, usize> = keys.iter().cloned().zip(0..).collect();
+    let cached = trie.clone().with_lookup_cache();
+
+    c.bench_function("zipf_get", |b| {
+        b.iter(|| {
+            for &i in &workload {
+                black_box(trie.get(&keys[i]));
+            }
+        })
+    });
+    c.bench_function("zipf_get_cached", |b| {
+        b.iter(|| {
+            for &i in &workload {
+                black_box(cached.get(&keys[i]));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, zipf_lookups);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+#[macro_use]
+extern crate criterion;
+extern crate radix_trie;
+
+use criterion::{black_box, Criterion};
+use radix_trie::Trie;
+
+// 64-byte keys that share long prefixes, so lookups spend most of their time comparing long
+// edge fragments.
+fn long_keys() -> Vec<Vec<u8>> {
+    let mut keys = vec![];
+    for group in 0..16u8 {
+        for i in 0..64u8 {
+            let mut key = vec![group; 48];
+            key.extend((0..16).map(|j| i.wrapping_mul(j as u8 + 1)));
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+fn get_long_keys(c: &mut Criterion) {
+    let keys = long_keys();
+    let trie: Trie<Vec<u8>, usize> = keys.iter().cloned().zip(0..).collect();
+    c.bench_function("get_64_byte_keys", |b| {
+        b.iter(|| {
+            for k in &keys {
+                black_box(trie.get(k));
+            }
+        })
+    });
+}
+
+fn insert_long_keys(c: &mut Criterion) {
+    let keys = long_keys();
+    c.bench_function("insert_64_byte_keys", |b| {
+        b.iter(|| {
+            let mut trie = Trie::new();
+            for k in &keys {
+                trie.insert(k.clone(), ());
+            }
+            trie
+        })
+    });
+}
+
+criterion_group!(benches, get_long_keys, insert_long_keys);
+criterion_main!(benches);
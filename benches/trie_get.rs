@@ -0,0 +1,50 @@
+#[macro_use]
+extern crate criterion;
+extern crate radix_trie;
+
+use criterion::{black_box, Criterion};
+use radix_trie::Trie;
+
+const KEYS: usize = 10_000;
+
+fn keys() -> Vec<String> {
+    (0..KEYS)
+        .map(|i| format!("/api/v1/tenants/{}/resources/{}", i % 100, i))
+        .collect()
+}
+
+// Look up every key by its borrowed form, which is the common case for string and byte keys.
+fn borrowed_lookups(c: &mut Criterion) {
+    let keys = keys();
+    let strings: Trie<String, usize> = keys.iter().cloned().zip(0..).collect();
+    let bytes: Trie<Vec<u8>, usize> = keys
+        .iter()
+        .map(|k| k.clone().into_bytes())
+        .zip(0..)
+        .collect();
+
+    c.bench_function("get_str", |b| {
+        b.iter(|| {
+            for k in &keys {
+                black_box(strings.get(k.as_str()));
+            }
+        })
+    });
+    c.bench_function("get_byte_slice", |b| {
+        b.iter(|| {
+            for k in &keys {
+                black_box(bytes.get(k.as_bytes()));
+            }
+        })
+    });
+    c.bench_function("get_ancestor_value_str", |b| {
+        b.iter(|| {
+            for k in &keys {
+                black_box(strings.get_ancestor_value(k.as_str()));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, borrowed_lookups);
+criterion_main!(benches);
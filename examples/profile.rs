@@ -0,0 +1,198 @@
+//! Repeatable timing harness for trie operations on the words of some text files.
+//!
+//! Usage:
+//!
+//! ```text
+//! cargo run --release --example profile -- [--op OP] [--iters N] [--trace PATH] FILE...
+//! ```
+//!
+//! where `OP` is one of `insert`, `get`, `insert_remove` or `prefix_iter` (default `insert`),
+//! and `N` is the number of timed iterations (default 10). The files are read and split into
+//! words once up front, so that the timings only cover trie work.
+//!
+//! With `--trace`, a flamegraph of the iterations is also written to `PATH`, as HTML. This
+//! needs the `profile` feature:
+//!
+//! ```text
+//! cargo run --release --features profile --example profile -- --trace flame.html FILE...
+//! ```
+#[cfg(feature = "profile")]
+extern crate flame;
+#[cfg(feature = "profile")]
+extern crate flamer;
+extern crate radix_trie;
+
+#[cfg(feature = "profile")]
+use flamer::flame;
+use radix_trie::{Trie, TrieCommon};
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: profile [--op insert|get|insert_remove|prefix_iter] [--iters N] \
+                     [--trace PATH] FILE...";
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Insert,
+    Get,
+    InsertRemove,
+    PrefixIter,
+}
+
+impl Op {
+    fn parse(s: &str) -> Option<Op> {
+        match s {
+            "insert" => Some(Op::Insert),
+            "get" => Some(Op::Get),
+            "insert_remove" => Some(Op::InsertRemove),
+            "prefix_iter" => Some(Op::PrefixIter),
+            _ => None,
+        }
+    }
+}
+
+struct Args {
+    op: Op,
+    iters: usize,
+    trace: Option<String>,
+    files: Vec<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        op: Op::Insert,
+        iters: 10,
+        trace: None,
+        files: vec![],
+    };
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--op" => {
+                let op = argv.next().ok_or("--op needs a value")?;
+                args.op = Op::parse(&op).ok_or(format!("unknown op: {}", op))?;
+            }
+            "--iters" => {
+                let iters = argv.next().ok_or("--iters needs a value")?;
+                args.iters = iters
+                    .parse()
+                    .map_err(|_| format!("invalid iteration count: {}", iters))?;
+            }
+            "--trace" => {
+                if cfg!(not(feature = "profile")) {
+                    return Err("--trace needs the profile feature".to_string());
+                }
+                args.trace = Some(argv.next().ok_or("--trace needs a path")?);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => args.files.push(arg),
+        }
+    }
+    if args.files.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(args)
+}
+
+fn read_words(files: &[String]) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    for file in files {
+        let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        words.extend(text.split_whitespace().map(str::to_string));
+    }
+    Ok(words)
+}
+
+#[cfg_attr(feature = "profile", flame)]
+fn build(words: &[String]) -> Trie<&str, usize> {
+    let mut trie = Trie::new();
+    for (i, word) in words.iter().enumerate() {
+        trie.insert(word.as_str(), i);
+    }
+    trie
+}
+
+// Run one iteration of `op`, returning the time taken and a checksum to keep the work alive.
+#[cfg_attr(feature = "profile", flame)]
+fn run(op: Op, words: &[String]) -> (Duration, usize) {
+    match op {
+        Op::Insert => {
+            let start = Instant::now();
+            let trie = build(words);
+            (start.elapsed(), trie.len())
+        }
+        Op::Get => {
+            let trie = build(words);
+            let start = Instant::now();
+            let found = words.iter().filter_map(|w| trie.get(w.as_str())).count();
+            (start.elapsed(), found)
+        }
+        Op::InsertRemove => {
+            let start = Instant::now();
+            let mut trie = build(words);
+            let removed = words.iter().filter_map(|w| trie.remove(w.as_str())).count();
+            (start.elapsed(), removed)
+        }
+        Op::PrefixIter => {
+            let trie = build(words);
+            let start = Instant::now();
+            let mut visited = 0;
+            for word in words {
+                let end = word.char_indices().nth(3).map_or(word.len(), |(i, _)| i);
+                if let Some(subtrie) = trie.get_raw_descendant(&word[..end]) {
+                    visited += subtrie.iter().count();
+                }
+            }
+            (start.elapsed(), visited)
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        process::exit(2);
+    });
+    let words = read_words(&args.files).unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        process::exit(1);
+    });
+
+    let mut times = Vec::with_capacity(args.iters);
+    let mut checksum = 0;
+    for _ in 0..args.iters {
+        let (time, sum) = run(args.op, &words);
+        times.push(time);
+        checksum ^= sum;
+    }
+    times.sort();
+
+    #[cfg(feature = "profile")]
+    {
+        if let Some(ref path) = args.trace {
+            let written = fs::File::create(path).and_then(flame::dump_html);
+            if let Err(e) = written {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let total: Duration = times.iter().sum();
+    let mean = total / args.iters.max(1) as u32;
+    println!("op:         {:?}", args.op);
+    println!("words:      {}", words.len());
+    println!("iterations: {}", args.iters);
+    if let (Some(min), Some(max)) = (times.first(), times.last()) {
+        println!("min:        {:?}", min);
+        println!("median:     {:?}", times[times.len() / 2]);
+        println!("mean:       {:?}", mean);
+        println!("max:        {:?}", max);
+    }
+    println!("checksum:   {}", checksum);
+    if let Some(ref path) = args.trace {
+        println!("trace:      {}", path);
+    }
+}
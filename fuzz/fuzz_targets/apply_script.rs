@@ -0,0 +1,14 @@
+//! Apply scripts of operations decoded from the fuzzer's input, checking the trie against a
+//! model after every step.
+//!
+//! Run with `cargo fuzz run apply_script`. Crashing inputs can be added to the regression
+//! scripts in `tests/scripts.rs`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use radix_trie::{script_from_bytes, Oracle, Trie};
+
+fuzz_target!(|data: &[u8]| {
+    Trie::new().apply_script(&script_from_bytes(data), &mut Oracle::new());
+});
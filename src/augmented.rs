@@ -0,0 +1,426 @@
+//! Tries that maintain an aggregate of the values below every node.
+//!
+//! The aggregates live in a tree of their own that mirrors the shape of the trie. A change to
+//! the trie only affects the nodes along the path to the changed key, so after each change
+//! the mirror is brought back in line by walking that path, patching up any node that was
+//! split or merged, and recomputing the aggregates on the way back up.
+
+use keys::*;
+use std::borrow::Borrow;
+use std::sync::OnceLock;
+use trie_node::TrieNode;
+use {AugmentedTrie, NibbleVec, Trie, BRANCH_FACTOR};
+
+/// A summary of a collection of values, which can be built up from the summaries of its parts.
+///
+/// `combine` should be associative, with `identity()` as its identity. Values are combined in
+/// the trie's iteration order, so it needn't be commutative.
+pub trait Aggregate<V> {
+    /// The aggregate of no values.
+    fn identity() -> Self;
+
+    /// The aggregate of a single value.
+    fn from_value(value: &V) -> Self;
+
+    /// The aggregate of the values summarised by `self`, followed by those summarised by
+    /// `other`.
+    fn combine(&self, other: &Self) -> Self;
+}
+
+/// The aggregate of the values below a trie node.
+#[derive(Debug, Clone)]
+pub struct AggregateNode<A> {
+    /// The length of the corresponding trie node's key.
+    len: usize,
+    aggregate: A,
+    children: [Option<Box<AggregateNode<A>>>; BRANCH_FACTOR],
+}
+
+impl<A> AggregateNode<A> {
+    /// Build the aggregates for a trie node and all its descendants.
+    pub fn build<K, V>(node: &TrieNode<K, V>) -> AggregateNode<A>
+    where
+        K: TrieKey,
+        A: Aggregate<V>,
+    {
+        let mut agg = AggregateNode {
+            len: node.key.len(),
+            aggregate: A::identity(),
+            children: Default::default(),
+        };
+        for (slot, child) in agg.children.iter_mut().zip(&node.children) {
+            *slot = child
+                .as_ref()
+                .map(|child| Box::new(AggregateNode::build(child)));
+        }
+        agg.recompute(node);
+        agg
+    }
+
+    // Recompute this node's aggregate from its value and its children's aggregates.
+    fn recompute<K, V>(&mut self, node: &TrieNode<K, V>)
+    where
+        K: TrieKey,
+        A: Aggregate<V>,
+    {
+        let own = node.value().map_or_else(A::identity, A::from_value);
+        self.aggregate = self
+            .children
+            .iter()
+            .flatten()
+            .fold(own, |acc, child| acc.combine(&child.aggregate));
+    }
+
+    /// Bring the aggregates along the path to `nv` back in line with the trie below `node`,
+    /// after the key `nv` has been inserted, removed or had its value changed.
+    ///
+    /// This and `node` correspond to each other, and their key ends at `depth` nibbles.
+    pub fn sync<K, V>(&mut self, node: &TrieNode<K, V>, nv: &NibbleVec, depth: usize)
+    where
+        K: TrieKey,
+        A: Aggregate<V>,
+    {
+        if depth < nv.len() {
+            let bucket = nv.get(depth) as usize;
+            match node.children[bucket] {
+                Some(ref child) => {
+                    let slot = &mut self.children[bucket];
+                    reshape(slot, child, nv, depth);
+                    match match_keys(depth, nv, &child.key) {
+                        KeyMatch::Full | KeyMatch::SecondPrefix => {
+                            let child_depth = depth + child.key.len();
+                            slot.as_mut().unwrap().sync(child, nv, child_depth);
+                        }
+                        // Nothing below a child that `nv` doesn't pass through can have changed.
+                        KeyMatch::FirstPrefix | KeyMatch::Partial(_) => (),
+                    }
+                }
+                None => self.children[bucket] = None,
+            }
+        }
+        self.recompute(node);
+    }
+
+    /// Get the aggregate of the values below `node` whose keys start with `nv`.
+    ///
+    /// This and `node` correspond to each other, and their key ends at `depth` nibbles.
+    pub fn prefix_aggregate<K, V>(&self, node: &TrieNode<K, V>, nv: &NibbleVec, depth: usize) -> A
+    where
+        K: TrieKey,
+        A: Aggregate<V> + Clone,
+    {
+        if depth == nv.len() {
+            return self.aggregate.clone();
+        }
+        let bucket = nv.get(depth) as usize;
+        match (&node.children[bucket], &self.children[bucket]) {
+            (Some(child), Some(agg)) => match match_keys(depth, nv, &child.key) {
+                KeyMatch::Full | KeyMatch::FirstPrefix => agg.aggregate.clone(),
+                KeyMatch::SecondPrefix => agg.prefix_aggregate(child, nv, depth + child.key.len()),
+                KeyMatch::Partial(_) => A::identity(),
+            },
+            _ => A::identity(),
+        }
+    }
+
+    /// Find the aggregates for the node that `get_raw_descendant` finds for `nv` below
+    /// `node`.
+    ///
+    /// This and `node` correspond to each other, and their key ends at `depth` nibbles.
+    pub fn descendant<K, V>(
+        &self,
+        node: &TrieNode<K, V>,
+        nv: &NibbleVec,
+        depth: usize,
+    ) -> Option<&AggregateNode<A>>
+    where
+        K: TrieKey,
+    {
+        if depth == nv.len() {
+            return Some(self);
+        }
+        let bucket = nv.get(depth) as usize;
+        match (&node.children[bucket], &self.children[bucket]) {
+            (Some(child), Some(agg)) => match match_keys(depth, nv, &child.key) {
+                KeyMatch::Full | KeyMatch::FirstPrefix => Some(agg),
+                KeyMatch::SecondPrefix => agg.descendant(child, nv, depth + child.key.len()),
+                KeyMatch::Partial(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The aggregates for the child in `bucket`.
+    pub fn child(&self, bucket: usize) -> Option<&AggregateNode<A>> {
+        self.children[bucket].as_deref()
+    }
+
+    /// The aggregate of the values below the corresponding trie node.
+    pub fn aggregate(&self) -> &A {
+        &self.aggregate
+    }
+}
+
+/// The number of values below a node, which a trie built with `TrieBuilder::count_subtrees`
+/// keeps for every node.
+#[derive(Debug, Clone, Copy)]
+pub struct Count(pub usize);
+
+impl<V> Aggregate<V> for Count {
+    fn identity() -> Self {
+        Count(0)
+    }
+
+    fn from_value(_: &V) -> Self {
+        Count(1)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Count(self.0 + other.0)
+    }
+}
+
+/// Start counting the values below each node of `trie`.
+pub fn enable_counts<K: TrieKey, V>(trie: &mut Trie<K, V>) {
+    trie.counts = Some(OnceLock::from(AggregateNode::build(&trie.node)));
+}
+
+/// The number of values whose keys start with `nv`, if the trie counts them.
+pub fn count_prefix<K: TrieKey, V>(trie: &Trie<K, V>, nv: &NibbleVec) -> Option<usize> {
+    let counts = trie
+        .counts
+        .as_ref()?
+        .get_or_init(|| AggregateNode::build(&trie.node));
+    Some(counts.prefix_aggregate(&trie.node, nv, 0).0)
+}
+
+/// The counts for the node that `Trie::get_raw_descendant` finds for `nv`, if the trie counts
+/// its values.
+pub fn descendant_counts<'a, K: TrieKey, V>(
+    trie: &'a Trie<K, V>,
+    nv: &NibbleVec,
+) -> Option<&'a AggregateNode<Count>> {
+    trie.counts
+        .as_ref()?
+        .get_or_init(|| AggregateNode::build(&trie.node))
+        .descendant(&trie.node, nv, 0)
+}
+
+/// Bring the counts back in line after `nv` has been inserted or removed.
+///
+/// This takes the trie's fields, so that it can be called while they're partly borrowed.
+pub fn sync_counts<K: TrieKey, V>(
+    counts: &mut Option<OnceLock<AggregateNode<Count>>>,
+    node: &TrieNode<K, V>,
+    nv: &NibbleVec,
+) {
+    if let Some(counts) = counts.as_mut().and_then(OnceLock::get_mut) {
+        counts.sync(node, nv, 0);
+    }
+}
+
+/// Throw away the counts after any other change to the trie, to be built again when they're
+/// next needed.
+pub fn reset_counts(counts: &mut Option<OnceLock<AggregateNode<Count>>>) {
+    if let Some(ref mut counts) = *counts {
+        counts.take();
+    }
+}
+
+/// Whether the counts, if there are any, match the trie.
+pub fn check_counts<K: TrieKey, V>(trie: &Trie<K, V>) -> bool {
+    fn check<K: TrieKey, V>(counts: &AggregateNode<Count>, node: &TrieNode<K, V>) -> bool {
+        counts.len == node.key.len()
+            && counts.aggregate.0 == node.compute_size()
+            && counts
+                .children
+                .iter()
+                .zip(&node.children)
+                .all(|pair| match pair {
+                    (Some(counts), Some(child)) => check(counts, child),
+                    (None, None) => true,
+                    _ => false,
+                })
+    }
+    match trie.counts.as_ref().and_then(OnceLock::get) {
+        Some(counts) => check(counts, &trie.node),
+        None => true,
+    }
+}
+
+// Make the aggregate node in `slot` correspond to `node` (the child of a node whose key ends
+// at `depth`), where the trie may have been changed along the path to `nv` in one of these ways:
+//
+// * inserting `nv` created `node` as a new leaf;
+// * inserting `nv` split the node that `slot` was for, making `node` a new branch above it;
+// * removing `nv` merged the node that `slot` was for with its only remaining child.
+fn reshape<K, V, A>(
+    slot: &mut Option<Box<AggregateNode<A>>>,
+    node: &TrieNode<K, V>,
+    nv: &NibbleVec,
+    depth: usize,
+) where
+    K: TrieKey,
+    A: Aggregate<V>,
+{
+    loop {
+        let mut agg = match slot.take() {
+            Some(agg) => agg,
+            None => {
+                *slot = Some(Box::new(AggregateNode::build(node)));
+                return;
+            }
+        };
+
+        if agg.len > node.key.len() {
+            // The old node is the child of the new branch that `nv` doesn't lead to.
+            let split = depth + node.key.len();
+            let bucket = (0..BRANCH_FACTOR)
+                .find(|&i| {
+                    node.children[i].is_some() && (nv.len() == split || nv.get(split) as usize != i)
+                })
+                .unwrap();
+            agg.len -= node.key.len();
+            let mut branch = AggregateNode {
+                len: node.key.len(),
+                aggregate: A::identity(),
+                children: Default::default(),
+            };
+            branch.children[bucket] = Some(agg);
+            *slot = Some(Box::new(branch));
+            return;
+        } else if agg.len < node.key.len() {
+            let bucket = node.key.get(agg.len) as usize;
+            let mut child = agg.children[bucket].take().unwrap();
+            child.len += agg.len;
+            *slot = Some(child);
+        } else {
+            *slot = Some(agg);
+            return;
+        }
+    }
+}
+
+impl<K, V, A> AugmentedTrie<K, V, A>
+where
+    K: TrieKey,
+    A: Aggregate<V>,
+{
+    /// Create an empty augmented trie.
+    pub fn new() -> AugmentedTrie<K, V, A> {
+        AugmentedTrie::from(Trie::new())
+    }
+
+    /// Get a read-only reference to the underlying trie, for lookups and iteration.
+    pub fn as_trie(&self) -> &Trie<K, V> {
+        &self.trie
+    }
+
+    /// Discard the aggregates, returning the underlying trie.
+    pub fn into_trie(self) -> Trie<K, V> {
+        self.trie
+    }
+
+    /// Fetch a reference to the given key's corresponding value, if any.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.trie.get(key)
+    }
+
+    /// Number of key/value pairs stored in the trie.
+    pub fn len(&self) -> usize {
+        self.trie.length
+    }
+
+    /// Determine if the trie contains 0 key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert the given key-value pair, returning any previous value associated with the key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let nv = key.encode();
+        let result = self.trie.insert(key, value);
+        self.aggregates.sync(&self.trie.node, &nv, 0);
+        result
+    }
+
+    /// Remove the value associated with the given key.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let result = self.trie.remove(key);
+        if result.is_some() {
+            self.aggregates.sync(&self.trie.node, &key.encode(), 0);
+        }
+        result
+    }
+
+    /// Change the value for the given key in place, returning the result of `f`, or `None`
+    /// if the key isn't in the trie.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn update_value<Q, F, R>(&mut self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&mut V) -> R,
+    {
+        let result = self.trie.get_mut(key).map(f);
+        if result.is_some() {
+            self.aggregates.sync(&self.trie.node, &key.encode(), 0);
+        }
+        result
+    }
+
+    /// Get the aggregate of the values whose keys start with `prefix`.
+    ///
+    /// This takes time proportional to the length of the prefix, whatever the number of keys.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn aggregate_prefix<Q>(&self, prefix: &Q) -> A
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        A: Clone,
+    {
+        self.aggregates
+            .prefix_aggregate(&self.trie.node, &prefix.encode(), 0)
+    }
+}
+
+impl<K, V, A> From<Trie<K, V>> for AugmentedTrie<K, V, A>
+where
+    K: TrieKey,
+    A: Aggregate<V>,
+{
+    fn from(trie: Trie<K, V>) -> AugmentedTrie<K, V, A> {
+        AugmentedTrie {
+            aggregates: AggregateNode::build(&trie.node),
+            trie,
+        }
+    }
+}
+
+impl<K, V, A> Default for AugmentedTrie<K, V, A>
+where
+    K: TrieKey,
+    A: Aggregate<V>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
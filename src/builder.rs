@@ -0,0 +1,189 @@
+//! Construction of tries with non-default options.
+
+use augmented::enable_counts;
+use keys::*;
+use std::error;
+use std::fmt;
+use std::marker::PhantomData;
+use trie_node::TrieNode;
+use {NibbleVec, Trie, TrieBuilder};
+
+/// What to do when two distinct keys have the same encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Panic, like `Trie::insert`.
+    Panic,
+    /// Return a `BuildError::Collision`.
+    Error,
+}
+
+/// Error returned by `TrieBuilder::build_from_sorted` when the entries break one of the
+/// builder's rules.
+///
+/// Gives back the two keys involved, in iteration order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError<K> {
+    /// Two distinct keys have the same encoding, under `CollisionPolicy::Error`.
+    Collision(K, K),
+    /// The first key is a proper prefix of the second, when building a prefix-free trie.
+    NotPrefixFree(K, K),
+}
+
+impl<K, V> TrieBuilder<K, V>
+where
+    K: TrieKey,
+{
+    /// Create a builder with the default options, which build the same tries as `Trie::new`
+    /// and `Trie::insert`.
+    pub fn new() -> TrieBuilder<K, V> {
+        TrieBuilder {
+            collisions: CollisionPolicy::Panic,
+            prefix_free: false,
+            expected_keys: 0,
+            count_subtrees: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Choose what happens when two distinct keys have the same encoding.
+    pub fn collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = policy;
+        self
+    }
+
+    /// Choose whether to reject keys that are a proper prefix of another key.
+    pub fn prefix_free(mut self, prefix_free: bool) -> Self {
+        self.prefix_free = prefix_free;
+        self
+    }
+
+    /// Say how many entries `build_from_sorted` will be given, so that it can allocate room
+    /// for them all up front when the iterator doesn't know its length.
+    pub fn expected_keys(mut self, n: usize) -> Self {
+        self.expected_keys = n;
+        self
+    }
+
+    /// Choose whether the trie keeps a count of the values below each of its nodes, so that
+    /// the `len` of a subtrie from `Trie::subtrie` (and the other methods that find one)
+    /// takes constant time rather than counting the values.
+    ///
+    /// The counts take memory like a second copy of the trie's nodes. `insert` and `remove`
+    /// keep them up to date; any other change throws them away, to be counted again the
+    /// next time a subtrie is found.
+    pub fn count_subtrees(mut self, count: bool) -> Self {
+        self.count_subtrees = count;
+        self
+    }
+
+    /// Build an empty trie.
+    pub fn build(&self) -> Trie<K, V> {
+        let mut trie = Trie::new();
+        if self.count_subtrees {
+            enable_counts(&mut trie);
+        }
+        trie
+    }
+
+    /// Build a trie from the given entries, in one pass if they're already sorted in the
+    /// trie's iteration order (and after sorting them if not).
+    ///
+    /// If a key is repeated, the last value for it is kept, as if the entries were inserted
+    /// in order.
+    pub fn build_from_sorted<I>(&self, entries: I) -> Result<Trie<K, V>, BuildError<K>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut encoded: Vec<(NibbleVec, K, V)> = Vec::with_capacity(self.expected_keys);
+        encoded.extend(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.encode(), key, value)),
+        );
+        if sorted_common_prefixes(encoded.iter().map(|e| &e.0)).is_none() {
+            // The sort is stable, so repeated keys stay in order.
+            encoded.sort_by(|a, b| compare_keys(&a.0, &b.0));
+        }
+
+        let mut sorted: Vec<(NibbleVec, K, V)> = Vec::with_capacity(encoded.len());
+        for (nv, key, value) in encoded {
+            if let Some(last) = sorted.last_mut() {
+                match match_keys(0, &last.0, &nv) {
+                    KeyMatch::Full => {
+                        if last.1 == key {
+                            last.2 = value;
+                            continue;
+                        }
+                        match self.collisions {
+                            CollisionPolicy::Panic => check_keys(&last.1, &key),
+                            CollisionPolicy::Error => {
+                                let first = sorted.pop().unwrap().1;
+                                return Err(BuildError::Collision(first, key));
+                            }
+                        }
+                    }
+                    // As the keys are sorted, any key that has a prefix in the trie comes
+                    // straight after it, or after another key with the same prefix.
+                    KeyMatch::FirstPrefix if self.prefix_free => {
+                        let prefix = sorted.pop().unwrap().1;
+                        return Err(BuildError::NotPrefixFree(prefix, key));
+                    }
+                    _ => (),
+                }
+            }
+            sorted.push((nv, key, value));
+        }
+
+        let common = sorted_common_prefixes(sorted.iter().map(|e| &e.0))
+            .expect("entries are sorted and distinct");
+        let mut trie = Trie {
+            length: sorted.len(),
+            node: TrieNode::from_sorted(
+                common
+                    .into_iter()
+                    .zip(sorted)
+                    .map(|(common, (nv, key, value))| (common, nv, key, value)),
+            ),
+            counts: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        };
+        if self.count_subtrees {
+            enable_counts(&mut trie);
+        }
+        Ok(trie)
+    }
+}
+
+impl<K, V> Default for TrieBuilder<K, V>
+where
+    K: TrieKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> fmt::Display for BuildError<K>
+where
+    K: fmt::Debug + TrieKey,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::Collision(ref first, ref second) => {
+                write!(
+                    f,
+                    "keys {:?} and {:?} have the same encoding 0x{}",
+                    first,
+                    second,
+                    Hex(&first.encode())
+                )
+            }
+            BuildError::NotPrefixFree(ref prefix, ref key) => {
+                write!(f, "key {:?} is a prefix of key {:?}", prefix, key)
+            }
+        }
+    }
+}
+
+impl<K> error::Error for BuildError<K> where K: fmt::Debug + TrieKey {}
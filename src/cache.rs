@@ -0,0 +1,164 @@
+//! An opt-in cache of recently used nodes, for workloads that look up the same few keys
+//! over and over.
+
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::fmt;
+use {CachedTrie, Trie, TrieKey};
+
+/// The number of recently used keys that a `CachedTrie` remembers.
+const CACHE_SLOTS: usize = 4;
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey,
+{
+    /// Wrap this trie in a `CachedTrie`, which remembers where its most recently used keys
+    /// are stored.
+    pub fn with_lookup_cache(self) -> CachedTrie<K, V> {
+        CachedTrie {
+            trie: self,
+            cache: RefCell::new(Vec::with_capacity(CACHE_SLOTS)),
+        }
+    }
+}
+
+impl<K, V> CachedTrie<K, V>
+where
+    K: TrieKey,
+{
+    /// Fetch a reference to the given key's corresponding value, if any.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let mut cache = self.cache.borrow_mut();
+        let hit = cache.iter().position(|path| {
+            self.trie
+                .node
+                .follow(path)
+                .and_then(|node| node.key())
+                .is_some_and(|k| k.borrow() == key)
+        });
+        match hit {
+            Some(idx) => {
+                cache[..=idx].rotate_right(1);
+                self.trie
+                    .node
+                    .follow(&cache[0])
+                    .and_then(|node| node.value())
+            }
+            None => {
+                let mut path = vec![];
+                let node = self.trie.node.get_path(&key.encode(), &mut path)?;
+                let value = node.value_checked(key)?;
+                remember(&mut cache, path);
+                Some(value)
+            }
+        }
+    }
+
+    /// Fetch a mutable reference to the given key's corresponding value, if any.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let cache = self.cache.get_mut();
+        let node = &self.trie.node;
+        let hit = cache.iter().position(|path| {
+            node.follow(path)
+                .and_then(|node| node.key())
+                .is_some_and(|k| k.borrow() == key)
+        });
+        match hit {
+            Some(idx) => cache[..=idx].rotate_right(1),
+            None => {
+                let mut path = vec![];
+                node.get_path(&key.encode(), &mut path)?
+                    .value_checked(key)?;
+                remember(cache, path);
+            }
+        }
+        self.trie
+            .node
+            .follow_mut(&cache[0])
+            .and_then(|node| node.value_mut())
+    }
+
+    /// Insert the given key-value pair, returning any previous value associated with the key.
+    ///
+    /// This forgets all the cached keys, as the trie may have been restructured, and then
+    /// remembers the new key (at the cost of looking it up again).
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let nv = key.encode();
+        let result = self.trie.insert(key, value);
+
+        let cache = self.cache.get_mut();
+        cache.clear();
+        let mut path = vec![];
+        if self.trie.node.get_path(&nv, &mut path).is_some() {
+            cache.push(path);
+        }
+        result
+    }
+
+    /// Remove the value associated with the given key.
+    ///
+    /// This forgets all the cached keys, as the trie may have been restructured.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.cache.get_mut().clear();
+        self.trie.remove(key)
+    }
+
+    /// Get a read-only reference to the underlying trie.
+    pub fn as_trie(&self) -> &Trie<K, V> {
+        &self.trie
+    }
+
+    /// Get a mutable reference to the underlying trie, for any other kind of change.
+    ///
+    /// This forgets all the cached keys.
+    pub fn trie_mut(&mut self) -> &mut Trie<K, V> {
+        self.cache.get_mut().clear();
+        &mut self.trie
+    }
+
+    /// Discard the cache, returning the underlying trie.
+    pub fn into_trie(self) -> Trie<K, V> {
+        self.trie
+    }
+}
+
+/// Formats the trie's contents as a map, in iteration order.
+impl<K, V> fmt::Debug for CachedTrie<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.trie.fmt(f)
+    }
+}
+
+// Make `path` the most recently used entry, evicting the least recently used if full.
+fn remember(cache: &mut Vec<Vec<u8>>, path: Vec<u8>) {
+    if cache.len() == CACHE_SLOTS {
+        cache.pop();
+    }
+    cache.insert(0, path);
+}
@@ -0,0 +1,222 @@
+//! Conversions between tries and the standard library's maps.
+
+use augmented::reset_counts;
+use keys::{compare_keys, sorted_common_prefixes};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use trie_node::TrieNode;
+use {ExtendStats, NibbleVec, Trie, TrieCommon, TrieKey};
+
+/// What `Trie::extend_with` does with a key that's already in the trie.
+pub enum ExtendPolicy<'a, K, V> {
+    /// Replace the existing value with the new one, like `insert` and `Extend`.
+    Overwrite,
+    /// Keep the existing value, and drop the new one.
+    KeepExisting,
+    /// Replace the existing value with the result of calling the function on the key, the
+    /// existing value and the new value.
+    Merge(MergeFn<'a, K, V>),
+}
+
+type MergeFn<'a, K, V> = Box<dyn FnMut(&K, V, V) -> V + 'a>;
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey,
+{
+    /// Move all the entries of a map (or any other collection of distinct keys) into the trie.
+    ///
+    /// If the trie is empty, it's built in one pass over the sorted keys rather than by
+    /// inserting them one at a time. This is fastest when the entries already come in the
+    /// trie's iteration order, e.g. from a `BTreeMap` whose key ordering agrees with the
+    /// `TrieKey` encoding (as it does for strings and byte vectors).
+    pub fn extend_from_map<M>(&mut self, map: M)
+    where
+        M: IntoIterator<Item = (K, V)>,
+    {
+        if !self.is_empty() {
+            for (key, value) in map {
+                self.insert(key, value);
+            }
+            return;
+        }
+
+        let mut encoded: Vec<(NibbleVec, K, V)> = map
+            .into_iter()
+            .map(|(key, value)| (key.encode(), key, value))
+            .collect();
+        let common = match sorted_common_prefixes(encoded.iter().map(|e| &e.0)) {
+            Some(common) => common,
+            None => {
+                encoded.sort_by(|a, b| compare_keys(&a.0, &b.0));
+                match sorted_common_prefixes(encoded.iter().map(|e| &e.0)) {
+                    Some(common) => common,
+                    // Distinct keys with the same encoding have to go through `insert`, so
+                    // that they get the usual key check.
+                    None => {
+                        for (_, key, value) in encoded {
+                            self.insert(key, value);
+                        }
+                        return;
+                    }
+                }
+            }
+        };
+
+        reset_counts(&mut self.counts);
+        self.length = encoded.len();
+        self.node = TrieNode::from_sorted(
+            common
+                .into_iter()
+                .zip(encoded)
+                .map(|(common, (nv, key, value))| (common, nv, key, value)),
+        );
+    }
+
+    /// Turn this into a trie whose keys are `f` of the old keys, with the same values.
+    ///
+    /// This is fastest when `f` preserves the order of the encoded keys (e.g. adding the
+    /// same prefix to every key), as the new trie can then be built in one pass; otherwise
+    /// the new keys are sorted first, as in `extend_from_map`.
+    ///
+    /// `f` should map distinct keys to distinct keys. If it maps two keys to the same key,
+    /// only the later value (in iteration order) is kept, and if it maps them to distinct keys
+    /// with the same encoding, this panics, as `insert` does. To get an error instead, build
+    /// the new trie with `TrieBuilder::build_from_sorted` and `CollisionPolicy::Error`.
+    pub fn map_keys<K2, F>(self, mut f: F) -> Trie<K2, V>
+    where
+        K2: TrieKey,
+        F: FnMut(&K) -> K2,
+    {
+        let mut trie = Trie::new();
+        trie.extend_from_map(self.into_iter().map(|(key, value)| (f(&key), value)));
+        trie
+    }
+
+    /// Insert all the given entries, deciding what to do with keys that are already in the
+    /// trie (including those repeated within `iter`) according to `policy`.
+    ///
+    /// Returns the number of entries whose keys were newly inserted, and the number whose
+    /// keys already existed.
+    pub fn extend_with<I>(&mut self, iter: I, mut policy: ExtendPolicy<K, V>) -> ExtendStats
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        reset_counts(&mut self.counts);
+        let mut stats = ExtendStats::default();
+        for (key, value) in iter {
+            let nv = key.encode();
+            let existing = self
+                .node
+                .get_mut(&nv)
+                .filter(|node| node.key_value.is_some());
+            match existing {
+                Some(node) => {
+                    stats.existing += 1;
+                    match policy {
+                        ExtendPolicy::Overwrite => {
+                            node.replace_value(key, value);
+                        }
+                        ExtendPolicy::KeepExisting => {
+                            node.value_checked(&key);
+                        }
+                        ExtendPolicy::Merge(ref mut f) => {
+                            let old = node.take_value(&key).unwrap();
+                            let merged = f(&key, old, value);
+                            node.add_key_value(key, merged);
+                        }
+                    }
+                }
+                None => {
+                    stats.inserted += 1;
+                    self.length += 1;
+                    self.node.insert(key, value, nv);
+                }
+            }
+        }
+        stats
+    }
+}
+
+impl<'a, K, V> fmt::Debug for ExtendPolicy<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExtendPolicy::Overwrite => write!(f, "Overwrite"),
+            ExtendPolicy::KeepExisting => write!(f, "KeepExisting"),
+            ExtendPolicy::Merge(_) => write!(f, "Merge(..)"),
+        }
+    }
+}
+
+impl<K, V> Extend<(K, V)> for Trie<K, V>
+where
+    K: TrieKey,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Clones the keys and values, e.g. to copy the entries of another trie or map.
+impl<'a, K, V> Extend<(&'a K, &'a V)> for Trie<K, V>
+where
+    K: TrieKey + Clone + 'a,
+    V: Clone + 'a,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (&'a K, &'a V)>,
+    {
+        self.extend(
+            iter.into_iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for Trie<K, V>
+where
+    K: TrieKey,
+{
+    fn from(map: BTreeMap<K, V>) -> Trie<K, V> {
+        let mut trie = Trie::new();
+        trie.extend_from_map(map);
+        trie
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for Trie<K, V>
+where
+    K: TrieKey,
+{
+    fn from(map: HashMap<K, V, S>) -> Trie<K, V> {
+        let mut trie = Trie::new();
+        trie.extend_from_map(map);
+        trie
+    }
+}
+
+impl<K, V> From<Trie<K, V>> for BTreeMap<K, V>
+where
+    K: Ord,
+{
+    fn from(trie: Trie<K, V>) -> BTreeMap<K, V> {
+        trie.into_iter().collect()
+    }
+}
+
+impl<K, V, S> From<Trie<K, V>> for HashMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from(trie: Trie<K, V>) -> HashMap<K, V, S> {
+        trie.into_iter().collect()
+    }
+}
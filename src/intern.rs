@@ -0,0 +1,301 @@
+//! Keys that share their repeated leading components, for tries of paths and other keys
+//! made of many long, repeated parts.
+//!
+//! A trie of `String` keys allocates each key separately, and keeps the whole of every key,
+//! though the keys of a trie of paths mostly repeat the same few directories. An
+//! `InternedKey` instead holds a reference to its prefix up to the last separator, which a
+//! `KeyPool` shares between all the keys that have it, and keeps only its last component to
+//! itself: inline, if it's short.
+
+use builder::BuildError;
+use keys::OrderedTrieKey;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::str;
+use std::sync::{Arc, Weak};
+use {InternedKey, KeyPool, KeyPoolStats, Trie, TrieBuilder, TrieKey};
+
+/// The longest last component that's stored inside a key rather than allocated.
+const INLINE_BYTES: usize = 22;
+
+/// A borrowed key type that `InternedKey` can store: `str` or `[u8]`.
+pub trait Internable {
+    /// The bytes of the key.
+    fn byte_slice(&self) -> &[u8];
+
+    /// The key with the given bytes, which are those of a key split at ASCII separators.
+    fn from_byte_slice(bytes: &[u8]) -> &Self;
+}
+
+impl Internable for str {
+    fn byte_slice(&self) -> &[u8] {
+        self.as_bytes()
+    }
+
+    fn from_byte_slice(bytes: &[u8]) -> &str {
+        str::from_utf8(bytes).expect("keys are split at ASCII separators")
+    }
+}
+
+impl Internable for [u8] {
+    fn byte_slice(&self) -> &[u8] {
+        self
+    }
+
+    fn from_byte_slice(bytes: &[u8]) -> &[u8] {
+        bytes
+    }
+}
+
+/// A shared prefix of interned keys: the prefix it extends, and one more component ending
+/// with the separator.
+pub struct Prefix {
+    parent: Option<Arc<Prefix>>,
+    component: Box<[u8]>,
+}
+
+/// The last component of an interned key, after its prefix.
+#[derive(Clone)]
+pub enum Tail {
+    Inline(u8, [u8; INLINE_BYTES]),
+    Heap(Box<[u8]>),
+}
+
+impl Tail {
+    fn new(bytes: &[u8]) -> Tail {
+        if bytes.len() <= INLINE_BYTES {
+            let mut inline = [0; INLINE_BYTES];
+            inline[..bytes.len()].copy_from_slice(bytes);
+            Tail::Inline(bytes.len() as u8, inline)
+        } else {
+            Tail::Heap(bytes.into())
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Tail::Inline(len, ref bytes) => &bytes[..len as usize],
+            Tail::Heap(ref bytes) => bytes,
+        }
+    }
+}
+
+impl<T: ?Sized + Internable> InternedKey<T> {
+    /// The bytes of the key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut len = self.tail.bytes().len();
+        let mut prefix = self.prefix.as_ref();
+        while let Some(p) = prefix {
+            len += p.component.len();
+            prefix = p.parent.as_ref();
+        }
+        let mut bytes = Vec::with_capacity(len);
+        if let Some(ref prefix) = self.prefix {
+            prefix.write_to(&mut bytes);
+        }
+        bytes.extend_from_slice(self.tail.bytes());
+        bytes
+    }
+}
+
+impl Prefix {
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        if let Some(ref parent) = self.parent {
+            parent.write_to(bytes);
+        }
+        bytes.extend_from_slice(&self.component);
+    }
+}
+
+/// A key that shares nothing, e.g. for a lookup.
+impl<'a, T: ?Sized + Internable> From<&'a T> for InternedKey<T> {
+    fn from(key: &'a T) -> InternedKey<T> {
+        InternedKey {
+            prefix: None,
+            tail: Tail::new(key.byte_slice()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for InternedKey<T> {
+    fn clone(&self) -> Self {
+        InternedKey {
+            prefix: self.prefix.clone(),
+            tail: self.tail.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + Internable + fmt::Debug> fmt::Debug for InternedKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        T::from_byte_slice(&self.to_bytes()).fmt(f)
+    }
+}
+
+impl<T: ?Sized + Internable + fmt::Display> fmt::Display for InternedKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        T::from_byte_slice(&self.to_bytes()).fmt(f)
+    }
+}
+
+impl<T: ?Sized + Internable> PartialEq for InternedKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let same_prefix = match (&self.prefix, &other.prefix) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        if same_prefix {
+            self.tail.bytes() == other.tail.bytes()
+        } else {
+            self.to_bytes() == other.to_bytes()
+        }
+    }
+}
+
+impl<T: ?Sized + Internable> Eq for InternedKey<T> {}
+
+/// Keys are ordered by their bytes.
+impl<T: ?Sized + Internable> PartialOrd for InternedKey<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized + Internable> Ord for InternedKey<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_bytes().cmp(&other.to_bytes())
+    }
+}
+
+impl<T: ?Sized + Internable> Hash for InternedKey<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state)
+    }
+}
+
+impl<T: ?Sized + Internable> TrieKey for InternedKey<T> {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        match self.prefix {
+            None => Some(self.tail.bytes()),
+            Some(_) => None,
+        }
+    }
+}
+
+impl<T: ?Sized + Internable> OrderedTrieKey for InternedKey<T> {}
+
+impl<T: ?Sized + Internable> KeyPool<T> {
+    /// Create a pool that shares the prefixes of keys up to each `separator`, which must be
+    /// an ASCII byte.
+    pub fn new(separator: u8) -> KeyPool<T> {
+        assert!(separator.is_ascii(), "the separator must be ASCII");
+        KeyPool {
+            separator,
+            prefixes: HashMap::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Make a key with the given bytes, sharing its prefix up to the last separator with the
+    /// other keys from this pool.
+    pub fn intern(&mut self, key: &T) -> InternedKey<T> {
+        let bytes = key.byte_slice();
+        let split = bytes.iter().rposition(|&b| b == self.separator);
+        InternedKey {
+            prefix: split.map(|i| self.prefix(&bytes[..=i])),
+            tail: Tail::new(&bytes[split.map_or(0, |i| i + 1)..]),
+            marker: PhantomData,
+        }
+    }
+
+    /// Measure the prefixes in the pool.
+    pub fn stats(&self) -> KeyPoolStats {
+        let mut stats = KeyPoolStats::default();
+        for prefix in self.prefixes.values() {
+            match prefix.upgrade() {
+                Some(prefix) => {
+                    stats.prefixes += 1;
+                    stats.prefix_bytes += prefix.component.len();
+                }
+                None => stats.garbage += 1,
+            }
+        }
+        stats
+    }
+
+    /// Forget the prefixes that no key uses any more.
+    pub fn compact(&mut self) {
+        self.prefixes.retain(|_, prefix| prefix.strong_count() > 0);
+        self.prefixes.shrink_to_fit();
+    }
+
+    // The shared prefix with the given bytes, which end with the separator.
+    fn prefix(&mut self, bytes: &[u8]) -> Arc<Prefix> {
+        if let Some(prefix) = self.prefixes.get(bytes).and_then(Weak::upgrade) {
+            return prefix;
+        }
+        let start = bytes[..bytes.len() - 1]
+            .iter()
+            .rposition(|&b| b == self.separator)
+            .map(|i| i + 1);
+        let prefix = Arc::new(Prefix {
+            parent: start.map(|start| self.prefix(&bytes[..start])),
+            component: bytes[start.unwrap_or(0)..].into(),
+        });
+        self.prefixes
+            .insert(bytes.to_vec(), Arc::downgrade(&prefix));
+        prefix
+    }
+}
+
+/// A pool separating components with `/`.
+impl<T: ?Sized + Internable> Default for KeyPool<T> {
+    fn default() -> Self {
+        KeyPool::new(b'/')
+    }
+}
+
+impl<T, V> TrieBuilder<InternedKey<T>, V>
+where
+    T: ?Sized + Internable,
+{
+    /// Build a trie like `build_from_sorted`, with the keys interned in `pool`.
+    ///
+    /// ```
+    /// use radix_trie::{InternedKey, KeyPool, TrieBuilder};
+    ///
+    /// let paths = ["/usr/bin/cc", "/usr/bin/ld", "/usr/lib/libc.so"];
+    /// let mut pool = KeyPool::default();
+    /// let trie = TrieBuilder::<InternedKey<str>, _>::new()
+    ///     .build_interned(&mut pool, paths.iter().map(|&path| (path, path.len())))
+    ///     .unwrap();
+    /// assert_eq!(trie.get(&InternedKey::from("/usr/bin/ld")), Some(&11));
+    /// // "/", "usr/", "bin/" and "lib/".
+    /// assert_eq!(pool.stats().prefixes, 4);
+    /// ```
+    pub fn build_interned<I, S>(
+        &self,
+        pool: &mut KeyPool<T>,
+        entries: I,
+    ) -> Result<Trie<InternedKey<T>, V>, BuildError<InternedKey<T>>>
+    where
+        I: IntoIterator<Item = (S, V)>,
+        S: AsRef<T>,
+    {
+        self.build_from_sorted(
+            entries
+                .into_iter()
+                .map(|(key, value)| (pool.intern(key.as_ref()), value)),
+        )
+    }
+}
@@ -1,10 +1,11 @@
 //! Iterators over key-value pairs, keys, values and child subtries.
 
+use std::fmt;
 use std::iter::{FilterMap, FromIterator, Map};
 use std::slice;
 
 use trie_node::TrieNode;
-use {NibbleVec, SubTrie, Trie, TrieKey};
+use {NibbleVec, SubTrie, Trie, TrieKey, TrieKeyDecode};
 
 // MY EYES.
 type Child<K, V> = Box<TrieNode<K, V>>;
@@ -14,22 +15,76 @@ type ChildIter<'a, K, V> = FilterMap<RawChildIter<'a, K, V>, ChildMapFn<'a, K, V
 
 /// Iterator over the keys and values of a Trie.
 pub struct Iter<'a, K: 'a, V: 'a> {
-    root: &'a TrieNode<K, V>,
-    root_visited: bool,
-    stack: Vec<ChildIter<'a, K, V>>,
+    inner: ValueNodes<'a, K, V>,
 }
 
 impl<'a, K, V> Iter<'a, K, V> {
     // TODO: make this private somehow (and same for the other iterators).
     pub fn new(root: &'a TrieNode<K, V>) -> Iter<'a, K, V> {
         Iter {
-            root: root,
-            root_visited: false,
-            stack: vec![],
+            inner: ValueNodes::new(root),
         }
     }
 }
 
+/// An empty iterator.
+impl<'a, K, V> Default for Iter<'a, K, V> {
+    fn default() -> Self {
+        Iter {
+            inner: ValueNodes {
+                root: None,
+                stack: vec![],
+            },
+        }
+    }
+}
+
+/// Iterator over the keys and values of a Trie that have no other values stored beneath them.
+pub struct Leaves<'a, K: 'a, V: 'a> {
+    inner: ValueNodes<'a, K, V>,
+}
+
+impl<'a, K, V> Leaves<'a, K, V> {
+    pub fn new(root: &'a TrieNode<K, V>) -> Leaves<'a, K, V> {
+        Leaves {
+            inner: ValueNodes::new(root),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Leaves<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .find(|node| !node.has_descendant_value())
+            .and_then(TrieNode::kv_as_pair)
+    }
+}
+
+/// Iterator over the keys and values of a Trie that have other values stored beneath them.
+pub struct Internal<'a, K: 'a, V: 'a> {
+    inner: ValueNodes<'a, K, V>,
+}
+
+impl<'a, K, V> Internal<'a, K, V> {
+    pub fn new(root: &'a TrieNode<K, V>) -> Internal<'a, K, V> {
+        Internal {
+            inner: ValueNodes::new(root),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Internal<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .find(|node| node.has_descendant_value())
+            .and_then(TrieNode::kv_as_pair)
+    }
+}
+
 /// Iterator over the keys of a Trie.
 pub struct Keys<'a, K: 'a, V: 'a> {
     inner: Map<Iter<'a, K, V>, KeyMapFn<'a, K, V>>,
@@ -82,6 +137,59 @@ impl<'a, K, V> Iterator for Values<'a, K, V> {
     }
 }
 
+/// Iterator over the keys and values of a subtrie, with the keys relative to its prefix.
+pub struct RelativeIter<'a, K: 'a, V: 'a> {
+    depth: usize,
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> RelativeIter<'a, K, V> {
+    pub fn new(depth: usize, iter: Iter<'a, K, V>) -> RelativeIter<'a, K, V> {
+        RelativeIter { depth, inner: iter }
+    }
+}
+
+impl<'a, K, V> Iterator for RelativeIter<'a, K, V>
+where
+    K: TrieKeyDecode,
+{
+    type Item = (Option<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|(k, v)| (K::decode(&k.encode().split(self.depth)), v))
+    }
+}
+
+/// Iterator over the keys and values of a Trie, with each key as the bytes of its encoding.
+pub struct EncodedIter<'a, K: 'a, V: 'a> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> EncodedIter<'a, K, V> {
+    pub fn new(iter: Iter<'a, K, V>) -> EncodedIter<'a, K, V> {
+        EncodedIter { inner: iter }
+    }
+}
+
+impl<'a, K, V> Iterator for EncodedIter<'a, K, V>
+where
+    K: TrieKey,
+{
+    type Item = (Vec<u8>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| {
+            let bytes = match k.encoded_bytes() {
+                Some(bytes) => bytes.to_vec(),
+                None => k.encode().into_bytes(),
+            };
+            (bytes, v)
+        })
+    }
+}
+
 /// Iterator over the child subtries of a trie.
 pub struct Children<'a, K: 'a, V: 'a> {
     prefix: NibbleVec,
@@ -104,6 +212,7 @@ impl<'a, K, V> Iterator for Children<'a, K, V> {
         self.inner.next().map(|node| SubTrie {
             prefix: self.prefix.clone().join(&node.key),
             node: node,
+            length: None,
         })
     }
 }
@@ -122,6 +231,30 @@ impl<K, V> TrieNode<K, V> {
     fn kv_as_pair(&self) -> Option<(&K, &V)> {
         self.key_value.as_ref().map(|kv| (&kv.key, &kv.value))
     }
+
+    /// Determine whether any node below this one stores a value.
+    ///
+    /// Value-less nodes normally have at least two children, but this doesn't rely on that.
+    fn has_descendant_value(&self) -> bool {
+        self.child_iter()
+            .any(|child| child.key_value.is_some() || child.has_descendant_value())
+    }
+}
+
+/// Pre-order iterator over the nodes of a trie that store values.
+struct ValueNodes<'a, K: 'a, V: 'a> {
+    /// The root, until it has been visited.
+    root: Option<&'a TrieNode<K, V>>,
+    stack: Vec<ChildIter<'a, K, V>>,
+}
+
+impl<'a, K, V> ValueNodes<'a, K, V> {
+    fn new(root: &'a TrieNode<K, V>) -> ValueNodes<'a, K, V> {
+        ValueNodes {
+            root: Some(root),
+            stack: vec![],
+        }
+    }
 }
 
 enum IterAction<'a, K: 'a, V: 'a> {
@@ -132,15 +265,22 @@ enum IterAction<'a, K: 'a, V: 'a> {
 impl<'a, K, V> Iterator for Iter<'a, K, V> {
     type Item = (&'a K, &'a V);
 
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().and_then(TrieNode::kv_as_pair)
+    }
+}
+
+impl<'a, K, V> Iterator for ValueNodes<'a, K, V> {
+    type Item = &'a TrieNode<K, V>;
+
     fn next(&mut self) -> Option<Self::Item> {
         use self::IterAction::*;
 
         // Visit each node as it is reached from its parent (with special root handling).
-        if !self.root_visited {
-            self.root_visited = true;
-            self.stack.push(self.root.child_iter());
-            if let Some(kv) = self.root.kv_as_pair() {
-                return Some(kv);
+        if let Some(root) = self.root.take() {
+            self.stack.push(root.child_iter());
+            if root.key_value.is_some() {
+                return Some(root);
             }
         }
 
@@ -156,8 +296,8 @@ impl<'a, K, V> Iterator for Iter<'a, K, V> {
             match action {
                 Push(trie) => {
                     self.stack.push(trie.child_iter());
-                    if let Some(kv) = trie.kv_as_pair() {
-                        return Some(kv);
+                    if trie.key_value.is_some() {
+                        return Some(trie);
                     }
                 }
                 Pop => {
@@ -168,6 +308,144 @@ impl<'a, K, V> Iterator for Iter<'a, K, V> {
     }
 }
 
+// Manual impls, as deriving would require `K: Clone, V: Clone`.
+macro_rules! impl_clone {
+    ($($name:ident),*) => {$(
+        impl<'a, K, V> Clone for $name<'a, K, V> {
+            fn clone(&self) -> Self {
+                $name {
+                    inner: self.inner.clone(),
+                }
+            }
+        }
+    )*};
+}
+
+impl_clone!(Iter, Leaves, Internal, Keys, Values, EncodedIter);
+
+impl<'a, K, V> Clone for ValueNodes<'a, K, V> {
+    fn clone(&self) -> Self {
+        ValueNodes {
+            root: self.root,
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Clone for RelativeIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        RelativeIter {
+            depth: self.depth,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Clone for Children<'a, K, V> {
+    fn clone(&self) -> Self {
+        Children {
+            prefix: self.prefix.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+// Debug impls show the remaining items, like the standard library's iterators.
+macro_rules! impl_debug {
+    ($($name:ident),*) => {$(
+        impl<'a, K, V> fmt::Debug for $name<'a, K, V>
+        where
+            K: fmt::Debug,
+            V: fmt::Debug,
+        {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_tuple(stringify!($name))
+                    .field(&DebugList(self.clone()))
+                    .finish()
+            }
+        }
+    )*};
+}
+
+impl_debug!(Iter, Leaves, Internal, Keys, Values, Children);
+
+impl<'a, K, V> fmt::Debug for RelativeIter<'a, K, V>
+where
+    K: TrieKeyDecode + fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RelativeIter")
+            .field(&DebugList(self.clone()))
+            .finish()
+    }
+}
+
+impl<'a, K, V> fmt::Debug for EncodedIter<'a, K, V>
+where
+    K: TrieKey,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("EncodedIter")
+            .field(&DebugList(self.clone()))
+            .finish()
+    }
+}
+
+struct DebugList<I>(I);
+
+impl<I> fmt::Debug for DebugList<I>
+where
+    I: Iterator + Clone,
+    I::Item: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.0.clone()).finish()
+    }
+}
+
+/// Consuming iterator over the keys and values of a Trie.
+pub struct IntoIter<K, V> {
+    stack: Vec<Child<K, V>>,
+    remaining: usize,
+}
+
+impl<K, V> IntoIterator for Trie<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter {
+            stack: vec![Box::new(self.node)],
+            remaining: self.length,
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        while let Some(mut node) = self.stack.pop() {
+            // Push the children in reverse, so that the first is visited next.
+            self.stack
+                .extend(node.children.iter_mut().rev().filter_map(Option::take));
+            if let Some(kv) = node.key_value.take() {
+                self.remaining -= 1;
+                return Some((kv.key, kv.value));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
 impl<K, V> FromIterator<(K, V)> for Trie<K, V>
 where
     K: TrieKey,
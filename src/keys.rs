@@ -1,7 +1,15 @@
 use endian_type::{BigEndian, LittleEndian};
-use std::ffi::OsString;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use NibbleVec;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use {KeyRelationship, NibbleVec};
 
 /// Trait for types which can be used to key a Radix Trie.
 ///
@@ -18,6 +26,8 @@ use NibbleVec;
 ///
 /// Many standard types implement this trait already. Integer types are encoded *big-endian*
 /// by default but can be encoded little-endian using the `LittleEndian<T>` wrapper type.
+/// For integer keys that iterate in numeric order, negative numbers included, use the
+/// `OrderedKey<T>` wrapper type.
 pub trait TrieKey: PartialEq + Eq {
     /// Encode a value as a vector of bytes.
     fn encode_bytes(&self) -> Vec<u8> {
@@ -26,12 +36,60 @@ pub trait TrieKey: PartialEq + Eq {
 
     /// Encode a value as a NibbleVec.
     fn encode(&self) -> NibbleVec {
+        record!(nibble_vec_allocations += 1);
         NibbleVec::from_byte_vec(self.encode_bytes())
     }
+
+    /// Borrow the bytes of a value's encoding, if it already holds them.
+    ///
+    /// Lookups use this to avoid encoding the key they are given. Types whose encoding is
+    /// just their bytes, like `str` and `[u8]`, should return them here; returning `None`
+    /// (the default) is always correct, but costs an allocation per lookup.
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Trait for key types which can be rebuilt from their `TrieKey` encoding.
+///
+/// As for `TrieKey`, you need to implement *either* `decode_bytes` or `decode`. Decoding the
+/// encoding of any key must give back that key, and decoding anything else (e.g. part of a
+/// key's encoding) gives either `None` or some other key with exactly that encoding.
+///
+/// Borrowed types like `str` and `[u8]` can't be decoded, but the owned types corresponding
+/// to them can.
+pub trait TrieKeyDecode: TrieKey + Sized {
+    /// Decode a key from a vector of bytes.
+    fn decode_bytes(_bytes: &[u8]) -> Option<Self> {
+        panic!("implement this method or TrieKeyDecode::decode");
+    }
+
+    /// Decode a key from a NibbleVec, which only succeeds for a whole number of bytes unless
+    /// overridden.
+    fn decode(nibbles: &NibbleVec) -> Option<Self> {
+        if !nibbles.len().is_multiple_of(2) {
+            return None;
+        }
+        Self::decode_bytes(nibbles.as_bytes())
+    }
 }
 
+/// Marker trait for key types whose encodings sort in the same order as the keys themselves.
+///
+/// A trie iterates over its keys in the lexicographic order of their encodings (see `Trie`),
+/// which for these types is also their `Ord` order. Code that relies on the trie's order
+/// matching the keys' own, e.g. to treat `next_after` as the successor of a key, can require
+/// this trait.
+///
+/// It's implemented for strings and byte strings (including `ByteKey` and `CString`),
+/// unsigned integers and sequences of them, `OrderedKey` for all integers, `Duration`,
+/// `SystemTime`, IP and socket addresses, `OsString` on Unix, and references to any of
+/// these. Plain signed integers aren't ordered (negative numbers sort after positive ones),
+/// nor are `LittleEndian` integers or paths, which are ordered component by component.
+pub trait OrderedTrieKey: TrieKey + Ord {}
+
 /// Key comparison result.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyMatch {
     /// The keys match up to the given index.
     Partial(usize),
@@ -43,17 +101,82 @@ pub enum KeyMatch {
     Full,
 }
 
+/// A borrowed encoded key, which is either a `NibbleVec` or the bytes returned by
+/// `TrieKey::encoded_bytes`.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyRef<'a> {
+    bytes: &'a [u8],
+    len: usize,
+}
+
+impl<'a> KeyRef<'a> {
+    /// Borrow bytes that are a key's encoding.
+    pub fn from_bytes(bytes: &'a [u8]) -> KeyRef<'a> {
+        KeyRef {
+            bytes,
+            len: 2 * bytes.len(),
+        }
+    }
+
+    /// The number of nibbles in the key.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the nibble at `idx`, with the same layout as `NibbleVec::get`.
+    pub fn get(&self, idx: usize) -> u8 {
+        assert!(idx < self.len, "index {} out of range for key", idx);
+        let byte = self.bytes[idx / 2];
+        if idx.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        }
+    }
+
+    /// The bytes holding the key's nibbles, two to a byte.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> From<&'a NibbleVec> for KeyRef<'a> {
+    fn from(nv: &'a NibbleVec) -> KeyRef<'a> {
+        KeyRef {
+            bytes: nv.as_bytes(),
+            len: nv.len(),
+        }
+    }
+}
+
+/// Call `f` with the encoding of `key`, borrowing it instead of encoding the key if possible.
+pub fn with_encoding<Q, F, R>(key: &Q, f: F) -> R
+where
+    Q: ?Sized + TrieKey,
+    F: FnOnce(KeyRef) -> R,
+{
+    match key.encoded_bytes() {
+        Some(bytes) => f(KeyRef::from_bytes(bytes)),
+        None => f(KeyRef::from(&key.encode())),
+    }
+}
+
 /// Compare two Trie keys.
 ///
 /// Compares `first[start_idx .. ]` to `second`, i.e. only looks at a slice of the first key.
-pub fn match_keys(start_idx: usize, first: &NibbleVec, second: &NibbleVec) -> KeyMatch {
+pub fn match_keys<'a, F>(start_idx: usize, first: F, second: &NibbleVec) -> KeyMatch
+where
+    F: Into<KeyRef<'a>>,
+{
+    let first = first.into();
     let first_len = first.len() - start_idx;
     let min_length = ::std::cmp::min(first_len, second.len());
 
-    for i in 0..min_length {
-        if first.get(start_idx + i) != second.get(i) {
-            return KeyMatch::Partial(i);
-        }
+    let common = common_prefix(start_idx, first, second, min_length);
+    // The nibbles looked at: the common ones, and the first differing one, if any.
+    record!(key_bytes_compared += (common + usize::from(common < min_length)).div_ceil(2));
+    if common < min_length {
+        return KeyMatch::Partial(common);
     }
 
     match (first_len, second.len()) {
@@ -63,13 +186,262 @@ pub fn match_keys(start_idx: usize, first: &NibbleVec, second: &NibbleVec) -> Ke
     }
 }
 
+// Length of the common prefix of `first[start_idx..]` and `second`, up to `max` nibbles.
+//
+// Compares 16 nibbles at a time by XOR-ing 64-bit words, and uses the leading zeros of the
+// first non-zero result to find the differing nibble. If `start_idx` is odd, the nibbles of
+// `first` straddle byte boundaries, so its words are shifted into alignment with `second`'s.
+// The last few nibbles are compared one at a time.
+fn common_prefix(start_idx: usize, first: KeyRef, second: &NibbleVec, max: usize) -> usize {
+    let (a, b) = (first.as_bytes(), second.as_bytes());
+    let offset = start_idx / 2;
+    let odd_start = start_idx % 2 == 1;
+
+    let mut i = 0;
+    while i + 16 <= max {
+        let byte = offset + i / 2;
+        let mut word_a = read_u64(&a[byte..byte + 8]);
+        if odd_start {
+            word_a = (word_a << 4) | u64::from(a[byte + 8] >> 4);
+        }
+        let x = word_a ^ read_u64(&b[i / 2..i / 2 + 8]);
+        if x != 0 {
+            return i + (x.leading_zeros() / 4) as usize;
+        }
+        i += 16;
+    }
+
+    (i..max)
+        .find(|&i| first.get(start_idx + i) != second.get(i))
+        .unwrap_or(max)
+}
+
+/// Compare two encoded keys in the trie's iteration order.
+///
+/// A key comes before any key that it is a prefix of.
+pub fn compare_keys(first: &NibbleVec, second: &NibbleVec) -> Ordering {
+    match match_keys(0, first, second) {
+        KeyMatch::Partial(idx) => first.get(idx).cmp(&second.get(idx)),
+        KeyMatch::FirstPrefix => Ordering::Less,
+        KeyMatch::SecondPrefix => Ordering::Greater,
+        KeyMatch::Full => Ordering::Equal,
+    }
+}
+
+/// Find how the encodings of two keys relate: whether one is a prefix of the other, and how
+/// long their common prefix is.
+///
+/// ```
+/// use radix_trie::{key_relationship, KeyMatch};
+///
+/// let rel = key_relationship("app", "apple");
+/// assert_eq!(rel.kind, KeyMatch::FirstPrefix);
+/// assert_eq!(rel.common_bytes(), 3);
+///
+/// // 'b' and 'c' are 0x62 and 0x63, so the keys differ in their last nibble.
+/// let rel = key_relationship("ab", "ac");
+/// assert_eq!(rel.kind, KeyMatch::Partial(3));
+/// assert_eq!((rel.common_nibbles, rel.common_bytes()), (3, 1));
+/// ```
+pub fn key_relationship<K>(first: &K, second: &K) -> KeyRelationship
+where
+    K: ?Sized + TrieKey,
+{
+    let (first, second) = (first.encode(), second.encode());
+    let kind = match_keys(0, &first, &second);
+    let common_nibbles = match kind {
+        KeyMatch::Partial(idx) => idx,
+        KeyMatch::FirstPrefix | KeyMatch::Full => first.len(),
+        KeyMatch::SecondPrefix => second.len(),
+    };
+    KeyRelationship {
+        kind,
+        common_nibbles,
+    }
+}
+
+impl KeyRelationship {
+    /// The length of the keys' common prefix, in whole bytes.
+    pub fn common_bytes(&self) -> usize {
+        self.common_nibbles / 2
+    }
+}
+
+/// Determine whether the encoding of `prefix` is a prefix of the encoding of `key`, which
+/// includes the case where they're equal.
+///
+/// This is the relationship between a key and the keys of `Trie::subtrie(prefix)`.
+pub fn is_prefix_of<K>(prefix: &K, key: &K) -> bool
+where
+    K: ?Sized + TrieKey,
+{
+    match key_relationship(prefix, key).kind {
+        KeyMatch::FirstPrefix | KeyMatch::Full => true,
+        KeyMatch::SecondPrefix | KeyMatch::Partial(_) => false,
+    }
+}
+
+/// Find the length of each encoded key's common prefix with the key before it (or 0 for the
+/// first key), if the keys are strictly increasing in the trie's iteration order.
+pub fn sorted_common_prefixes<'a, I>(keys: I) -> Option<Vec<usize>>
+where
+    I: IntoIterator<Item = &'a NibbleVec>,
+{
+    let mut keys = keys.into_iter();
+    let mut prev = match keys.next() {
+        Some(first) => first,
+        None => return Some(vec![]),
+    };
+    let mut common = vec![0];
+    for key in keys {
+        common.push(match match_keys(0, prev, key) {
+            KeyMatch::Partial(idx) if prev.get(idx) < key.get(idx) => idx,
+            KeyMatch::FirstPrefix => prev.len(),
+            _ => return None,
+        });
+        prev = key;
+    }
+    Some(common)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes.try_into().unwrap())
+}
+
 /// Check two keys for equality and panic if they differ.
+///
+/// The keys are assumed to have the same encoding, which the panic message gives in hex.
 pub fn check_keys<K: ?Sized>(key1: &K, key2: &K)
 where
     K: TrieKey,
 {
     if *key1 != *key2 {
-        panic!("multiple-keys with the same bit representation.");
+        panic!(
+            "multiple-keys with the same bit representation. Both encode to 0x{}",
+            Hex(&key1.encode())
+        );
+    }
+}
+
+/// Formats an encoded key as one hex digit per nibble, so that keys with an odd number of
+/// nibbles are shown exactly.
+pub struct Hex<'a>(pub &'a NibbleVec);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..self.0.len() {
+            write!(f, "{:x}", self.0.get(i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Builder for composite keys made up of several fields, like `(tenant, table, id)`.
+///
+/// Each field is appended using an encoding that is unambiguous and order-preserving, so that
+/// the resulting byte keys sort field-by-field, and a key built from the first few fields of
+/// another key is a prefix of it (and only of keys that share those fields):
+///
+/// * Integers are encoded big-endian at their full width, with the sign bit flipped for
+///   signed types so that negative numbers sort first.
+/// * Strings and byte strings are terminated by `00 01`, with any zero bytes inside
+///   them escaped as `00 FF`. Length prefixes would be simpler, but would make keys sort by
+///   length first, and break prefix queries over the field's contents. The cost is one extra
+///   byte per zero byte, plus two for the terminator.
+///
+/// ```
+/// use radix_trie::{KeyBuf, Trie, TrieCommon};
+///
+/// let mut trie = Trie::new();
+/// trie.insert(KeyBuf::new().push_str("acme").push_u64(1).finish(), "first");
+/// trie.insert(KeyBuf::new().push_str("acme").push_u64(2).finish(), "second");
+/// trie.insert(KeyBuf::new().push_str("acme\0").push_u64(1).finish(), "other");
+///
+/// let acme = KeyBuf::new().push_str("acme").prefix();
+/// let values: Vec<_> = trie.get_raw_descendant(&acme).unwrap().values().cloned().collect();
+/// assert_eq!(values, vec!["first", "second"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyBuf {
+    bytes: Vec<u8>,
+}
+
+const KEYBUF_ESCAPE: u8 = 0x00;
+const KEYBUF_ESCAPED_ZERO: u8 = 0xff;
+const KEYBUF_TERMINATOR: u8 = 0x01;
+
+/// Append a zero-escaped, terminated byte string to `out` (see `KeyBuf`).
+pub fn push_escaped(out: &mut Vec<u8>, bytes: &[u8]) {
+    for &b in bytes {
+        out.push(b);
+        if b == KEYBUF_ESCAPE {
+            out.push(KEYBUF_ESCAPED_ZERO);
+        }
+    }
+    out.push(KEYBUF_ESCAPE);
+    out.push(KEYBUF_TERMINATOR);
+}
+
+macro_rules! keybuf_unsigned {
+    ( $( $name:ident: $t:ty ),* ) => {
+        $(
+        /// Append a fixed-width, big-endian integer field.
+        pub fn $name(mut self, x: $t) -> KeyBuf {
+            self.bytes.extend_from_slice(&x.to_be_bytes());
+            self
+        }
+        )*
+    };
+}
+
+macro_rules! keybuf_signed {
+    ( $( $name:ident: $t:ty => $unsigned:ident: $ut:ty ),* ) => {
+        $(
+        /// Append a fixed-width, big-endian integer field, with the sign bit flipped so
+        /// that negative numbers sort first.
+        pub fn $name(self, x: $t) -> KeyBuf {
+            self.$unsigned((x as $ut) ^ (1 << (<$t>::BITS - 1)))
+        }
+        )*
+    };
+}
+
+impl KeyBuf {
+    /// Create an empty key.
+    pub fn new() -> KeyBuf {
+        KeyBuf { bytes: Vec::new() }
+    }
+
+    /// Append a string field.
+    pub fn push_str(self, s: &str) -> KeyBuf {
+        self.push_bytes(s.as_bytes())
+    }
+
+    /// Append a variable-length byte string field.
+    pub fn push_bytes(mut self, bytes: &[u8]) -> KeyBuf {
+        push_escaped(&mut self.bytes, bytes);
+        self
+    }
+
+    keybuf_unsigned!(push_u8: u8, push_u16: u16, push_u32: u32, push_u64: u64);
+    keybuf_signed!(
+        push_i8: i8 => push_u8: u8,
+        push_i16: i16 => push_u16: u16,
+        push_i32: i32 => push_u32: u32,
+        push_i64: i64 => push_u64: u64
+    );
+
+    /// The key for the fields pushed so far, for use as a prefix with `get_raw_descendant`.
+    ///
+    /// Its descendants are exactly the keys whose leading fields are equal to the ones
+    /// pushed so far.
+    pub fn prefix(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+
+    /// Return the finished key.
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
     }
 }
 
@@ -82,42 +454,313 @@ where
 // }
 // }
 
+/// Key wrapper for any type that can be viewed as a byte slice, like `[u8; N]`,
+/// `bytes::Bytes` or `SmallVec<[u8; N]>`.
+///
+/// Keys are encoded exactly like the equivalent `[u8]`, and compared, hashed and ordered by
+/// their bytes. A `ByteKey<T>` borrows as a `[u8]`, so a trie keyed by `ByteKey<T>` can be
+/// queried with plain byte slices.
+///
+/// ```
+/// use radix_trie::{ByteKey, Trie};
+///
+/// let mut trie = Trie::new();
+/// trie.insert(ByteKey([1u8, 2, 3, 4]), "array");
+/// assert_eq!(trie.get(&[1u8, 2, 3, 4][..]), Some(&"array"));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteKey<T>(pub T);
+
+impl<T: AsRef<[u8]>> TrieKey for ByteKey<T> {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.0.as_ref().encode_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self.0.as_ref())
+    }
+}
+
+impl<T: AsRef<[u8]>> PartialEq for ByteKey<T> {
+    fn eq(&self, other: &ByteKey<T>) -> bool {
+        self.0.as_ref() == other.0.as_ref()
+    }
+}
+
+impl<T: AsRef<[u8]>> Eq for ByteKey<T> {}
+
+impl<T: AsRef<[u8]>> PartialOrd for ByteKey<T> {
+    fn partial_cmp(&self, other: &ByteKey<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: AsRef<[u8]>> Ord for ByteKey<T> {
+    fn cmp(&self, other: &ByteKey<T>) -> Ordering {
+        self.0.as_ref().cmp(other.0.as_ref())
+    }
+}
+
+impl<T: AsRef<[u8]>> Hash for ByteKey<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ref().hash(state)
+    }
+}
+
+impl<T> Deref for ByteKey<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for ByteKey<T> {
+    fn from(inner: T) -> ByteKey<T> {
+        ByteKey(inner)
+    }
+}
+
+impl<T: AsRef<[u8]>> AsRef<[u8]> for ByteKey<T> {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl<T: AsRef<[u8]>> Borrow<[u8]> for ByteKey<T> {
+    fn borrow(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+/// Integer key wrapper whose encoding sorts in numeric order.
+///
+/// Integers are encoded big-endian at their full width, with the sign bit flipped for signed
+/// types, as in `KeyBuf`. The trie's iteration order, and so `next_after` and friends, then
+/// agrees with the integers' own order, including for negative numbers.
+///
+/// Plain integer keys are big-endian too, but signed ones aren't flipped, so negative numbers
+/// sort *after* positive ones. `LittleEndian<T>` keys don't sort numerically at all.
+///
+/// ```
+/// use radix_trie::{OrderedKey, Trie, TrieCommon};
+///
+/// let trie: Trie<_, _> = vec![(OrderedKey(3i32), 'c'), (OrderedKey(-7), 'a'), (OrderedKey(0), 'b')]
+///     .into_iter()
+///     .collect();
+/// assert_eq!(trie.values().cloned().collect::<String>(), "abc");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct OrderedKey<T>(pub T);
+
+macro_rules! ordered_unsigned_keys {
+    ( $( $t:ty ),* ) => {
+        $(
+        impl TrieKey for OrderedKey<$t> {
+            fn encode_bytes(&self) -> Vec<u8> {
+                self.0.to_be_bytes().to_vec()
+            }
+        }
+
+        impl TrieKeyDecode for OrderedKey<$t> {
+            fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+                bytes.try_into().ok().map(|bytes| OrderedKey(<$t>::from_be_bytes(bytes)))
+            }
+        }
+        )*
+    };
+}
+
+macro_rules! ordered_signed_keys {
+    ( $( $t:ty => $ut:ty ),* ) => {
+        $(
+        impl TrieKey for OrderedKey<$t> {
+            fn encode_bytes(&self) -> Vec<u8> {
+                OrderedKey((self.0 as $ut) ^ (1 << (<$t>::BITS - 1))).encode_bytes()
+            }
+        }
+
+        impl TrieKeyDecode for OrderedKey<$t> {
+            fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+                OrderedKey::<$ut>::decode_bytes(bytes)
+                    .map(|key| OrderedKey((key.0 ^ (1 << (<$t>::BITS - 1))) as $t))
+            }
+        }
+        )*
+    };
+}
+
+ordered_unsigned_keys!(u8, u16, u32, u64, u128, usize);
+ordered_signed_keys!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+/// Nibble vectors are their own encoding, so can be used as keys with half-byte precision,
+/// including keys with an odd number of nibbles.
+///
+/// `encode_bytes` can't represent odd lengths, and pads them with a zero nibble. Such
+/// keys are still stored, compared and iterated correctly, as the trie only uses `encode`.
+impl TrieKey for NibbleVec {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn encode(&self) -> NibbleVec {
+        record!(nibble_vec_allocations += 1);
+        self.clone()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        if self.len().is_multiple_of(2) {
+            Some(self.as_bytes())
+        } else {
+            None
+        }
+    }
+}
+
+impl TrieKeyDecode for NibbleVec {
+    fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(NibbleVec::from_byte_vec(bytes.to_vec()))
+    }
+
+    fn decode(nibbles: &NibbleVec) -> Option<Self> {
+        Some(nibbles.clone())
+    }
+}
+
 impl TrieKey for Vec<u8> {
     fn encode_bytes(&self) -> Vec<u8> {
         self.clone()
     }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl TrieKeyDecode for Vec<u8> {
+    fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
 }
 
 impl TrieKey for [u8] {
     fn encode_bytes(&self) -> Vec<u8> {
         self.to_vec()
     }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
 }
 
 impl TrieKey for String {
     fn encode_bytes(&self) -> Vec<u8> {
         self.as_bytes().encode_bytes()
     }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self.as_bytes())
+    }
+}
+
+/// Bytes that aren't valid UTF-8 don't decode.
+impl TrieKeyDecode for String {
+    fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
 }
 
 impl TrieKey for str {
     fn encode_bytes(&self) -> Vec<u8> {
         self.as_bytes().encode_bytes()
     }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self.as_bytes())
+    }
 }
 
 impl<'a, T: ?Sized + TrieKey> TrieKey for &'a T {
     fn encode_bytes(&self) -> Vec<u8> {
         (**self).encode_bytes()
     }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        (**self).encoded_bytes()
+    }
 }
 
 impl<'a, T: ?Sized + TrieKey> TrieKey for &'a mut T {
     fn encode_bytes(&self) -> Vec<u8> {
         (**self).encode_bytes()
     }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        (**self).encoded_bytes()
+    }
 }
 
+impl<T: ?Sized + OrderedTrieKey> OrderedTrieKey for &T {}
+impl<T: ?Sized + OrderedTrieKey> OrderedTrieKey for &mut T {}
+
+macro_rules! ordered_trie_keys {
+    ( $( $t:ty ),* ) => {
+        $(
+        impl OrderedTrieKey for $t {}
+        )*
+    };
+}
+
+ordered_trie_keys!(Vec<u8>, [u8], String, str, u8, u16, u32, u64, usize);
+ordered_trie_keys!(
+    Vec<u16>,
+    [u16],
+    Vec<u32>,
+    [u32],
+    Vec<u64>,
+    [u64],
+    Vec<usize>,
+    [usize]
+);
+ordered_trie_keys!(CStr, CString, Duration, SystemTime);
+ordered_trie_keys!(
+    Ipv4Addr,
+    Ipv6Addr,
+    IpAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    SocketAddr
+);
+ordered_trie_keys!(
+    OrderedKey<u8>,
+    OrderedKey<u16>,
+    OrderedKey<u32>,
+    OrderedKey<u64>,
+    OrderedKey<u128>,
+    OrderedKey<usize>,
+    OrderedKey<i8>,
+    OrderedKey<i16>,
+    OrderedKey<i32>,
+    OrderedKey<i64>,
+    OrderedKey<i128>,
+    OrderedKey<isize>
+);
+
+impl<T: AsRef<[u8]>> OrderedTrieKey for ByteKey<T> {}
+
+// On Windows the encoding is UTF-16, which doesn't sort like `OsStr`'s WTF-8 bytes.
+#[cfg(unix)]
+ordered_trie_keys!(OsStr, OsString);
+
+#[cfg(feature = "uuid")]
+impl OrderedTrieKey for ::uuid::Uuid {}
+
+#[cfg(feature = "bytes")]
+ordered_trie_keys!(::bytes::Bytes, ::bytes::BytesMut);
+
+#[cfg(feature = "smallvec")]
+impl<const N: usize> OrderedTrieKey for ::smallvec::SmallVec<[u8; N]> {}
+
 impl TrieKey for i8 {
     fn encode_bytes(&self) -> Vec<u8> {
         let mut v: Vec<u8> = Vec::with_capacity(1);
@@ -126,6 +769,12 @@ impl TrieKey for i8 {
     }
 }
 
+impl TrieKeyDecode for i8 {
+    fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+        u8::decode_bytes(bytes).map(|b| b as i8)
+    }
+}
+
 impl TrieKey for u8 {
     fn encode_bytes(&self) -> Vec<u8> {
         let mut v: Vec<u8> = Vec::with_capacity(1);
@@ -134,6 +783,237 @@ impl TrieKey for u8 {
     }
 }
 
+impl TrieKeyDecode for u8 {
+    fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+        match *bytes {
+            [b] => Some(b),
+            _ => None,
+        }
+    }
+}
+
+/// OS strings are encoded using the platform's native representation, so the same
+/// `OsString` may encode differently on different platforms. Don't persist encodings (or
+/// rely on iteration order) across platforms.
+///
+/// On Unix the raw bytes are used as-is (via `OsStrExt::as_bytes`), so non-UTF-8 strings
+/// are fine. On Windows the string is encoded as its (potentially ill-formed) UTF-16 code
+/// units, from `OsStrExt::encode_wide`, with each unit written big-endian. Unpaired
+/// surrogates are preserved, so the encoding is injective on both platforms. There are no
+/// implementations on other platforms.
+#[cfg(any(unix, windows))]
+impl TrieKey for OsStr {
+    #[cfg(unix)]
+    fn encode_bytes(&self) -> Vec<u8> {
+        use std::os::unix::ffi::OsStrExt;
+        self.as_bytes().encode_bytes()
+    }
+
+    #[cfg(windows)]
+    fn encode_bytes(&self) -> Vec<u8> {
+        use std::os::windows::ffi::OsStrExt;
+        let mut v = Vec::with_capacity(2 * self.len());
+        for unit in self.encode_wide() {
+            v.extend_from_slice(&unit.to_be_bytes());
+        }
+        v
+    }
+}
+
+/// Encoded identically to `OsStr`, see above.
+#[cfg(any(unix, windows))]
+impl TrieKey for OsString {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.as_os_str().encode_bytes()
+    }
+}
+
+/// C strings are encoded as their bytes *without* the trailing NUL, exactly like the
+/// equivalent `[u8]`. A C string can't contain interior NULs, so this is unambiguous.
+impl TrieKey for CStr {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.to_bytes().encode_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self.to_bytes())
+    }
+}
+
+impl TrieKey for CString {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.as_c_str().encode_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self.to_bytes())
+    }
+}
+
+/// Bytes containing a NUL don't decode.
+impl TrieKeyDecode for CString {
+    fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+        CString::new(bytes).ok()
+    }
+}
+
+/// UUIDs are encoded as their 16 big-endian bytes (requires the `uuid` feature).
+///
+/// Time-ordered UUIDs (v7, or v1/v6) therefore sort chronologically and share prefixes with
+/// UUIDs generated around the same time, which makes prefix scans over time ranges cheap.
+/// Random (v4) UUIDs get no meaningful prefix locality.
+#[cfg(feature = "uuid")]
+impl TrieKey for ::uuid::Uuid {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.as_bytes().encode_bytes()
+    }
+}
+
+/// `Bytes` and `BytesMut` are encoded like the byte slices they hold, so they can be looked up
+/// by `&[u8]` (requires the `bytes` feature).
+#[cfg(feature = "bytes")]
+impl TrieKey for ::bytes::Bytes {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self[..].encode_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(&self[..])
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl TrieKey for ::bytes::BytesMut {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self[..].encode_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(&self[..])
+    }
+}
+
+/// Small byte vectors are encoded like the byte slices they hold, so they can be looked up by
+/// `&[u8]` (requires the `smallvec` feature).
+#[cfg(feature = "smallvec")]
+impl<const N: usize> TrieKey for ::smallvec::SmallVec<[u8; N]> {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self[..].encode_bytes()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(&self[..])
+    }
+}
+
+// Address family tags for IP and socket address keys.
+const IPV4_TAG: u8 = 4;
+const IPV6_TAG: u8 = 6;
+
+/// IP addresses are encoded as an address family tag byte followed by the address
+/// bytes in network order.
+///
+/// `Ipv4Addr` and `Ipv6Addr` carry the tag too, so they encode exactly like the equivalent
+/// `IpAddr`, and all IPv4 addresses sort before all IPv6 addresses.
+impl TrieKey for Ipv4Addr {
+    fn encode_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(5);
+        v.push(IPV4_TAG);
+        v.extend_from_slice(&self.octets());
+        v
+    }
+}
+
+impl TrieKey for Ipv6Addr {
+    fn encode_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(17);
+        v.push(IPV6_TAG);
+        v.extend_from_slice(&self.octets());
+        v
+    }
+}
+
+impl TrieKey for IpAddr {
+    fn encode_bytes(&self) -> Vec<u8> {
+        match *self {
+            IpAddr::V4(ref ip) => ip.encode_bytes(),
+            IpAddr::V6(ref ip) => ip.encode_bytes(),
+        }
+    }
+}
+
+/// Socket addresses are encoded as the encoding of their IP address (including the family
+/// tag), followed by the port as a big-endian `u16`.
+///
+/// All sockets on the same host therefore share a prefix, which is the encoding of the
+/// host's `IpAddr`.
+impl TrieKey for SocketAddrV4 {
+    fn encode_bytes(&self) -> Vec<u8> {
+        let mut v = self.ip().encode_bytes();
+        v.extend_from_slice(&self.port().to_be_bytes());
+        v
+    }
+}
+
+/// IPv6 socket addresses also include the flow info and scope ID (both big-endian `u32`s)
+/// after the port. They're part of `SocketAddrV6`'s equality, so leaving them out would
+/// cause distinct keys to collide.
+impl TrieKey for SocketAddrV6 {
+    fn encode_bytes(&self) -> Vec<u8> {
+        let mut v = self.ip().encode_bytes();
+        v.extend_from_slice(&self.port().to_be_bytes());
+        v.extend_from_slice(&self.flowinfo().to_be_bytes());
+        v.extend_from_slice(&self.scope_id().to_be_bytes());
+        v
+    }
+}
+
+impl TrieKey for SocketAddr {
+    fn encode_bytes(&self) -> Vec<u8> {
+        match *self {
+            SocketAddr::V4(ref addr) => addr.encode_bytes(),
+            SocketAddr::V6(ref addr) => addr.encode_bytes(),
+        }
+    }
+}
+
+/// Durations are encoded as their whole seconds (big-endian `u64`) followed by their
+/// subsecond nanoseconds (big-endian `u32`), so shorter durations sort first.
+impl TrieKey for Duration {
+    fn encode_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(12);
+        v.extend_from_slice(&self.as_secs().to_be_bytes());
+        v.extend_from_slice(&self.subsec_nanos().to_be_bytes());
+        v
+    }
+}
+
+/// System times are encoded relative to the `UNIX_EPOCH`, such that earlier times always sort
+/// before later times, including times before the epoch (which don't panic).
+///
+/// The first byte is `1` for times at or after the epoch, followed by the encoding of the
+/// `Duration` since the epoch. For times before the epoch it's `0`, followed by the
+/// *bitwise complement* of the seconds and nanoseconds of the `Duration` until the epoch, so
+/// that times further in the past sort first.
+impl TrieKey for SystemTime {
+    fn encode_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(13);
+        match self.duration_since(UNIX_EPOCH) {
+            Ok(after) => {
+                v.push(1);
+                v.extend_from_slice(&after.encode_bytes());
+            }
+            Err(err) => {
+                let before = err.duration();
+                v.push(0);
+                v.extend_from_slice(&(!before.as_secs()).to_be_bytes());
+                v.extend_from_slice(&(!before.subsec_nanos()).to_be_bytes());
+            }
+        }
+        v
+    }
+}
+
 #[cfg(unix)]
 impl TrieKey for PathBuf {
     fn encode_bytes(&self) -> Vec<u8> {
@@ -151,6 +1031,11 @@ impl TrieKey for Path {
     }
 }
 
+/// Little-endian integers are encoded as their bytes in memory, least significant first.
+///
+/// **Don't use these as keys if their order matters.** Numbers that differ only in their high
+/// bytes share a long encoded prefix, so iteration order (and `next_after`, views and so on)
+/// bears no relation to numeric order. Use `OrderedKey<T>` for ordered integer keys.
 impl<T> TrieKey for LittleEndian<T>
 where
     T: Eq + Copy,
@@ -178,8 +1063,66 @@ macro_rules! int_keys {
                 be.encode_bytes()
             }
         }
+
+        impl TrieKeyDecode for $t {
+            fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+                bytes.try_into().ok().map(<$t>::from_be_bytes)
+            }
+        }
+
+        impl TrieKeyDecode for BigEndian<$t> {
+            fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+                <$t>::decode_bytes(bytes).map(BigEndian::from)
+            }
+        }
+
+        impl TrieKeyDecode for LittleEndian<$t> {
+            fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+                bytes
+                    .try_into()
+                    .ok()
+                    .map(|bytes| LittleEndian::from(<$t>::from_le_bytes(bytes)))
+            }
+        }
         )*
     };
 }
 
 int_keys!(u16, u32, u64, i16, i32, i64, usize, isize);
+
+// Sequences of integers are encoded element by element, each big-endian like the integer keys
+// above. As every element has the same width, one sequence is an encoded prefix of another
+// exactly when it's a prefix element-wise.
+macro_rules! int_seq_keys {
+    ( $( $t:ty ),* ) => {
+        $(
+        impl TrieKey for [$t] {
+            fn encode_bytes(&self) -> Vec<u8> {
+                let mut v = Vec::with_capacity(self.len() * ::std::mem::size_of::<$t>());
+                for x in self {
+                    v.extend_from_slice(&x.to_be_bytes());
+                }
+                v
+            }
+        }
+
+        impl TrieKey for Vec<$t> {
+            fn encode_bytes(&self) -> Vec<u8> {
+                self.as_slice().encode_bytes()
+            }
+        }
+
+        impl TrieKeyDecode for Vec<$t> {
+            fn decode_bytes(bytes: &[u8]) -> Option<Self> {
+                let size = ::std::mem::size_of::<$t>();
+                if !bytes.len().is_multiple_of(size) {
+                    return None;
+                }
+                bytes.chunks(size).map(<$t>::decode_bytes).collect()
+            }
+        }
+        )*
+    };
+}
+
+int_seq_keys!(u16, u32, u64, i16, i32, i64, usize, isize);
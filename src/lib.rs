@@ -1,32 +1,98 @@
 //! A wonderful, fast, safe, generic radix trie implementation.
 //!
 //! To get started, see the docs for `Trie` below.
+//!
+//! # Cargo features
+//!
+//! * `serde`: `Serialize` and `Deserialize` implementations for `Trie`, `Serialize` for
+//!   `SubTrie`, and the `SerdeKey` wrapper for using any `Serialize` type as a key.
+//! * `ndjson`: the NDJSON `LineFormat` (implies `serde`).
+//! * `uuid`: a `TrieKey` implementation for `uuid::Uuid`.
+//! * `bytes`: `TrieKey` implementations for `bytes::Bytes` and `bytes::BytesMut`.
+//! * `smallvec`: a `TrieKey` implementation for `smallvec::SmallVec<[u8; N]>`.
+//! * `unicode`: the `Normalized` key wrapper, for Unicode-normalized string keys.
+//! * `concurrent`: the `ShardedTrie` type, for sharing a trie between threads.
+//! * `metrics`: `Trie::take_metrics`, for counting the work done by a trie's operations.
+//!   When it's off, the counting is compiled out entirely.
+//! * `profile`: flamegraph output from the `profile` example, with `--trace`.
 
 // #![warn(missing_docs)]
 
+#[cfg(feature = "bytes")]
+extern crate bytes;
 extern crate endian_type;
 extern crate nibble_vec;
 #[cfg(test)]
 extern crate quickcheck;
 #[cfg(test)]
 extern crate rand;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
+#[cfg(feature = "uuid")]
+extern crate uuid;
 
-pub use keys::TrieKey;
+pub use augmented::Aggregate;
+use augmented::{AggregateNode, Count};
+pub use builder::{BuildError, CollisionPolicy};
+pub use convert::ExtendPolicy;
+pub use intern::Internable;
+use intern::{Prefix, Tail};
+pub use keys::{
+    is_prefix_of, key_relationship, ByteKey, KeyBuf, KeyMatch, OrderedKey, OrderedTrieKey, TrieKey,
+    TrieKeyDecode,
+};
+pub use lines::{ImportError, KeyDisplay, LineFormat};
+pub use merge::{merge, MergeIter};
 pub use nibble_vec::NibbleVec;
+pub use persist::{KeyCodec, ValueCodec};
+#[doc(hidden)]
+pub use script::{script_from_bytes, Op, Oracle};
+#[cfg(feature = "serde")]
+pub use serde::RelativeKeys;
+#[cfg(feature = "serde")]
+pub use serde_key::{SerdeKey, SerdeKeyError};
+#[cfg(feature = "concurrent")]
+pub use sharded::{ShardedTrie, SnapshotIter};
 pub use trie_common::TrieCommon;
 use trie_node::TrieNode;
+#[cfg(feature = "unicode")]
+pub use unicode::{Nfc, Nfkc, NormalizationForm, Normalized};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, OnceLock, Weak};
 
 #[macro_use]
 mod macros;
+mod augmented;
+mod builder;
+mod cache;
+mod convert;
+mod intern;
 pub mod iter;
 mod keys;
+mod lines;
+mod merge;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod persist;
+mod report;
+mod script;
 #[cfg(feature = "serde")]
 mod serde;
+#[cfg(feature = "serde")]
+mod serde_key;
+#[cfg(feature = "concurrent")]
+mod sharded;
 mod subtrie;
 mod traversal;
 mod trie;
 mod trie_common;
 mod trie_node;
+#[cfg(feature = "unicode")]
+mod unicode;
+mod view;
 
 #[cfg(test)]
 mod qc_test;
@@ -45,28 +111,264 @@ const BRANCH_FACTOR: usize = 16;
 /// Lots of the methods on `Trie` return optional values - they can be composed
 /// nicely using `Option::and_then`.
 ///
+/// # Iteration order
+///
+/// Iterators over a trie, its subtries and views yield keys in the lexicographic order of
+/// their encodings, nibble by nibble, with each key before the longer keys that extend it.
+/// For keys whose encoding has a whole number of bytes, that's the order of sorting the
+/// results of `TrieKey::encode_bytes`. This is guaranteed, and the ordered queries such as
+/// `next_after`, `pop_prefix_min` and `merge` are defined in terms of it.
+///
+/// Whether that's also the keys' own `Ord` order depends on their encoding: key types for
+/// which it is implement `OrderedTrieKey`.
+///
 /// [radix-wiki]: http://en.wikipedia.org/wiki/Radix_tree
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Trie<K, V> {
     /// The number of values stored in this sub-trie (this node and all descendants).
     length: usize,
     /// The main content of this trie.
     node: TrieNode<K, V>,
+    /// The number of values below each node, if the trie was built with
+    /// `TrieBuilder::count_subtrees`. It's emptied by changes that `insert` and `remove`
+    /// don't keep it in line with, and built again when it's next needed.
+    counts: Option<OnceLock<AggregateNode<Count>>>,
+    /// Counts of the work done by this trie's methods.
+    #[cfg(feature = "metrics")]
+    metrics: metrics::Counters,
 }
 
 /// Immutable view of a sub-tree a larger trie.
-#[derive(Debug)]
 pub struct SubTrie<'a, K: 'a, V: 'a> {
     prefix: NibbleVec,
     node: &'a TrieNode<K, V>,
+    /// The number of values in the subtrie, if the trie counts them.
+    length: Option<usize>,
 }
 
 /// Mutable view of a sub-tree of a larger trie.
-#[derive(Debug)]
 pub struct SubTrieMut<'a, K: 'a, V: 'a> {
     prefix: NibbleVec,
     length: &'a mut usize,
-    node: &'a mut TrieNode<K, V>,
+    /// The parent of this subtrie's node (so that it can be restructured when removing),
+    /// or the node itself if it is the root of the whole trie.
+    parent: &'a mut TrieNode<K, V>,
+    /// The bucket of this subtrie's node in `parent`, or `None` if `parent` is the node.
+    bucket: Option<usize>,
+    /// The length of the prefix the subtrie was created for, which every key in it starts
+    /// with. `prefix` (the node's key) can grow past it when removing values merges the node
+    /// with its only child.
+    scope: usize,
+}
+
+/// Trie that keeps an aggregate of the values below every node, such as their sum or maximum.
+///
+/// This makes `aggregate_prefix` take time proportional to the length of the prefix, rather
+/// than the number of keys below it. The aggregates are kept up to date by `insert`, `remove`
+/// and `update_value`, and recomputed along the path to the changed key each time. There is
+/// no `get_mut`, as changing a value behind the trie's back would leave the aggregates stale;
+/// use `update_value` instead. For everything else, use the read-only `as_trie`.
+///
+/// The combining operation comes from an implementation of `Aggregate<V>` for `A`.
+pub struct AugmentedTrie<K, V, A> {
+    trie: Trie<K, V>,
+    /// The aggregates for each node of `trie`, in a tree of the same shape.
+    aggregates: AggregateNode<A>,
+}
+
+/// Trie that remembers where its most recently used keys are stored, to speed up repeated
+/// lookups of the same few keys.
+///
+/// A cached lookup follows the remembered path straight to the key's node, without encoding
+/// the key or comparing it against the key fragments on the way down. Every entry is checked
+/// against the key it's used for, and any change to the trie forgets them all.
+///
+/// Create one with `Trie::with_lookup_cache`.
+#[derive(Clone)]
+pub struct CachedTrie<K, V> {
+    trie: Trie<K, V>,
+    /// The buckets along the path to each remembered node, most recently used first.
+    cache: RefCell<Vec<Vec<u8>>>,
+}
+
+/// Builder for tries with non-default construction options.
+///
+/// The options apply to the entries passed to `build_from_sorted`, and `count_subtrees` to the
+/// trie that's built. The other opt-in features wrap a trie once it's built:
+/// `Trie::with_lookup_cache` for a cache of recently used keys, and `AugmentedTrie::from` for
+/// aggregates of the values below every node.
+#[derive(Debug, Clone)]
+pub struct TrieBuilder<K, V> {
+    collisions: CollisionPolicy,
+    prefix_free: bool,
+    expected_keys: usize,
+    count_subtrees: bool,
+    marker: PhantomData<fn() -> (K, V)>,
+}
+
+/// Read-only view of the keys in a trie that start with a given prefix.
+///
+/// Unlike a `SubTrie`, a view can be made for any prefix, whether or not it ends at a node.
+/// See `Trie::view`.
+pub struct TrieView<'a, K: 'a, V: 'a> {
+    /// The encoded prefix.
+    prefix: NibbleVec,
+    /// The highest node whose keys all start with the prefix, if there is one.
+    subtrie: Option<SubTrie<'a, K, V>>,
+    /// The number of keys in the view, once it has been computed.
+    length: Cell<Option<usize>>,
+}
+
+/// Summary of the keys below one fixed-width continuation of a prefix.
+///
+/// See `Trie::children_at`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchSummary<'a, V: 'a> {
+    /// The bytes following the prefix.
+    pub continuation: Vec<u8>,
+    /// The number of keys that start with the prefix followed by `continuation`.
+    pub count: usize,
+    /// The value for the key made of exactly the prefix followed by `continuation`, if any.
+    pub value: Option<&'a V>,
+}
+
+/// The number of entries that `Trie::extend_with` inserted under new and existing keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtendStats {
+    /// The number of entries whose keys weren't in the trie.
+    pub inserted: usize,
+    /// The number of entries whose keys were already in the trie, which were handled according
+    /// to the `ExtendPolicy`.
+    pub existing: usize,
+}
+
+/// How the encodings of two keys relate to each other.
+///
+/// See `key_relationship`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRelationship {
+    /// Whether either key is a prefix of the other, or the nibble at which they differ.
+    pub kind: KeyMatch,
+    /// The length of the keys' common prefix, in nibbles.
+    pub common_nibbles: usize,
+}
+
+/// Counts of the work done by a trie's operations (requires the `metrics` feature).
+///
+/// See `Trie::take_metrics`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TrieMetrics {
+    /// The number of nodes looked at on the way to the nodes being searched for.
+    pub nodes_visited: u64,
+    /// The number of nodes split in two by insertions.
+    pub splits: u64,
+    /// The number of value-less nodes merged into their only child by removals.
+    pub merges: u64,
+    /// The number of bytes of encoded keys compared against key fragments in the nodes.
+    pub key_bytes_compared: u64,
+    /// The number of `NibbleVec`s created by encoding keys, and splitting or merging nodes.
+    pub nibble_vec_allocations: u64,
+}
+
+/// How much of its keys' encodings a trie avoids storing by sharing their prefixes.
+///
+/// See `Trie::compression_report`. The `Display` impl formats the report as a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionReport {
+    /// The number of keys.
+    pub keys: usize,
+    /// The number of nodes, including the root, but not leaves left empty by
+    /// `SubTrieMut::remove`.
+    pub nodes: usize,
+    /// The total length of the encoded keys in bytes, as if each were stored separately.
+    pub flat_key_bytes: usize,
+    /// The total length of the encoded keys in nibbles.
+    pub flat_nibbles: usize,
+    /// The total length of the key fragments stored in the nodes, in nibbles.
+    pub stored_nibbles: usize,
+    /// The same totals for the nodes at each depth, where the root is at depth 0.
+    pub levels: Vec<LevelSharing>,
+    /// The prefixes (at nodes below the root) shared by the most keys, most shared first.
+    pub top_prefixes: Vec<SharedPrefix>,
+}
+
+/// The sharing of the nodes at one depth of a trie, as part of a `CompressionReport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LevelSharing {
+    /// The number of nodes at this depth.
+    pub nodes: usize,
+    /// The total length of these nodes' key fragments, in nibbles.
+    pub stored_nibbles: usize,
+    /// The total length of these nodes' key fragments if each key below them had a copy, in
+    /// nibbles.
+    pub flat_nibbles: usize,
+}
+
+/// A prefix at a node, and the number of keys that share it, as part of a
+/// `CompressionReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedPrefix {
+    /// The encoded prefix.
+    pub prefix: NibbleVec,
+    /// The number of keys that start with the prefix.
+    pub keys: usize,
+}
+
+/// A key that shares its prefix, up to the last separator, with the other keys from the
+/// same `KeyPool`.
+///
+/// Make them with `KeyPool::intern` or `TrieBuilder::build_interned`. As the key's bytes
+/// aren't stored in one place, lookups take an `InternedKey` too, e.g.
+/// `trie.get(&InternedKey::from("key"))`, and `to_bytes` (or `to_string` for
+/// `InternedKey<str>`) gives back the whole key.
+pub struct InternedKey<T: ?Sized> {
+    prefix: Option<Arc<Prefix>>,
+    tail: Tail,
+    marker: PhantomData<T>,
+}
+
+/// The shared prefixes of `InternedKey`s with the same separator.
+///
+/// The pool only refers weakly to its prefixes, so removing the last key with a prefix
+/// frees it, and `compact` forgets it.
+pub struct KeyPool<T: ?Sized> {
+    separator: u8,
+    prefixes: HashMap<Vec<u8>, Weak<Prefix>>,
+    marker: PhantomData<T>,
+}
+
+/// The prefixes in a `KeyPool`. See `KeyPool::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyPoolStats {
+    /// The number of prefixes that keys are using.
+    pub prefixes: usize,
+    /// The bytes of those prefixes' last components, which is all that each one stores.
+    pub prefix_bytes: usize,
+    /// The number of prefixes that no key uses any more, which `KeyPool::compact` forgets.
+    pub garbage: usize,
+}
+
+/// The first key at which two tries differ. See `Trie::first_divergence`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence<'a, K: 'a, V: 'a> {
+    /// The key, from the first trie if both hold it.
+    pub key: &'a K,
+    /// The key's value in the first trie, if any.
+    pub left: Option<&'a V>,
+    /// The key's value in the second trie, if any.
+    pub right: Option<&'a V>,
+}
+
+/// Error returned by `Trie::insert_prefix_free` when a key is a proper prefix of another.
+///
+/// Gives back the key and value that weren't inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixConflict<K, V> {
+    /// The key that wasn't inserted.
+    pub key: K,
+    /// The value that wasn't inserted.
+    pub value: V,
 }
 
 /// Wrapper for subtrie lookup results.
@@ -0,0 +1,252 @@
+//! Line-oriented text formats, for exchanging tries with tools like `grep`, `sort` and `join`.
+//!
+//! Each entry is written on a line of its own, in the trie's iteration order, using the text
+//! for its key and value given by `KeyDisplay`. The formats escape whatever that text
+//! contains, so keys and values can hold tabs, newlines and (e.g. as hex) arbitrary bytes.
+
+#[cfg(feature = "ndjson")]
+extern crate serde_json;
+
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use {Trie, TrieBuilder, TrieCommon, TrieKey};
+
+/// A line format for `Trie::export_lines` and `Trie::import_lines`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineFormat {
+    /// The key and value separated by a tab. A backslash, tab, newline or carriage return in
+    /// either is escaped as `\\`, `\t`, `\n` or `\r`.
+    Tsv,
+    /// A JSON object on each line, with the key and value as strings, e.g.
+    /// `{"key":"abc","value":"1"}` (requires the `ndjson` feature).
+    #[cfg(feature = "ndjson")]
+    Ndjson,
+}
+
+/// Conversion of keys and values to and from text, for `Trie::export_lines` and
+/// `Trie::import_lines`.
+///
+/// Despite the name, values are converted with it too.
+pub trait KeyDisplay: Sized {
+    /// Append the text for this key to `out`.
+    fn display_key(&self, out: &mut String);
+
+    /// Parse a key from the text written by `display_key`, or describe what's wrong with it.
+    fn parse_key(text: &str) -> Result<Self, String>;
+}
+
+impl KeyDisplay for String {
+    fn display_key(&self, out: &mut String) {
+        out.push_str(self);
+    }
+
+    fn parse_key(text: &str) -> Result<Self, String> {
+        Ok(text.to_string())
+    }
+}
+
+/// Written in lowercase hex, two digits per byte.
+impl KeyDisplay for Vec<u8> {
+    fn display_key(&self, out: &mut String) {
+        for byte in self {
+            out.push_str(&format!("{:02x}", byte));
+        }
+    }
+
+    fn parse_key(text: &str) -> Result<Self, String> {
+        if !text.len().is_multiple_of(2) {
+            return Err(format!("odd number of hex digits in {:?}", text));
+        }
+        (0..text.len())
+            .step_by(2)
+            .map(|i| {
+                text.get(i..i + 2)
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| format!("invalid hex in {:?}", text))
+            })
+            .collect()
+    }
+}
+
+macro_rules! int_key_display {
+    ($($t:ty),*) => {$(
+        impl KeyDisplay for $t {
+            fn display_key(&self, out: &mut String) {
+                out.push_str(&self.to_string());
+            }
+
+            fn parse_key(text: &str) -> Result<Self, String> {
+                text.parse()
+                    .map_err(|e| format!("invalid {} {:?}: {}", stringify!($t), text, e))
+            }
+        }
+    )*};
+}
+
+int_key_display!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// Error returned by `Trie::import_lines`.
+#[derive(Debug)]
+pub enum ImportError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// A line (numbered from 1) isn't a valid entry.
+    Parse {
+        /// The line number.
+        line: usize,
+        /// What's wrong with the line.
+        reason: String,
+    },
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Io(ref e) => write!(f, "error reading lines: {}", e),
+            ImportError::Parse { line, ref reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ImportError::Io(ref e) => Some(e),
+            ImportError::Parse { .. } => None,
+        }
+    }
+}
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey + KeyDisplay,
+    V: KeyDisplay,
+{
+    /// Write the trie's entries to `w` in the given line format, one per line.
+    ///
+    /// The entries are written in the trie's iteration order, so `import_lines` can build
+    /// the trie again without sorting them. They're written one at a time, so it's best to
+    /// pass a buffered writer.
+    pub fn export_lines<W: Write>(&self, mut w: W, fmt: LineFormat) -> io::Result<()> {
+        let (mut key, mut value, mut line) = (String::new(), String::new(), String::new());
+        for (k, v) in self.iter() {
+            key.clear();
+            value.clear();
+            line.clear();
+            k.display_key(&mut key);
+            v.display_key(&mut value);
+            match fmt {
+                LineFormat::Tsv => {
+                    escape(&key, &mut line);
+                    line.push('\t');
+                    escape(&value, &mut line);
+                }
+                #[cfg(feature = "ndjson")]
+                LineFormat::Ndjson => {
+                    let mut object = serde_json::Map::new();
+                    object.insert("key".to_string(), key.clone().into());
+                    object.insert("value".to_string(), value.clone().into());
+                    line.push_str(&serde_json::Value::Object(object).to_string());
+                }
+            }
+            line.push('\n');
+            w.write_all(line.as_bytes())?;
+        }
+        w.flush()
+    }
+
+    /// Read a trie from lines in the given format, as written by `export_lines`.
+    ///
+    /// Lines may end with `\r\n` as well as `\n`. If a key is repeated, the last value for it
+    /// is kept. As for `Trie::insert`, this panics if two distinct keys have the same
+    /// encoding.
+    ///
+    /// Return `ImportError::Parse`, with the line number, for the first line that isn't in
+    /// the format or whose key or value doesn't parse.
+    pub fn import_lines<R: BufRead>(mut r: R, fmt: LineFormat) -> Result<Trie<K, V>, ImportError> {
+        let mut entries = vec![];
+        let mut buf = vec![];
+        let mut line = 0;
+        loop {
+            buf.clear();
+            if r.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            line += 1;
+            let entry = parse_line(&buf, fmt)
+                .and_then(|(key, value)| Ok((K::parse_key(&key)?, V::parse_key(&value)?)))
+                .map_err(|reason| ImportError::Parse { line, reason })?;
+            entries.push(entry);
+        }
+
+        match TrieBuilder::new().build_from_sorted(entries) {
+            Ok(trie) => Ok(trie),
+            Err(_) => unreachable!("the default builder panics on collisions"),
+        }
+    }
+}
+
+// Split a line into its key and value text.
+fn parse_line(line: &[u8], fmt: LineFormat) -> Result<(String, String), String> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = std::str::from_utf8(line).map_err(|e| e.to_string())?;
+    match fmt {
+        LineFormat::Tsv => {
+            let mut fields = line.split('\t');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(key), Some(value), None) => Ok((unescape(key)?, unescape(value)?)),
+                _ => Err("expected a key and a value separated by a tab".to_string()),
+            }
+        }
+        #[cfg(feature = "ndjson")]
+        LineFormat::Ndjson => {
+            let object: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(line).map_err(|e| e.to_string())?;
+            let field = |name| match object.get(name) {
+                Some(serde_json::Value::String(text)) => Ok(text.clone()),
+                _ => Err(format!("expected a string field {:?}", name)),
+            };
+            Ok((field("key")?, field("value")?))
+        }
+    }
+}
+
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn unescape(field: &str) -> Result<String, String> {
+    let mut text = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => text.push('\\'),
+            Some('t') => text.push('\t'),
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some(c) => return Err(format!("invalid escape \\{}", c)),
+            None => return Err("backslash at the end of a field".to_string()),
+        }
+    }
+    Ok(text)
+}
@@ -4,3 +4,20 @@ macro_rules! id {
         $e
     };
 }
+
+// Add `$n` to one of the operation counters, if the `metrics` feature is enabled.
+macro_rules! record {
+    ($field:ident += $n:expr) => {
+        #[cfg(feature = "metrics")]
+        ::metrics::record(|m| m.$field += $n as u64);
+    };
+}
+
+// Credit the work done by the rest of the enclosing block to `$trie`'s counters, if the
+// `metrics` feature is enabled.
+macro_rules! metrics_scope {
+    ($trie:expr) => {
+        #[cfg(feature = "metrics")]
+        let _scope = ::metrics::Scope::new(&$trie.metrics);
+    };
+}
@@ -0,0 +1,211 @@
+//! Merging several tries in key order, without copying or re-encoding their keys.
+
+use std::cmp::Ordering;
+use trie_node::TrieNode;
+use {Divergence, Trie, TrieKey, BRANCH_FACTOR};
+
+/// Iterate over the keys of all of `tries` in the trie iteration order, with each key's
+/// values from every trie that holds it, in the order the tries are given.
+///
+/// The tries are walked side by side, and only those holding the smallest remaining key are
+/// moved on at each step. Keys are compared by the key fragments along the path to them, so
+/// nothing is encoded or cloned.
+///
+/// Keys from different tries are grouped if they have the same encoding, and the key from
+/// the first of those tries is yielded.
+///
+/// ```
+/// use radix_trie::Trie;
+///
+/// let a: Trie<&str, u32> = vec![("apple", 1), ("cherry", 3)].into_iter().collect();
+/// let b: Trie<&str, u32> = vec![("banana", 2), ("cherry", 4)].into_iter().collect();
+/// let tries = [&a, &b];
+/// let merged: Vec<_> = radix_trie::merge(&tries).collect();
+/// assert_eq!(
+///     merged,
+///     vec![(&"apple", vec![&1]), (&"banana", vec![&2]), (&"cherry", vec![&3, &4])]
+/// );
+/// ```
+pub fn merge<'a, K, V>(tries: &'a [&'a Trie<K, V>]) -> MergeIter<'a, K, V>
+where
+    K: TrieKey,
+{
+    MergeIter {
+        cursors: tries.iter().map(|trie| Cursor::new(&trie.node)).collect(),
+    }
+}
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey,
+{
+    /// Find the smallest key, in iteration order, at which this trie and `other` differ:
+    /// either only one of them holds it, or they hold different values for it.
+    ///
+    /// Returns `None` if the tries have the same entries, however they were built. The tries
+    /// are walked side by side as by `merge`, stopping at the first difference.
+    ///
+    /// ```
+    /// use radix_trie::{Divergence, Trie};
+    ///
+    /// let primary: Trie<&str, u32> = vec![("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+    /// let mut replica: Trie<&str, u32> = vec![("c", 3), ("b", 2), ("a", 1)].into_iter().collect();
+    /// assert_eq!(primary.first_divergence(&replica), None);
+    ///
+    /// replica.insert("b", 20);
+    /// assert_eq!(
+    ///     primary.first_divergence(&replica),
+    ///     Some(Divergence { key: &"b", left: Some(&2), right: Some(&20) })
+    /// );
+    /// ```
+    pub fn first_divergence<'a>(&'a self, other: &'a Trie<K, V>) -> Option<Divergence<'a, K, V>>
+    where
+        V: PartialEq,
+    {
+        let mut left = Cursor::new(&self.node);
+        let mut right = Cursor::new(&other.node);
+        loop {
+            let order = match (left.current(), right.current()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(l), Some(r)) => match left.cmp_key(&right) {
+                    Ordering::Equal if l.value() == r.value() => {
+                        left.advance();
+                        right.advance();
+                        continue;
+                    }
+                    order => order,
+                },
+            };
+            let (l, r) = (left.current(), right.current());
+            return Some(match order {
+                Ordering::Less => Divergence {
+                    key: l.and_then(|l| l.key()).unwrap(),
+                    left: l.and_then(|l| l.value()),
+                    right: None,
+                },
+                Ordering::Greater => Divergence {
+                    key: r.and_then(|r| r.key()).unwrap(),
+                    left: None,
+                    right: r.and_then(|r| r.value()),
+                },
+                Ordering::Equal => Divergence {
+                    key: l.and_then(|l| l.key()).unwrap(),
+                    left: l.and_then(|l| l.value()),
+                    right: r.and_then(|r| r.value()),
+                },
+            });
+        }
+    }
+}
+
+/// Iterator over the keys of several tries in order, created by `merge`.
+pub struct MergeIter<'a, K: 'a, V: 'a> {
+    cursors: Vec<Cursor<'a, K, V>>,
+}
+
+// A position in one of the tries being merged.
+struct Cursor<'a, K: 'a, V: 'a> {
+    // The nodes from the root to the current one, each with the next child bucket to visit.
+    // The last node holds the current key, or the stack is empty once the trie is done.
+    stack: Vec<(&'a TrieNode<K, V>, usize)>,
+}
+
+impl<'a, K, V> Cursor<'a, K, V> {
+    fn new(root: &'a TrieNode<K, V>) -> Cursor<'a, K, V> {
+        let mut cursor = Cursor {
+            stack: vec![(root, 0)],
+        };
+        if root.key_value.is_none() {
+            cursor.advance();
+        }
+        cursor
+    }
+
+    // The node holding the current key, if any.
+    fn current(&self) -> Option<&'a TrieNode<K, V>> {
+        self.stack.last().map(|&(node, _)| node)
+    }
+
+    // Move on to the next node with a key, in iteration order.
+    fn advance(&mut self) {
+        while let Some(&mut (node, ref mut next)) = self.stack.last_mut() {
+            match (*next..BRANCH_FACTOR).find(|&i| node.children[i].is_some()) {
+                Some(bucket) => {
+                    *next = bucket + 1;
+                    let child = node.children[bucket].as_ref().unwrap();
+                    self.stack.push((child, 0));
+                    if child.key_value.is_some() {
+                        return;
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    // Compare the current keys of two cursors that aren't done, by their encodings.
+    fn cmp_key(&self, other: &Cursor<'a, K, V>) -> Ordering {
+        self.nibbles().cmp(other.nibbles())
+    }
+
+    fn nibbles(&self) -> impl Iterator<Item = u8> + '_ {
+        self.stack
+            .iter()
+            .flat_map(|&(node, _)| (0..node.key.len()).map(move |i| node.key.get(i)))
+    }
+}
+
+impl<'a, K, V> Iterator for MergeIter<'a, K, V>
+where
+    K: TrieKey,
+{
+    type Item = (&'a K, Vec<&'a V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut first = None;
+        for (i, cursor) in self.cursors.iter().enumerate() {
+            if cursor.current().is_some()
+                && first.is_none_or(|f: usize| cursor.cmp_key(&self.cursors[f]) == Ordering::Less)
+            {
+                first = Some(i);
+            }
+        }
+        let first = first?;
+
+        // Any earlier cursor with the same key would have been chosen instead.
+        let (before, rest) = self.cursors.split_at_mut(first + 1);
+        let smallest = &mut before[first];
+        let node = smallest.current().unwrap();
+        let mut values = vec![node.value().unwrap()];
+        for cursor in rest {
+            if let Some(other) = cursor.current() {
+                if cursor.cmp_key(smallest) == Ordering::Equal {
+                    values.push(other.value().unwrap());
+                    cursor.advance();
+                }
+            }
+        }
+        smallest.advance();
+        Some((node.key().unwrap(), values))
+    }
+}
+
+impl<'a, K, V> Clone for MergeIter<'a, K, V> {
+    fn clone(&self) -> Self {
+        MergeIter {
+            cursors: self.cursors.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Clone for Cursor<'a, K, V> {
+    fn clone(&self) -> Self {
+        Cursor {
+            stack: self.stack.clone(),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Opt-in counters of the work done by trie operations (requires the `metrics` feature).
+//!
+//! The traversal code adds to counters for the current thread as it goes, which are collected
+//! by the trie whose method is running when that method returns.
+
+use std::cell::Cell;
+use std::sync::Mutex;
+use {Trie, TrieKey, TrieMetrics};
+
+thread_local! {
+    /// How many trie methods are running on this thread, and the work they've done so far.
+    static PENDING: Cell<(usize, TrieMetrics)> = const { Cell::new((0, ZERO)) };
+}
+
+const ZERO: TrieMetrics = TrieMetrics {
+    nodes_visited: 0,
+    splits: 0,
+    merges: 0,
+    key_bytes_compared: 0,
+    nibble_vec_allocations: 0,
+};
+
+/// Add to this thread's pending counters.
+pub fn record<F: FnOnce(&mut TrieMetrics)>(f: F) {
+    PENDING.with(|pending| {
+        let (depth, mut metrics) = pending.get();
+        f(&mut metrics);
+        pending.set((depth, metrics));
+    })
+}
+
+/// A trie's counters.
+#[derive(Debug, Default)]
+pub struct Counters(Mutex<TrieMetrics>);
+
+impl Clone for Counters {
+    fn clone(&self) -> Counters {
+        Counters(Mutex::new(*self.lock()))
+    }
+}
+
+impl Counters {
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, TrieMetrics> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Guard that credits the work done on this thread while it's alive to a trie's counters.
+///
+/// Work done while no guard is alive (e.g. through a subtrie) isn't counted, and work done
+/// under nested guards is credited once, when the innermost guard around it is dropped.
+pub struct Scope<'a> {
+    counters: &'a Counters,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(counters: &'a Counters) -> Scope<'a> {
+        PENDING.with(|pending| {
+            let (depth, metrics) = pending.get();
+            pending.set((depth + 1, if depth == 0 { ZERO } else { metrics }));
+        });
+        Scope { counters }
+    }
+}
+
+impl<'a> Drop for Scope<'a> {
+    fn drop(&mut self) {
+        let work = PENDING.with(|pending| {
+            let (depth, work) = pending.get();
+            pending.set((depth - 1, ZERO));
+            work
+        });
+        let mut counters = self.counters.lock();
+        counters.nodes_visited += work.nodes_visited;
+        counters.splits += work.splits;
+        counters.merges += work.merges;
+        counters.key_bytes_compared += work.key_bytes_compared;
+        counters.nibble_vec_allocations += work.nibble_vec_allocations;
+    }
+}
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey,
+{
+    /// Get the counts of the work done by this trie's methods since the counts were last
+    /// taken (or the trie was created), and reset them to zero.
+    ///
+    /// The counts cover `get`, `get_mut`, `contains_key`, `insert`, `remove`, `remove_if`,
+    /// `subtrie`, `get_ancestor` and `get_raw_descendant`, and the methods built on them.
+    /// Work done through subtries, views, iterators and other wrappers isn't counted.
+    pub fn take_metrics(&self) -> TrieMetrics {
+        ::std::mem::take(&mut *self.metrics.lock())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Counters, Scope};
+    use {Trie, TrieBuilder, TrieCommon, TrieMetrics};
+
+    fn metrics(
+        nodes_visited: u64,
+        splits: u64,
+        merges: u64,
+        key_bytes_compared: u64,
+        nibble_vec_allocations: u64,
+    ) -> TrieMetrics {
+        TrieMetrics {
+            nodes_visited,
+            splits,
+            merges,
+            key_bytes_compared,
+            nibble_vec_allocations,
+        }
+    }
+
+    #[test]
+    fn scripted_operations() {
+        let mut trie = Trie::new();
+
+        // Encoding the key, and the new leaf's key fragment.
+        trie.insert("abc".to_string(), 1);
+        assert_eq!(trie.take_metrics(), metrics(0, 0, 0, 0, 2));
+
+        // "abc" and "abd" differ in their last nibble, so the leaf for "abc" is split into
+        // "ab6" and "3", and "abd" goes in a new leaf, "4".
+        trie.insert("abd".to_string(), 2);
+        assert_eq!(trie.take_metrics(), metrics(1, 1, 0, 3, 3));
+        assert_eq!(trie.take_metrics(), TrieMetrics::default());
+
+        // Looking up by `&str` doesn't encode the key.
+        assert_eq!(trie.get("abd"), Some(&2));
+        assert_eq!(trie.take_metrics(), metrics(2, 0, 0, 4, 0));
+        assert!(trie.contains_key("abd"));
+        assert_eq!(trie.take_metrics(), metrics(2, 0, 0, 4, 0));
+
+        // Removing "abd" leaves "ab6" without a value and with one child, so they're merged.
+        assert_eq!(trie.remove("abd"), Some(2));
+        assert_eq!(trie.take_metrics(), metrics(2, 0, 1, 4, 2));
+        assert!(trie.check_integrity());
+    }
+
+    #[test]
+    fn uncounted_work() {
+        let mut trie = Trie::new();
+        trie.insert("abc".to_string(), 1);
+        trie.insert("abd".to_string(), 2);
+        let copy = trie.clone();
+        trie.take_metrics();
+
+        // Work through a subtrie isn't counted, even by the trie's next operation.
+        let subtrie = trie.get_raw_descendant("ab").unwrap();
+        trie.take_metrics();
+        assert_eq!(subtrie.get("abd"), Ok(Some(&2)));
+        trie.get("abc");
+        assert_eq!(trie.take_metrics(), metrics(2, 0, 0, 4, 0));
+
+        // Clones count separately.
+        assert_eq!(copy.take_metrics(), metrics(1, 1, 0, 3, 5));
+        assert_eq!(copy.take_metrics(), TrieMetrics::default());
+    }
+
+    // The work done by `f`, credited to a fresh set of counters as if it were a trie's method.
+    fn work<F: FnOnce()>(f: F) -> TrieMetrics {
+        let counters = Counters::default();
+        {
+            let _scope = Scope::new(&counters);
+            f();
+        }
+        let work = *counters.lock();
+        work
+    }
+
+    #[test]
+    fn counted_subtrees() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("k/{:03}", i)).collect();
+        let plain = TrieBuilder::new()
+            .build_from_sorted(keys.iter().map(|k| (k.as_str(), ())))
+            .unwrap();
+        let counted = TrieBuilder::new()
+            .count_subtrees(true)
+            .build_from_sorted(keys.iter().map(|k| (k.as_str(), ())))
+            .unwrap();
+
+        // Without the counts, the length of a subtrie visits every node in it.
+        let subtrie = plain.get_raw_descendant("k/").unwrap();
+        assert!(work(|| assert_eq!(subtrie.len(), 1000)).nodes_visited >= 1000);
+
+        let subtrie = counted.get_raw_descendant("k/").unwrap();
+        assert_eq!(work(|| assert_eq!(subtrie.len(), 1000)).nodes_visited, 0);
+
+        // Likewise for the number of keys in each summary from `children_at`.
+        let summaries = |trie: &Trie<&str, ()>| {
+            let summaries = trie.children_at("k/", 1);
+            assert_eq!(summaries.len(), 10);
+            assert!(summaries.iter().all(|summary| summary.count == 100));
+        };
+        assert!(work(|| summaries(&plain)).nodes_visited >= 1000);
+        assert!(work(|| summaries(&counted)).nodes_visited < 10);
+    }
+}
@@ -0,0 +1,195 @@
+//! A simple binary format for saving tries to disk, without serde.
+//!
+//! The format is a header of the magic bytes `RDXT`, a format version byte and the number of
+//! entries as a big-endian `u64`, followed by the entries in the trie's iteration order. Each
+//! entry is a key then a value, and each of those is a big-endian `u32` length followed by
+//! that many bytes, as produced by `KeyCodec` or `ValueCodec`.
+
+use keys::{match_keys, KeyMatch};
+use std::io::{self, Read, Write};
+use trie_node::TrieNode;
+use {NibbleVec, Trie, TrieCommon, TrieKey};
+
+const MAGIC: &[u8; 4] = b"RDXT";
+const VERSION: u8 = 1;
+
+/// Conversion of keys to and from bytes, for `Trie::write_to` and `Trie::read_from`.
+pub trait KeyCodec: Sized {
+    /// Append the bytes for this key to `out`.
+    fn write_key(&self, out: &mut Vec<u8>);
+
+    /// Decode a key from the bytes written by `write_key`.
+    fn read_key(bytes: Vec<u8>) -> io::Result<Self>;
+}
+
+/// Conversion of values to and from bytes, for `Trie::write_to` and `Trie::read_from`.
+pub trait ValueCodec: Sized {
+    /// Append the bytes for this value to `out`.
+    fn write_value(&self, out: &mut Vec<u8>);
+
+    /// Decode a value from the bytes written by `write_value`.
+    fn read_value(bytes: Vec<u8>) -> io::Result<Self>;
+}
+
+impl KeyCodec for Vec<u8> {
+    fn write_key(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn read_key(bytes: Vec<u8>) -> io::Result<Self> {
+        Ok(bytes)
+    }
+}
+
+impl KeyCodec for Box<[u8]> {
+    fn write_key(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn read_key(bytes: Vec<u8>) -> io::Result<Self> {
+        Ok(bytes.into_boxed_slice())
+    }
+}
+
+impl KeyCodec for String {
+    fn write_key(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn read_key(bytes: Vec<u8>) -> io::Result<Self> {
+        String::from_utf8(bytes).map_err(|e| invalid_data(&e.to_string()))
+    }
+}
+
+macro_rules! int_value_codec {
+    ($($t:ty),*) => {$(
+        impl ValueCodec for $t {
+            fn write_value(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn read_value(bytes: Vec<u8>) -> io::Result<Self> {
+                let mut buf = [0; ::std::mem::size_of::<$t>()];
+                if bytes.len() != buf.len() {
+                    return Err(invalid_data("wrong length for integer value"));
+                }
+                buf.copy_from_slice(&bytes);
+                Ok(<$t>::from_be_bytes(buf))
+            }
+        }
+    )*};
+}
+
+int_value_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey + KeyCodec,
+    V: ValueCodec,
+{
+    /// Write the trie's contents to `w`, in a compact binary format.
+    ///
+    /// The entries are written one at a time in the trie's iteration order, so it's best to
+    /// pass a buffered writer.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&(self.len() as u64).to_be_bytes())?;
+
+        let mut buf = vec![];
+        for (key, value) in self.iter() {
+            buf.clear();
+            write_field(&mut buf, |out| key.write_key(out))?;
+            write_field(&mut buf, |out| value.write_value(out))?;
+            w.write_all(&buf)?;
+        }
+        w.flush()
+    }
+
+    /// Read a trie written by `write_to`.
+    ///
+    /// Return an error of kind `InvalidData` if the input isn't in the expected format (or
+    /// was written by an incompatible version), including if its keys repeat or aren't in
+    /// the trie's iteration order, and `UnexpectedEof` if it's truncated.
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<Trie<K, V>> {
+        let mut header = [0; 13];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not a radix_trie file"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported format version {} (expected {})",
+                header[4], VERSION
+            )));
+        }
+        let mut count = [0; 8];
+        count.copy_from_slice(&header[5..]);
+        let count = u64::from_be_bytes(count);
+
+        // The entries were written in iteration order, so the trie can be built in one pass
+        // once they've all been read, from each key's common prefix with the one before it.
+        let mut entries: Vec<(usize, NibbleVec, K, V)> = vec![];
+        for _ in 0..count {
+            let key = K::read_key(read_field(&mut r)?)?;
+            let value = V::read_value(read_field(&mut r)?)?;
+            let nv = key.encode();
+            let common = match entries.last() {
+                None => 0,
+                Some((_, prev, _, _)) => match match_keys(0, prev, &nv) {
+                    KeyMatch::FirstPrefix => prev.len(),
+                    KeyMatch::Partial(idx) if prev.get(idx) < nv.get(idx) => idx,
+                    KeyMatch::Full => return Err(invalid_data("duplicate key")),
+                    _ => return Err(invalid_data("keys out of order")),
+                },
+            };
+            entries.push((common, nv, key, value));
+        }
+        Ok(Trie {
+            length: entries.len(),
+            node: TrieNode::from_sorted(entries),
+            counts: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        })
+    }
+}
+
+fn write_field<F>(buf: &mut Vec<u8>, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut Vec<u8>),
+{
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    write(buf);
+    let len = buf.len() - start - 4;
+    if len > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "key or value too long",
+        ));
+    }
+    buf[start..start + 4].copy_from_slice(&(len as u32).to_be_bytes());
+    Ok(())
+}
+
+fn read_field<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    r.read_exact(&mut len)?;
+    let len = u64::from(u32::from_be_bytes(len));
+
+    // Don't trust the length enough to allocate it all up front.
+    let mut bytes = vec![];
+    r.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated key or value",
+        ));
+    }
+    Ok(bytes)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
@@ -1,11 +1,16 @@
 //! Proper testing, with QuickCheck.
 
+use endian_type::{BigEndian, LittleEndian};
 use quickcheck::{quickcheck, Arbitrary, Gen};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CString;
 use std::iter::FromIterator;
-use {Trie, TrieCommon, TrieKey};
+use {
+    merge, script_from_bytes, Aggregate, AugmentedTrie, InternedKey, KeyPool, LineFormat, Oracle,
+    OrderedKey, Trie, TrieBuilder, TrieCommon, TrieKey, TrieKeyDecode,
+};
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct Key(Vec<u8>);
 
 #[derive(Clone, Debug)]
@@ -165,6 +170,24 @@ fn subtrie_get() {
     quickcheck(prop as fn(RandomKeys, Key, Key) -> bool);
 }
 
+#[test]
+fn subtrie_get_ancestor_within() {
+    fn prop(trie_keys: RandomKeys, k1: Key, k2: Key) -> bool {
+        let mut trie = length_trie(trie_keys.0);
+        trie.insert(k1.clone(), k1.len());
+
+        // The longest key that starts with `k1` and is a prefix of `k2`.
+        let expected = trie
+            .keys()
+            .filter(|k| k.0.starts_with(&k1.0) && k2.0.starts_with(&k.0))
+            .max_by_key(|k| k.len());
+        let subtrie = trie.subtrie(&k1).unwrap();
+        subtrie.get_ancestor_within(&k2).map(|(k, _)| k) == expected
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key, Key) -> bool);
+}
+
 #[test]
 fn subtrie_mut_get() {
     fn prop(trie_keys: RandomKeys, k1: Key, k2: Key) -> bool {
@@ -217,6 +240,69 @@ fn subtrie_insert() {
     quickcheck(prop as fn(RandomKeys, RandomKeys, Key) -> bool);
 }
 
+#[test]
+fn subtrie_mut_ops_match_model() {
+    fn prop(RandomKeys(keys): RandomKeys, prefix: Key, ops: Vec<(u8, Key, usize)>) -> bool {
+        let mut model: BTreeMap<Vec<u8>, usize> = keys.into_iter().map(|k| (k.0, 0)).collect();
+        model.insert(prefix.0.clone(), 0);
+        let mut trie: Trie<Vec<u8>, usize> = model.clone().into_iter().collect();
+
+        {
+            let mut subtrie = trie.subtrie_mut(&prefix.0).unwrap();
+            for (op, suffix, value) in ops {
+                let key = prefix.extend(suffix.clone()).0;
+                let ok = match op % 5 {
+                    0 => subtrie.insert(key.clone(), value) == Ok(model.insert(key, value)),
+                    1 => {
+                        subtrie.insert_relative(&suffix.0, key.clone(), value)
+                            == Ok(model.insert(key, value))
+                    }
+                    2 => subtrie.remove(&key) == Ok(model.remove(&key)),
+                    3 => subtrie.remove_relative(&suffix.0) == model.remove(&key),
+                    _ => subtrie.get(&key) == Ok(model.get(&key)),
+                };
+                if !ok {
+                    return false;
+                }
+            }
+        }
+
+        trie.check_integrity() && trie.len() == model.len() && trie.iter().eq(model.iter())
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key, Vec<(u8, Key, usize)>) -> bool);
+}
+
+#[test]
+fn subtrie_mut_remove_all() {
+    fn prop(RandomKeys(keys): RandomKeys, prefix: Key) -> bool {
+        let mut trie = length_trie(keys);
+        trie.insert(prefix.clone(), prefix.len());
+        let expected = trie.len() - trie.subtrie(&prefix).unwrap().len();
+
+        {
+            // Remove the shortest keys first, so that the node loses its own value early on.
+            let mut subtrie = trie.subtrie_mut(&prefix).unwrap();
+            let mut keys: Vec<Key> = (&subtrie).keys().cloned().collect();
+            keys.sort_by_key(Key::len);
+            for key in keys {
+                if subtrie.remove(&key) != Ok(Some(key.len())) {
+                    return false;
+                }
+            }
+            if subtrie.prune() != 0 {
+                return false;
+            }
+        }
+
+        // Only the root stays once it's empty.
+        let gone = prefix.len() == 0 || trie.subtrie(&prefix).is_none();
+        trie.check_integrity() && trie.len() == expected && gone
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key) -> bool);
+}
+
 // Construct a trie from a set of keys, with each key mapped to its length.
 fn length_trie(keys: HashSet<Key>) -> Trie<Key, usize> {
     let mut t = Trie::new();
@@ -272,3 +358,689 @@ fn values_iter() {
     }
     quickcheck(prop as fn(RandomKeys) -> bool);
 }
+
+#[test]
+fn remove_all() {
+    fn prop(RandomKeys(insert_keys): RandomKeys, RandomKeys(remove_keys): RandomKeys) -> bool {
+        let mut to_remove: Vec<Key> = insert_keys
+            .iter()
+            .filter(|k| k.len() % 2 == 0)
+            .cloned()
+            .chain(remove_keys)
+            .collect();
+
+        // Sorted (with duplicates) and unsorted removal should both match the naive loop.
+        let mut naive = length_trie(insert_keys.clone());
+        let naive_removed = to_remove
+            .iter()
+            .filter(|k| naive.remove(k).is_some())
+            .count();
+
+        let mut unsorted = length_trie(insert_keys.clone());
+        let unsorted_removed = unsorted.remove_all(to_remove.clone());
+
+        to_remove.sort();
+        let mut sorted = length_trie(insert_keys);
+        let sorted_removed = sorted.remove_all(to_remove);
+
+        naive_removed == sorted_removed
+            && naive_removed == unsorted_removed
+            && sorted.check_integrity()
+            && unsorted.check_integrity()
+            && sorted == naive
+            && unsorted == naive
+    }
+    quickcheck(prop as fn(RandomKeys, RandomKeys) -> bool);
+}
+
+#[test]
+fn leaves_and_internal() {
+    fn prop(RandomKeys(keys): RandomKeys) -> bool {
+        let trie = length_trie(keys.clone());
+        let has_extension = |k: &Key| {
+            keys.iter()
+                .any(|o| o.len() > k.len() && o.0.starts_with(&k.0))
+        };
+
+        let leaves: HashSet<Key> = trie.iter_leaves().map(|(k, _)| k.clone()).collect();
+        let internal: HashSet<Key> = trie.iter_internal().map(|(k, _)| k.clone()).collect();
+
+        leaves.len() + internal.len() == keys.len()
+            && leaves.iter().all(|k| keys.contains(k) && !has_extension(k))
+            && internal
+                .iter()
+                .all(|k| keys.contains(k) && has_extension(k))
+    }
+
+    quickcheck(prop as fn(RandomKeys) -> bool);
+}
+
+#[test]
+fn remove_subtrie() {
+    fn prop(RandomKeys(keys): RandomKeys, prefix: Key) -> bool {
+        let mut trie = length_trie(keys.clone());
+        let in_subtrie = |k: &Key| k.0.starts_with(&prefix.0);
+        let expected = keys.iter().filter(|k| in_subtrie(k)).count();
+
+        let copied = trie.subtrie(&prefix).map(|s| s.to_trie());
+        let removed = trie.remove_subtrie(&prefix);
+        if copied.is_none() || removed.is_none() {
+            return copied.is_none() && removed.is_none() && trie.len() == keys.len();
+        }
+        let (copied, removed) = (copied.unwrap(), removed.unwrap());
+
+        copied.check_integrity()
+            && removed.check_integrity()
+            && trie.check_integrity()
+            && copied == removed
+            && removed.len() == expected
+            && removed.keys().all(&in_subtrie)
+            && trie.len() == keys.len() - expected
+            && trie.keys().all(|k| !in_subtrie(k))
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key) -> bool);
+}
+
+#[test]
+fn subtrie_mut_prune_and_remove_value() {
+    fn prop(RandomKeys(keys): RandomKeys, key: Key) -> bool {
+        let mut pruned = length_trie(keys.clone());
+        let mut expected_pruned = pruned.clone();
+        let removed = pruned.subtrie_mut(&key).map(|s| s.prune());
+        let expected_removed = expected_pruned.remove_subtrie(&key).map(|t| t.len());
+
+        let mut unvalued = length_trie(keys.clone());
+        let value = unvalued.subtrie_mut(&key).and_then(|s| s.remove_value());
+
+        removed == expected_removed
+            && pruned.check_integrity()
+            && pruned == expected_pruned
+            && unvalued.check_integrity()
+            && value == length_trie(keys.clone()).remove(&key)
+            && unvalued.len() == keys.len() - value.map_or(0, |_| 1)
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key) -> bool);
+}
+
+#[test]
+fn remove_if() {
+    fn prop(RandomKeys(insert_keys): RandomKeys, RandomKeys(remove_keys): RandomKeys) -> bool {
+        let mut trie = length_trie(insert_keys.clone());
+        let mut expected = trie.clone();
+
+        for k in &remove_keys {
+            // Only remove keys of even length.
+            let removed = trie.remove_if(k, |&v| v % 2 == 0);
+            let expected_removed = match expected.get(k) {
+                Some(&v) if v % 2 == 0 => expected.remove(k),
+                _ => None,
+            };
+            if removed != expected_removed {
+                return false;
+            }
+        }
+
+        trie.check_integrity() && trie == expected
+    }
+
+    quickcheck(prop as fn(RandomKeys, RandomKeys) -> bool);
+}
+
+#[test]
+fn next_after() {
+    fn prop(RandomKeys(keys): RandomKeys, key: Key) -> bool {
+        let trie = length_trie(keys.clone());
+        let expected = keys.iter().filter(|k| **k >= key).min();
+        trie.next_after(&key).map(|(k, _)| k) == expected
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key) -> bool);
+}
+
+#[test]
+fn insert_prefix_free() {
+    fn prop(RandomKeys(keys): RandomKeys) -> bool {
+        let mut trie = Trie::new();
+        let mut rejected = vec![];
+        for k in keys {
+            if let Err(conflict) = trie.insert_prefix_free(k, ()) {
+                rejected.push(conflict.key);
+            }
+        }
+
+        let conflicts = |k: &Key| {
+            trie.keys()
+                .any(|o| o != k && (o.0.starts_with(&k.0) || k.0.starts_with(&o.0)))
+        };
+        trie.check_integrity() && trie.is_prefix_free() && rejected.iter().all(conflicts)
+    }
+
+    quickcheck(prop as fn(RandomKeys) -> bool);
+}
+
+#[test]
+fn write_to_read_from() {
+    fn prop(RandomKeys(keys): RandomKeys) -> bool {
+        let trie: Trie<Vec<u8>, u64> = keys
+            .into_iter()
+            .map(|k| {
+                let value = k.len() as u64 * 0x0101_0101;
+                (k.0, value)
+            })
+            .collect();
+        let mut bytes = vec![];
+        trie.write_to(&mut bytes).unwrap();
+
+        let read = Trie::read_from(&bytes[..]).unwrap();
+        read.check_integrity() && read == trie
+    }
+
+    quickcheck(prop as fn(RandomKeys) -> bool);
+}
+
+#[test]
+fn export_import_lines() {
+    fn prop(entries: Vec<(String, Vec<u8>)>) -> bool {
+        let trie: Trie<String, Vec<u8>> = entries.into_iter().collect();
+        let mut text = vec![];
+        trie.export_lines(&mut text, LineFormat::Tsv).unwrap();
+
+        let read = Trie::import_lines(&text[..], LineFormat::Tsv).unwrap();
+        read.check_integrity() && read == trie
+    }
+
+    quickcheck(prop as fn(Vec<(String, Vec<u8>)>) -> bool);
+}
+
+#[test]
+fn get_many_mut() {
+    fn prop(RandomKeys(keys): RandomKeys, k1: Key, k2: Key, k3: Key) -> bool {
+        let mut trie = length_trie(keys.clone());
+        let expected = if k1 == k2 || k1 == k3 || k2 == k3 {
+            None
+        } else {
+            match (trie.get(&k1), trie.get(&k2), trie.get(&k3)) {
+                (Some(&a), Some(&b), Some(&c)) => Some([a, b, c]),
+                _ => None,
+            }
+        };
+        let got = trie.get_many_mut([&k1, &k2, &k3]).map(|[a, b, c]| {
+            *a += 1000;
+            [*a - 1000, *b, *c]
+        });
+        got == expected && (expected.is_none() || trie.get(&k1) == Some(&(k1.len() + 1000)))
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key, Key, Key) -> bool);
+}
+
+#[test]
+fn std_map_round_trip() {
+    use std::collections::BTreeMap;
+
+    fn prop(RandomKeys(keys): RandomKeys) -> bool {
+        let btree: BTreeMap<Key, usize> = keys.iter().map(|k| (k.clone(), k.len())).collect();
+        let hash: HashMap<Key, usize> = btree.clone().into_iter().collect();
+        let expected = length_trie(keys);
+
+        let from_btree = Trie::from(btree.clone());
+        let from_hash = Trie::from(hash.clone());
+        from_btree.check_integrity()
+            && from_hash.check_integrity()
+            && from_btree.len() == btree.len()
+            && from_btree == expected
+            && from_hash == expected
+            && BTreeMap::from(from_btree) == btree
+            && HashMap::from(from_hash) == hash
+    }
+
+    quickcheck(prop as fn(RandomKeys) -> bool);
+}
+
+#[test]
+fn view_matches_filter() {
+    fn prop(RandomKeys(keys): RandomKeys, prefix: Key) -> bool {
+        let trie = length_trie(keys.clone());
+        let view = trie.view(&prefix);
+        let expected: Vec<&Key> = trie.keys().filter(|k| k.0.starts_with(&prefix.0)).collect();
+        view.len() == expected.len()
+            && view.keys().eq(expected.iter().cloned())
+            && keys
+                .iter()
+                .all(|k| view.get(k).is_some() == k.0.starts_with(&prefix.0))
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key) -> bool);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SumMax(usize, Option<usize>);
+
+impl Aggregate<usize> for SumMax {
+    fn identity() -> SumMax {
+        SumMax(0, None)
+    }
+
+    fn from_value(value: &usize) -> SumMax {
+        SumMax(*value, Some(*value))
+    }
+
+    fn combine(&self, other: &SumMax) -> SumMax {
+        SumMax(self.0 + other.0, self.1.max(other.1))
+    }
+}
+
+#[test]
+fn augmented_matches_brute_force() {
+    fn prop(ops: Vec<(u8, Key, usize)>) -> bool {
+        let mut trie: AugmentedTrie<Key, usize, SumMax> = AugmentedTrie::new();
+        let mut prefixes = vec![Key(vec![])];
+        for (op, key, value) in ops {
+            match op % 3 {
+                0 => {
+                    trie.insert(key.clone(), value);
+                }
+                1 => {
+                    trie.remove(&key);
+                }
+                _ => {
+                    trie.update_value(&key, |v| *v = value);
+                }
+            }
+            prefixes.extend((0..key.0.len()).map(|i| Key(key.0[..i].to_vec())));
+            prefixes.push(key);
+
+            let ok = prefixes.iter().all(|prefix| {
+                let expected = trie
+                    .as_trie()
+                    .iter()
+                    .filter(|&(k, _)| k.0.starts_with(&prefix.0))
+                    .fold(SumMax::identity(), |acc, (_, v)| {
+                        acc.combine(&SumMax::from_value(v))
+                    });
+                trie.aggregate_prefix(prefix) == expected
+            });
+            if !ok || !trie.as_trie().check_integrity() {
+                return false;
+            }
+        }
+        true
+    }
+
+    quickcheck(prop as fn(Vec<(u8, Key, usize)>) -> bool);
+}
+
+#[test]
+fn lookup_cache_matches_trie() {
+    fn prop(ops: Vec<(u8, Key, usize)>) -> bool {
+        let mut cached = Trie::new().with_lookup_cache();
+        let mut plain = Trie::new();
+        for (op, key, value) in ops {
+            let ok = match op % 4 {
+                0 => cached.insert(key.clone(), value) == plain.insert(key, value),
+                1 => cached.remove(&key) == plain.remove(&key),
+                2 => cached.get_mut(&key).map(|v| *v) == plain.get_mut(&key).map(|v| *v),
+                _ => cached.get(&key) == plain.get(&key),
+            };
+            if !ok {
+                return false;
+            }
+        }
+        plain.keys().all(|key| cached.get(key) == plain.get(key))
+    }
+
+    quickcheck(prop as fn(Vec<(u8, Key, usize)>) -> bool);
+}
+
+#[test]
+fn group_by_prefix_partitions() {
+    fn prop(RandomKeys(keys): RandomKeys, depth: u8) -> bool {
+        let depth = depth as usize % 4;
+        let trie = length_trie(keys);
+        let mut seen = HashSet::new();
+        for (prefix, subtrie) in trie.group_by_prefix(depth) {
+            if prefix.len() != depth {
+                return false;
+            }
+            for (key, _) in subtrie.iter() {
+                if !key.0.starts_with(&prefix) || !seen.insert(key.clone()) {
+                    return false;
+                }
+            }
+        }
+        for (key, _) in trie.short_keys(depth) {
+            if key.len() >= depth || !seen.insert(key.clone()) {
+                return false;
+            }
+        }
+        seen.len() == trie.len()
+    }
+
+    quickcheck(prop as fn(RandomKeys, u8) -> bool);
+}
+
+// Look up a key by stepping down one child at a time.
+fn get_by_children<'a>(trie: &'a Trie<Key, usize>, key: &Key) -> Option<&'a usize> {
+    let nv = key.encode();
+    let mut subtrie = match nv.len() {
+        0 => return trie.get(key),
+        _ => trie.child(nv.get(0))?,
+    };
+    let mut depth = 0;
+    loop {
+        let edge = subtrie.prefix();
+        for i in 0..edge.len() {
+            if depth + i >= nv.len() || nv.get(depth + i) != edge.get(i) {
+                return None;
+            }
+        }
+        depth += edge.len();
+        if depth == nv.len() {
+            return subtrie.value();
+        }
+        subtrie = subtrie.child(nv.get(depth))?;
+    }
+}
+
+#[test]
+fn child_lookup_matches_get() {
+    fn prop(RandomKeys(keys): RandomKeys, others: Vec<Key>) -> bool {
+        let trie = length_trie(keys.clone());
+        keys.iter()
+            .chain(&others)
+            .all(|key| get_by_children(&trie, key) == trie.get(key))
+    }
+
+    quickcheck(prop as fn(RandomKeys, Vec<Key>) -> bool);
+}
+
+fn decodes<K: TrieKeyDecode + Clone>(key: K) -> bool {
+    K::decode(&key.encode()).is_some_and(|decoded| decoded == key)
+}
+
+#[test]
+fn decode_bytes_and_strings() {
+    fn prop(bytes: Vec<u8>, string: String) -> bool {
+        let c_string = CString::new(
+            bytes
+                .iter()
+                .filter(|&&b| b != 0)
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        decodes(bytes.clone())
+            && decodes(string)
+            && decodes(c_string.unwrap())
+            && decodes(::NibbleVec::from_byte_vec(bytes))
+    }
+
+    quickcheck(prop as fn(Vec<u8>, String) -> bool);
+}
+
+#[test]
+fn decode_integers() {
+    fn prop(a: u8, b: i8, c: u16, d: i16, e: u32, f: i32, h: i64) -> bool {
+        decodes(a)
+            && decodes(b)
+            && decodes(c)
+            && decodes(d)
+            && decodes(e)
+            && decodes(f)
+            && decodes(h as u64)
+            && decodes(h)
+            && decodes(h as usize)
+            && decodes(h as isize)
+    }
+
+    quickcheck(prop as fn(u8, i8, u16, i16, u32, i32, i64) -> bool);
+}
+
+#[test]
+fn decode_endian_wrappers() {
+    fn prop(c: u16, f: i32, g: u64, h: i64) -> bool {
+        decodes(BigEndian::from(c))
+            && decodes(LittleEndian::from(c))
+            && decodes(BigEndian::from(f))
+            && decodes(LittleEndian::from(f))
+            && decodes(BigEndian::from(g))
+            && decodes(LittleEndian::from(g))
+            && decodes(BigEndian::from(h as isize))
+            && decodes(LittleEndian::from(h as usize))
+    }
+
+    quickcheck(prop as fn(u16, i32, u64, i64) -> bool);
+}
+
+#[test]
+fn decode_integer_sequences() {
+    fn prop(a: Vec<u16>, b: Vec<i32>, c: Vec<u64>, d: Vec<isize>) -> bool {
+        decodes(a) && decodes(b) && decodes(c) && decodes(d)
+    }
+
+    quickcheck(prop as fn(Vec<u16>, Vec<i32>, Vec<u64>, Vec<isize>) -> bool);
+}
+
+#[test]
+fn retain_prefixes_matches_filter() {
+    fn prop(RandomKeys(keys): RandomKeys, prefixes: Vec<Key>) -> bool {
+        let mut trie = length_trie(keys.clone());
+        let expected = length_trie(
+            keys.into_iter()
+                .filter(|key| prefixes.iter().any(|p| key.0.starts_with(&p.0)))
+                .collect(),
+        );
+        let before = trie.len();
+        let removed = trie.retain_prefixes(&prefixes);
+        removed == before - expected.len()
+            && trie.len() == expected.len()
+            && trie == expected
+            && trie.check_integrity()
+    }
+
+    quickcheck(prop as fn(RandomKeys, Vec<Key>) -> bool);
+}
+
+#[test]
+fn pop_prefix_matches_iteration() {
+    fn prop(RandomKeys(keys): RandomKeys, prefix: Key, last: bool) -> bool {
+        let mut trie = length_trie(keys);
+        let mut expected: Vec<Key> = trie
+            .keys()
+            .filter(|k| k.0.starts_with(&prefix.0) && k.len() > prefix.len())
+            .cloned()
+            .collect();
+        if last {
+            expected.reverse();
+        }
+        let before = trie.len();
+
+        let mut popped = vec![];
+        loop {
+            let next = if last {
+                trie.pop_prefix_max(&prefix)
+            } else {
+                trie.pop_prefix_min(&prefix)
+            };
+            match next {
+                Some((key, len)) if key.len() == len && trie.check_integrity() => popped.push(key),
+                Some(_) => return false,
+                None => break,
+            }
+        }
+        popped == expected && trie.len() == before - popped.len()
+    }
+
+    quickcheck(prop as fn(RandomKeys, Key, bool) -> bool);
+}
+
+// Check that ordered keys iterate in numeric order, and that `next_after` finds the smallest
+// key at or after `start`.
+fn iterates_in_order<T>(values: Vec<T>, start: T) -> bool
+where
+    T: Copy + Ord,
+    OrderedKey<T>: TrieKeyDecode,
+{
+    let trie: Trie<OrderedKey<T>, ()> = values.iter().map(|&x| (OrderedKey(x), ())).collect();
+    let mut sorted = values.clone();
+    sorted.sort();
+    sorted.dedup();
+    let keys: Vec<T> = trie.keys().map(|key| key.0).collect();
+    let next = trie.next_after(&OrderedKey(start)).map(|(key, _)| key.0);
+    keys == sorted
+        && next == sorted.iter().cloned().find(|&x| x >= start)
+        && values.into_iter().all(|x| decodes(OrderedKey(x)))
+}
+
+#[test]
+fn ordered_keys_iterate_in_order() {
+    fn prop(a: Vec<i8>, b: Vec<u16>, c: Vec<i32>, d: Vec<i64>, e: Vec<usize>, start: i64) -> bool {
+        iterates_in_order(a, start as i8)
+            && iterates_in_order(b, start as u16)
+            && iterates_in_order(c, start as i32)
+            && iterates_in_order(d, start)
+            && iterates_in_order(e, start as usize)
+    }
+
+    quickcheck(prop as fn(Vec<i8>, Vec<u16>, Vec<i32>, Vec<i64>, Vec<usize>, i64) -> bool);
+}
+
+#[test]
+fn merge_matches_sorted_union() {
+    fn prop(a: RandomKeys, b: RandomKeys, c: RandomKeys) -> bool {
+        // Share some of the first shard's keys with the others, so that some keys overlap.
+        let shared: Vec<Key> = a.0.iter().step_by(2).cloned().collect();
+        let shards: Vec<Trie<Key, usize>> = vec![a.0, b.0, c.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, keys)| {
+                let extra = if i > 0 { shared.clone() } else { vec![] };
+                keys.into_iter().chain(extra).map(|k| (k, i)).collect()
+            })
+            .collect();
+
+        let mut oracle: BTreeMap<Vec<u8>, Vec<usize>> = BTreeMap::new();
+        for (i, shard) in shards.iter().enumerate() {
+            for key in shard.keys() {
+                oracle.entry(key.0.clone()).or_default().push(i);
+            }
+        }
+
+        let tries: Vec<&Trie<Key, usize>> = shards.iter().collect();
+        let merged: Vec<(Vec<u8>, Vec<usize>)> = merge(&tries)
+            .map(|(k, vs)| (k.0.clone(), vs.into_iter().cloned().collect()))
+            .collect();
+        merged == oracle.into_iter().collect::<Vec<_>>()
+    }
+
+    quickcheck(prop as fn(RandomKeys, RandomKeys, RandomKeys) -> bool);
+}
+
+#[test]
+fn random_scripts() {
+    fn prop(data: Vec<u8>) -> bool {
+        Trie::new().apply_script(&script_from_bytes(&data), &mut Oracle::new());
+        true
+    }
+
+    quickcheck(prop as fn(Vec<u8>) -> bool);
+}
+
+#[test]
+fn random_scripts_counted() {
+    fn prop(data: Vec<u8>) -> bool {
+        TrieBuilder::new()
+            .count_subtrees(true)
+            .build()
+            .apply_script(&script_from_bytes(&data), &mut Oracle::new());
+        true
+    }
+
+    quickcheck(prop as fn(Vec<u8>) -> bool);
+}
+
+#[test]
+fn iteration_order_matches_sorted_encodings() {
+    fn prop(RandomKeys(keys): RandomKeys) -> bool {
+        let trie = length_trie(keys.clone());
+        let mut sorted: Vec<Vec<u8>> = keys.iter().map(|k| k.encode_bytes()).collect();
+        sorted.sort();
+
+        let iter: Vec<Vec<u8>> = trie.keys().map(|k| k.0.clone()).collect();
+        let view: Vec<Vec<u8>> = trie
+            .view(&Key(vec![]))
+            .keys()
+            .map(|k| k.0.clone())
+            .collect();
+        let owned: Vec<Vec<u8>> = trie.clone().into_iter().map(|(k, _)| k.0).collect();
+        iter == sorted && view == sorted && owned == sorted
+    }
+
+    quickcheck(prop as fn(RandomKeys) -> bool);
+}
+
+#[test]
+fn interned_keys_match_owned() {
+    fn prop(RandomKeys(keys): RandomKeys) -> bool {
+        let keys: Vec<Vec<u8>> = keys.into_iter().map(|k| k.0).collect();
+        let mut owned: Trie<Vec<u8>, usize> = keys.iter().cloned().zip(0..).collect();
+        // Split the keys at one of the few values their bytes take.
+        let mut pool = KeyPool::new(0);
+        let mut interned = TrieBuilder::<InternedKey<[u8]>, usize>::new()
+            .build_interned(&mut pool, keys.iter().zip(0..))
+            .unwrap();
+
+        for key in keys.iter().step_by(2) {
+            if owned.remove(key) != interned.remove(&InternedKey::from(&key[..])) {
+                return false;
+            }
+        }
+        pool.compact();
+
+        interned.check_integrity()
+            && pool.stats().garbage == 0
+            && owned
+                .iter()
+                .map(|(k, v)| (k.clone(), v))
+                .eq(interned.iter().map(|(k, v)| (k.to_bytes(), v)))
+    }
+
+    quickcheck(prop as fn(RandomKeys) -> bool);
+}
+
+#[test]
+fn first_divergence_matches_model() {
+    fn prop(RandomKeys(keys): RandomKeys, changes: Vec<(Key, Option<usize>)>) -> bool {
+        let primary = length_trie(keys.clone());
+        let mut replica = length_trie(keys);
+        let mut oracle: BTreeMap<Vec<u8>, usize> =
+            primary.iter().map(|(k, &v)| (k.0.clone(), v)).collect();
+        let original = oracle.clone();
+        for (key, change) in changes {
+            match change {
+                Some(value) => {
+                    oracle.insert(key.0.clone(), value);
+                    replica.insert(key, value);
+                }
+                None => {
+                    oracle.remove(&key.0);
+                    replica.remove(&key);
+                }
+            }
+        }
+
+        let expected = original
+            .keys()
+            .chain(oracle.keys())
+            .filter(|&k| original.get(k) != oracle.get(k))
+            .min()
+            .map(|k| (k.clone(), original.get(k), oracle.get(k)));
+        let actual = primary
+            .first_divergence(&replica)
+            .map(|d| (d.key.0.clone(), d.left, d.right));
+        actual == expected
+    }
+
+    quickcheck(prop as fn(RandomKeys, Vec<(Key, Option<usize>)>) -> bool);
+}
@@ -0,0 +1,186 @@
+//! Reports on how much a trie saves by sharing the prefixes of its keys.
+
+use keys::compare_keys;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use trie_node::TrieNode;
+use {CompressionReport, LevelSharing, NibbleVec, SharedPrefix, Trie, TrieKey};
+
+/// The number of prefixes listed in `CompressionReport::top_prefixes`.
+const TOP_PREFIXES: usize = 10;
+
+// The most-shared prefixes found so far, as (number of keys, prefix bytes, prefix nibbles),
+// with the least-shared on top so that it can be replaced.
+type TopHeap = BinaryHeap<Reverse<(usize, Vec<u8>, usize)>>;
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey,
+{
+    /// Measure how much of the keys' encodings the trie avoids storing by sharing their
+    /// prefixes, in one traversal.
+    ///
+    /// The report compares the total length of the encoded keys with the total length of
+    /// the key fragments stored in the nodes, overall and at each depth, and lists the
+    /// prefixes with the most keys below them. Its `Display` impl formats it as a table.
+    pub fn compression_report(&self) -> CompressionReport {
+        let mut report = CompressionReport {
+            keys: 0,
+            nodes: 0,
+            flat_key_bytes: 0,
+            flat_nibbles: 0,
+            stored_nibbles: 0,
+            levels: vec![],
+            top_prefixes: vec![],
+        };
+        let mut top = BinaryHeap::with_capacity(TOP_PREFIXES + 1);
+        walk(&self.node, &NibbleVec::new(), 0, &mut report, &mut top);
+
+        report.top_prefixes = top
+            .into_iter()
+            .map(|Reverse((keys, bytes, len))| {
+                let mut prefix = NibbleVec::from_byte_vec(bytes);
+                prefix.split(len);
+                SharedPrefix { prefix, keys }
+            })
+            .collect();
+        report.top_prefixes.sort_by(|a, b| {
+            b.keys
+                .cmp(&a.keys)
+                .then_with(|| compare_keys(&a.prefix, &b.prefix))
+        });
+        report
+    }
+}
+
+// Add the node `trie`, whose full key is `path` and which is `level` nodes below the root, and
+// its descendants to the report, returning the number of keys at or below it.
+fn walk<K, V>(
+    trie: &TrieNode<K, V>,
+    path: &NibbleVec,
+    level: usize,
+    report: &mut CompressionReport,
+    top: &mut TopHeap,
+) -> usize {
+    let mut keys = 0;
+    if trie.key_value.is_some() {
+        keys += 1;
+        report.keys += 1;
+        report.flat_key_bytes += path.len().div_ceil(2);
+    }
+    for child in trie.children.iter().flatten() {
+        let child_path = path.clone().join(&child.key);
+        keys += walk(child, &child_path, level + 1, report, top);
+    }
+
+    // A leaf left empty by `SubTrieMut::remove` stores part of no key, so it's left out of
+    // the report, rather than counting as nibbles stored for nothing.
+    if keys == 0 && level > 0 {
+        return 0;
+    }
+
+    report.nodes += 1;
+    report.stored_nibbles += trie.key.len();
+    report.flat_nibbles += trie.key.len() * keys;
+    if report.levels.len() <= level {
+        report.levels.resize(level + 1, LevelSharing::default());
+    }
+    let sharing = &mut report.levels[level];
+    sharing.nodes += 1;
+    sharing.stored_nibbles += trie.key.len();
+    sharing.flat_nibbles += trie.key.len() * keys;
+
+    if level > 0 && keys > 1 {
+        top.push(Reverse((keys, path.as_bytes().to_vec(), path.len())));
+        if top.len() > TOP_PREFIXES {
+            top.pop();
+        }
+    }
+    keys
+}
+
+impl CompressionReport {
+    /// The number of nibbles of the encoded keys that the trie doesn't store, as they're
+    /// shared with other keys.
+    pub fn shared_prefix_savings(&self) -> usize {
+        self.flat_nibbles - self.stored_nibbles
+    }
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} keys in {} nodes", self.keys, self.nodes)?;
+        writeln!(
+            f,
+            "flat: {} bytes ({} nibbles), stored: {} nibbles, saved: {} nibbles ({})",
+            self.flat_key_bytes,
+            self.flat_nibbles,
+            self.stored_nibbles,
+            self.shared_prefix_savings(),
+            Percent(self.shared_prefix_savings(), self.flat_nibbles)
+        )?;
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>5} {:>8} {:>10} {:>10} {:>7}",
+            "depth", "nodes", "stored", "flat", "saved"
+        )?;
+        for (depth, level) in self.levels.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>5} {:>8} {:>10} {:>10} {:>7}",
+                depth,
+                level.nodes,
+                level.stored_nibbles,
+                level.flat_nibbles,
+                Percent(
+                    level.flat_nibbles - level.stored_nibbles,
+                    level.flat_nibbles
+                )
+                .to_string()
+            )?;
+        }
+
+        if !self.top_prefixes.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:>8}  prefix", "keys")?;
+            for shared in &self.top_prefixes {
+                writeln!(f, "{:>8}  \"{}\"", shared.keys, Escaped(&shared.prefix))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// `part` as a percentage of `whole`, or "-" if `whole` is zero.
+struct Percent(usize, usize);
+
+impl fmt::Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.1 == 0 {
+            write!(f, "-")
+        } else {
+            write!(f, "{:.1}%", 100.0 * self.0 as f64 / self.1 as f64)
+        }
+    }
+}
+
+// The bytes of an encoded prefix with non-printable bytes escaped, and a trailing half byte
+// written as `\xN_`.
+struct Escaped<'a>(&'a NibbleVec);
+
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0.as_bytes();
+        let whole = self.0.len() / 2;
+        for &byte in &bytes[..whole] {
+            write!(f, "{}", ::std::ascii::escape_default(byte))?;
+        }
+        if !self.0.len().is_multiple_of(2) {
+            write!(f, "\\x{:x}_", bytes[whole] >> 4)?;
+        }
+        Ok(())
+    }
+}
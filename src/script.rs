@@ -0,0 +1,159 @@
+//! Scripts of trie operations checked against a simple model, for fuzzing and for turning
+//! structure-sensitive bugs into regression tests.
+//!
+//! The model is a `BTreeMap` from each key's nibbles (one per byte, so that keys with an odd
+//! number of nibbles are kept exactly) to its value, which sorts in the trie's iteration
+//! order. Besides the contents, it predicts which prefixes end at a node: those equal to the
+//! longest common prefix of the keys that start with them.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use {NibbleVec, Trie, TrieCommon, TrieKey};
+
+/// One step of a script for `Trie::apply_script`.
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<K, V> {
+    /// Insert the key and value, checking the previous value.
+    Insert(K, V),
+    /// Remove the key, checking the removed value.
+    Remove(K),
+    /// Look up the key.
+    GetCheck(K),
+    /// Check whether the key has a subtrie, and the keys starting with it.
+    SubtrieCheck(K),
+    /// Remove the subtrie for the key with `Trie::remove_subtrie`, checking what's removed.
+    RemovePrefix(K),
+}
+
+/// The model of a trie used by `Trie::apply_script`.
+#[doc(hidden)]
+pub type Oracle<V> = BTreeMap<Vec<u8>, V>;
+
+/// Decode arbitrary bytes (e.g. from a fuzzer) as a script, so that every input is valid.
+///
+/// Each op is a tag byte, a key length byte and the key's bytes, then a value byte for an
+/// insert. Key bytes are mapped onto a few values that differ in either nibble, so that keys
+/// share prefixes of both even and odd lengths.
+#[doc(hidden)]
+pub fn script_from_bytes(data: &[u8]) -> Vec<Op<Vec<u8>, u8>> {
+    const KEY_BYTES: [u8; 4] = [0x00, 0x01, 0x10, 0x11];
+
+    let mut data = data.iter().cloned();
+    let mut ops = vec![];
+    while let (Some(tag), Some(len)) = (data.next(), data.next()) {
+        let key: Vec<u8> = data
+            .by_ref()
+            .take(len as usize % 8)
+            .map(|b| KEY_BYTES[b as usize % KEY_BYTES.len()])
+            .collect();
+        ops.push(match tag % 5 {
+            0 => Op::Insert(key, data.next().unwrap_or(0)),
+            1 => Op::Remove(key),
+            2 => Op::GetCheck(key),
+            3 => Op::SubtrieCheck(key),
+            _ => Op::RemovePrefix(key),
+        });
+    }
+    ops
+}
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey + Clone + Debug,
+    V: Clone + PartialEq + Debug,
+{
+    /// Apply each op to both this trie and `oracle`, a model of its contents, panicking as
+    /// soon as they disagree or the trie's structure is broken.
+    ///
+    /// The oracle should start out with the same contents as the trie, e.g. both empty.
+    #[doc(hidden)]
+    pub fn apply_script(&mut self, ops: &[Op<K, V>], oracle: &mut Oracle<V>) {
+        for (step, op) in ops.iter().enumerate() {
+            match *op {
+                Op::Insert(ref key, ref value) => {
+                    let expected = oracle.insert(nibbles(key), value.clone());
+                    let actual = self.insert(key.clone(), value.clone());
+                    assert_eq!(actual, expected, "step {}: {:?}", step, op);
+                }
+                Op::Remove(ref key) => {
+                    let expected = oracle.remove(&nibbles(key));
+                    assert_eq!(self.remove(key), expected, "step {}: {:?}", step, op);
+                }
+                Op::GetCheck(ref key) => {
+                    let expected = oracle.get(&nibbles(key));
+                    assert_eq!(self.get(key), expected, "step {}: {:?}", step, op);
+                }
+                Op::SubtrieCheck(ref key) => {
+                    let prefix = nibbles(key);
+                    let expected = with_prefix(oracle, &prefix);
+                    let actual: Vec<_> = self
+                        .view(key)
+                        .iter()
+                        .map(|(k, v)| (nibbles(k), v.clone()))
+                        .collect();
+                    assert_eq!(actual, expected, "step {}: {:?}", step, op);
+                    assert_eq!(
+                        self.subtrie(key).map(|subtrie| subtrie.len()),
+                        has_node(&expected, &prefix).then_some(expected.len()),
+                        "step {}: {:?}",
+                        step,
+                        op
+                    );
+                }
+                Op::RemovePrefix(ref key) => {
+                    let prefix = nibbles(key);
+                    let expected = with_prefix(oracle, &prefix);
+                    let removed = self.remove_subtrie(key).map(|subtrie| {
+                        subtrie
+                            .iter()
+                            .map(|(k, v)| (nibbles(k), v.clone()))
+                            .collect::<Vec<_>>()
+                    });
+                    if has_node(&expected, &prefix) {
+                        for (k, _) in &expected {
+                            oracle.remove(k);
+                        }
+                        assert_eq!(removed, Some(expected), "step {}: {:?}", step, op);
+                    } else {
+                        assert_eq!(removed, None, "step {}: {:?}", step, op);
+                    }
+                }
+            }
+            assert!(self.check_integrity(), "step {}: {:?}", step, op);
+            assert_eq!(self.len(), oracle.len(), "step {}: {:?}", step, op);
+        }
+
+        let contents: Vec<_> = self.iter().map(|(k, v)| (nibbles(k), v)).collect();
+        let expected: Vec<_> = oracle.iter().map(|(k, v)| (k.clone(), v)).collect();
+        assert_eq!(contents, expected);
+    }
+}
+
+// The nibbles of a key's encoding, one per byte.
+fn nibbles<K: ?Sized + TrieKey>(key: &K) -> Vec<u8> {
+    let nv: NibbleVec = key.encode();
+    (0..nv.len()).map(|i| nv.get(i)).collect()
+}
+
+// The entries of the oracle whose keys start with `prefix`, in order.
+fn with_prefix<V: Clone>(oracle: &Oracle<V>, prefix: &[u8]) -> Vec<(Vec<u8>, V)> {
+    oracle
+        .range(prefix.to_vec()..)
+        .take_while(|(k, _)| k.starts_with(prefix))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+// Whether a trie holding `entries`, all the keys that start with `prefix`, has a node whose key
+// is `prefix`: the root always does, and otherwise the keys' longest common prefix must be
+// `prefix` itself.
+fn has_node<V>(entries: &[(Vec<u8>, V)], prefix: &[u8]) -> bool {
+    match (entries.first(), entries.last()) {
+        _ if prefix.is_empty() => true,
+        (Some((first, _)), Some((last, _))) => {
+            first.iter().zip(last).take_while(|(a, b)| a == b).count() == prefix.len()
+        }
+        _ => false,
+    }
+}
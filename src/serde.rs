@@ -1,8 +1,8 @@
 extern crate serde;
 
 use self::serde::ser::SerializeMap;
-use self::serde::{de, Deserialize, Deserializer, Serialize, Serializer};
-use super::{Trie, TrieCommon, TrieKey};
+use self::serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use super::{KeyDisplay, NibbleVec, SubTrie, Trie, TrieCommon, TrieKey, TrieKeyDecode};
 use std::fmt::{self, Formatter};
 use std::marker::PhantomData;
 
@@ -82,10 +82,225 @@ where
     }
 }
 
+/// Serializes the subtrie as a map with full keys, exactly like a `Trie` of its own, so it can
+/// be deserialized as one. Use `SubTrie::relative_keys` for keys relative to the subtrie.
+impl<'a, K, V> Serialize for SubTrie<'a, K, V>
+where
+    K: Serialize + TrieKey,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+/// A subtrie that serializes with keys relative to its prefix, made by `SubTrie::relative_keys`.
+pub struct RelativeKeys<'a, K: 'a, V: 'a> {
+    subtrie: SubTrie<'a, K, V>,
+}
+
+impl<'a, K, V> SubTrie<'a, K, V>
+where
+    K: TrieKey,
+{
+    /// Serialize the subtrie as a map from the rest of each key's `TrieKey` encoding, after
+    /// the subtrie's prefix, to its value. The relative keys are serialized as bytes, or as
+    /// lowercase hex strings for human-readable formats like JSON, which need string keys.
+    ///
+    /// This makes the output independent of where the subtrie lives, so it can be put back
+    /// under any prefix with `Trie::extend_from_deserializer`. Serializing fails if the prefix
+    /// or any relative key isn't a whole number of bytes.
+    pub fn relative_keys(&self) -> RelativeKeys<'a, K, V> {
+        RelativeKeys {
+            subtrie: self.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Serialize for RelativeKeys<'a, K, V>
+where
+    K: TrieKey,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use self::serde::ser::Error;
+
+        let depth = self.subtrie.prefix.len();
+        if !depth.is_multiple_of(2) {
+            return Err(S::Error::custom(
+                "subtrie prefix isn't a whole number of bytes",
+            ));
+        }
+        let mut map = serializer.serialize_map(Some(self.subtrie.len()))?;
+        for (k, v) in self.subtrie.iter() {
+            let rest = k.encode().split(depth);
+            if !rest.len().is_multiple_of(2) {
+                return Err(S::Error::custom("key isn't a whole number of bytes"));
+            }
+            map.serialize_entry(&Bytes(rest.as_bytes()), v)?;
+        }
+        map.end()
+    }
+}
+
+// A key serialized as bytes, or as hex for human-readable formats.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        if serializer.is_human_readable() {
+            let mut hex = String::with_capacity(2 * self.0.len());
+            self.0.to_vec().display_key(&mut hex);
+            serializer.serialize_str(&hex)
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+// A key deserialized from bytes, from a sequence of them for formats without a byte type, or
+// from hex.
+struct ByteBuf(Vec<u8>);
+
+impl<'a> Deserialize<'a> for ByteBuf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(ByteBufVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(ByteBufVisitor)
+        }
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'a> de::Visitor<'a> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "a relative key as bytes")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'a>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(ByteBuf(bytes))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Vec::parse_key(v).map(ByteBuf).map_err(E::custom)
+    }
+}
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKeyDecode,
+{
+    /// Insert the entries of a subtrie serialized by `SubTrie::relative_keys`, under `prefix`,
+    /// returning the number of entries read.
+    ///
+    /// Each key is decoded by `TrieKeyDecode::decode` from the encoding of `prefix` followed
+    /// by the relative key, so a subtrie serialized from under one prefix is put back with
+    /// the keys it would have had if it had been under this one all along. A key that
+    /// doesn't decode is an error.
+    pub fn extend_from_deserializer<'a, D>(
+        &mut self,
+        prefix: &K,
+        deserializer: D,
+    ) -> Result<usize, D::Error>
+    where
+        D: Deserializer<'a>,
+        V: Deserialize<'a>,
+    {
+        deserializer.deserialize_map(ExtendVisitor {
+            trie: self,
+            prefix: prefix.encode(),
+        })
+    }
+}
+
+struct ExtendVisitor<'t, K: 't, V: 't> {
+    trie: &'t mut Trie<K, V>,
+    prefix: NibbleVec,
+}
+
+impl<'a, 't, K, V> de::Visitor<'a> for ExtendVisitor<'t, K, V>
+where
+    K: TrieKeyDecode,
+    V: Deserialize<'a>,
+{
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "a serialized subtrie with relative keys")
+    }
+
+    fn visit_map<M>(self, mut visitor: M) -> Result<Self::Value, M::Error>
+    where
+        M: de::MapAccess<'a>,
+    {
+        let mut count = 0;
+        while let Some((ByteBuf(rest), value)) = visitor.next_entry()? {
+            let nv = self.prefix.clone().join(&NibbleVec::from_byte_vec(rest));
+            let key = K::decode(&nv)
+                .ok_or_else(|| de::Error::custom("relative key doesn't decode under the prefix"))?;
+            self.trie.insert(key, value);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(0)
+    }
+}
+
 #[cfg(test)]
 mod test {
+    extern crate serde_json;
     extern crate serde_test;
-    use self::serde_test::Token;
+    use self::serde_test::{Configure, Token};
     use super::super::Trie;
 
     macro_rules! tests_de {
@@ -209,4 +424,98 @@ mod test {
             Token::StructEnd,
         ],
     }
+
+    fn namespaced() -> Trie<String, i32> {
+        trie!["ns/a".to_string() => 1, "ns/z".to_string() => 2, "other".to_string() => 3]
+    }
+
+    #[test]
+    fn test_ser_subtrie_full_keys() {
+        let mut trie = namespaced();
+        let tokens = &[
+            Token::Map { len: Some(2) },
+            Token::Str("ns/a"),
+            Token::I32(1),
+            Token::Str("ns/z"),
+            Token::I32(2),
+            Token::MapEnd,
+        ];
+        serde_test::assert_ser_tokens(&trie.subtrie("ns/").unwrap(), tokens);
+
+        // Deserializing the namespace gives the same trie as splitting it off.
+        let split = trie.remove_subtrie("ns/").unwrap();
+        serde_test::assert_de_tokens(&split, tokens);
+    }
+
+    #[test]
+    fn test_ser_subtrie_relative_keys() {
+        let trie = namespaced();
+        let subtrie = trie.subtrie("ns/").unwrap();
+        serde_test::assert_ser_tokens(
+            &subtrie.relative_keys().compact(),
+            &[
+                Token::Map { len: Some(2) },
+                Token::Bytes(b"a"),
+                Token::I32(1),
+                Token::Bytes(b"z"),
+                Token::I32(2),
+                Token::MapEnd,
+            ],
+        );
+        serde_test::assert_ser_tokens(
+            &subtrie.relative_keys().readable(),
+            &[
+                Token::Map { len: Some(2) },
+                Token::Str("61"),
+                Token::I32(1),
+                Token::Str("7a"),
+                Token::I32(2),
+                Token::MapEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_extend_from_deserializer() {
+        let trie = namespaced();
+        let json = serde_json::to_string(&trie.subtrie("ns/").unwrap().relative_keys()).unwrap();
+        assert_eq!(json, r#"{"61":1,"7a":2}"#);
+
+        let mut moved = trie!["other".to_string() => 3];
+        let count = moved
+            .extend_from_deserializer(
+                &"moved/".to_string(),
+                &mut serde_json::Deserializer::from_str(&json),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            moved,
+            trie!["moved/a".to_string() => 1, "moved/z".to_string() => 2, "other".to_string() => 3]
+        );
+
+        // Keys are rebuilt from their encodings, which aren't their bytes for every key type.
+        let numbers: Trie<Vec<u16>, i32> = trie![vec![1] => 0, vec![1, 2] => 1, vec![1, 300] => 2];
+        let json =
+            serde_json::to_string(&numbers.subtrie(&vec![1]).unwrap().relative_keys()).unwrap();
+        let mut moved: Trie<Vec<u16>, i32> = Trie::new();
+        moved
+            .extend_from_deserializer(&vec![7], &mut serde_json::Deserializer::from_str(&json))
+            .unwrap();
+        assert_eq!(
+            moved,
+            trie![vec![7] => 0, vec![7, 2] => 1, vec![7, 300] => 2]
+        );
+
+        // A relative key that isn't hex, or doesn't decode.
+        let mut trie = Trie::<String, i32>::new();
+        let bad_hex = &mut serde_json::Deserializer::from_str(r#"{"6":1}"#);
+        assert!(trie
+            .extend_from_deserializer(&String::new(), bad_hex)
+            .is_err());
+        let bad_utf8 = &mut serde_json::Deserializer::from_str(r#"{"ff":1}"#);
+        assert!(trie
+            .extend_from_deserializer(&String::new(), bad_utf8)
+            .is_err());
+    }
 }
@@ -0,0 +1,548 @@
+//! Order-preserving key encoding for any `Serialize` type (requires the `serde` feature).
+
+extern crate serde;
+
+use self::serde::ser::{self, Serialize};
+use keys::push_escaped;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use TrieKey;
+
+/// Key wrapper that makes any `Serialize` type usable as a trie key.
+///
+/// The value is encoded using a purpose-built serializer, which is *order-preserving*: if
+/// `a < b` according to a derived `Ord` implementation, then `a`'s encoding is
+/// lexicographically less than `b`'s, so iterating over the trie yields keys in their natural
+/// order. It's also injective: distinct values of the same type never encode the same.
+/// The format is:
+///
+/// * `bool`s are a single byte, and `char`s are a big-endian `u32`.
+/// * Integers are big-endian at their full width, with the sign bit flipped for signed
+///   types so that negative numbers sort first.
+/// * Floats are big-endian, with the sign bit flipped for positive numbers and all bits
+///   flipped for negative ones, giving the IEEE total order (`-0.0` and `0.0` are distinct).
+/// * Strings and byte strings are terminated by `00 01` with zero bytes in them escaped as
+///   `00 FF`, as in `KeyBuf`. A length prefix would make shorter strings sort first.
+/// * Sequences and maps prefix each element (or entry) with `01`, and end with `00`.
+/// * `None` is `00`, and `Some(x)` is `01` followed by `x`.
+/// * Enum variants are their variant index as a big-endian `u32`, followed by any fields.
+/// * Structs, tuples and newtypes are their fields in order, with nothing in between.
+///
+/// The encoding is only as consistent as the `Serialize` implementation: `Eq` must agree with
+/// it, and maps must serialize in a deterministic order (a `BTreeMap` does, a `HashMap`
+/// doesn't).
+///
+/// ```
+/// use radix_trie::{SerdeKey, Trie, TrieCommon};
+///
+/// let mut trie = Trie::new();
+/// trie.insert(SerdeKey((2u32, "b".to_string())), ());
+/// trie.insert(SerdeKey((1u32, "zz".to_string())), ());
+/// trie.insert(SerdeKey((2u32, "a".to_string())), ());
+///
+/// let keys: Vec<_> = trie.keys().map(|k| (k.0).1.clone()).collect();
+/// assert_eq!(keys, vec!["zz", "a", "b"]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SerdeKey<T>(pub T);
+
+impl<T> SerdeKey<T>
+where
+    T: Serialize,
+{
+    /// Encode the key, returning an error if the value's `Serialize` implementation fails.
+    ///
+    /// `TrieKey::encode_bytes` panics in that case instead.
+    pub fn try_encode_bytes(&self) -> Result<Vec<u8>, SerdeKeyError> {
+        let mut serializer = KeySerializer { output: Vec::new() };
+        self.0.serialize(&mut serializer)?;
+        Ok(serializer.output)
+    }
+}
+
+impl<T> TrieKey for SerdeKey<T>
+where
+    T: Serialize + Eq,
+{
+    fn encode_bytes(&self) -> Vec<u8> {
+        match self.try_encode_bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("failed to encode SerdeKey: {}", e),
+        }
+    }
+}
+
+/// Error raised by a value's `Serialize` implementation whilst encoding a `SerdeKey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerdeKeyError {
+    msg: String,
+}
+
+impl Display for SerdeKeyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for SerdeKeyError {}
+
+impl ser::Error for SerdeKeyError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerdeKeyError {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+const SEQ_ELEMENT: u8 = 0x01;
+const SEQ_END: u8 = 0x00;
+const NONE: u8 = 0x00;
+const SOME: u8 = 0x01;
+
+struct KeySerializer {
+    output: Vec<u8>,
+}
+
+impl KeySerializer {
+    fn push_variant(&mut self, variant_index: u32) {
+        self.output.extend_from_slice(&variant_index.to_be_bytes());
+    }
+}
+
+macro_rules! serialize_unsigned {
+    ( $( $name:ident: $t:ty ),* ) => {
+        $(
+        fn $name(self, v: $t) -> Result<(), SerdeKeyError> {
+            self.output.extend_from_slice(&v.to_be_bytes());
+            Ok(())
+        }
+        )*
+    };
+}
+
+macro_rules! serialize_signed {
+    ( $( $name:ident: $t:ty => $ut:ty ),* ) => {
+        $(
+        fn $name(self, v: $t) -> Result<(), SerdeKeyError> {
+            let flipped = (v as $ut) ^ (1 << (<$t>::BITS - 1));
+            self.output.extend_from_slice(&flipped.to_be_bytes());
+            Ok(())
+        }
+        )*
+    };
+}
+
+macro_rules! serialize_float {
+    ( $( $name:ident: $t:ty => $ut:ty ),* ) => {
+        $(
+        fn $name(self, v: $t) -> Result<(), SerdeKeyError> {
+            let bits = v.to_bits();
+            let sign = 1 << (<$ut>::BITS - 1);
+            let ordered = if bits & sign == 0 { bits ^ sign } else { !bits };
+            self.output.extend_from_slice(&ordered.to_be_bytes());
+            Ok(())
+        }
+        )*
+    };
+}
+
+impl ser::Serializer for &mut KeySerializer {
+    type Ok = ();
+    type Error = SerdeKeyError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), SerdeKeyError> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    serialize_unsigned!(
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128
+    );
+    serialize_signed!(
+        serialize_i8: i8 => u8,
+        serialize_i16: i16 => u16,
+        serialize_i32: i32 => u32,
+        serialize_i64: i64 => u64,
+        serialize_i128: i128 => u128
+    );
+    serialize_float!(serialize_f32: f32 => u32, serialize_f64: f64 => u64);
+
+    fn serialize_char(self, v: char) -> Result<(), SerdeKeyError> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), SerdeKeyError> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), SerdeKeyError> {
+        push_escaped(&mut self.output, v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), SerdeKeyError> {
+        self.output.push(NONE);
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), SerdeKeyError> {
+        self.output.push(SOME);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), SerdeKeyError> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), SerdeKeyError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), SerdeKeyError> {
+        self.push_variant(variant_index);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeKeyError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeKeyError> {
+        self.push_variant(variant_index);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, SerdeKeyError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, SerdeKeyError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, SerdeKeyError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, SerdeKeyError> {
+        self.push_variant(variant_index);
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self, SerdeKeyError> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, SerdeKeyError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, SerdeKeyError> {
+        self.push_variant(variant_index);
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for &mut KeySerializer {
+    type Ok = ();
+    type Error = SerdeKeyError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerdeKeyError> {
+        self.output.push(SEQ_ELEMENT);
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), SerdeKeyError> {
+        self.output.push(SEQ_END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut KeySerializer {
+    type Ok = ();
+    type Error = SerdeKeyError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), SerdeKeyError> {
+        self.output.push(SEQ_ELEMENT);
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerdeKeyError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), SerdeKeyError> {
+        self.output.push(SEQ_END);
+        Ok(())
+    }
+}
+
+// Fixed-length compound types are just their fields, one after another.
+macro_rules! serialize_fields {
+    ( $( $tr:ident :: $method:ident ),* ) => {
+        $(
+        impl ser::$tr for &mut KeySerializer {
+            type Ok = ();
+            type Error = SerdeKeyError;
+
+            fn $method<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerdeKeyError> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<(), SerdeKeyError> {
+                Ok(())
+            }
+        }
+        )*
+    };
+}
+
+serialize_fields!(
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+macro_rules! serialize_named_fields {
+    ( $( $tr:ident ),* ) => {
+        $(
+        impl ser::$tr for &mut KeySerializer {
+            type Ok = ();
+            type Error = SerdeKeyError;
+
+            fn serialize_field<T: ?Sized + Serialize>(
+                &mut self,
+                _key: &'static str,
+                value: &T,
+            ) -> Result<(), SerdeKeyError> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<(), SerdeKeyError> {
+                Ok(())
+            }
+        }
+        )*
+    };
+}
+
+serialize_named_fields!(SerializeStruct, SerializeStructVariant);
+
+#[cfg(test)]
+mod test {
+    use super::serde::ser::{Serialize, Serializer};
+    use super::{SerdeKey, SerdeKeyError};
+    use quickcheck::quickcheck;
+    use std::collections::BTreeMap;
+    use {Trie, TrieCommon, TrieKey};
+
+    fn enc<T: Serialize + Eq>(x: T) -> Vec<u8> {
+        SerdeKey(x).encode_bytes()
+    }
+
+    // Encoding order and equality agree with `Ord`.
+    fn order_preserved<T: Serialize + Ord + Clone>(a: T, b: T) -> bool {
+        let (ea, eb) = (enc(a.clone()), enc(b.clone()));
+        a.cmp(&b) == ea.cmp(&eb)
+    }
+
+    #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+    enum Shape {
+        Point,
+        Circle(u32),
+        Rect { w: i16, h: i16 },
+    }
+
+    // Hand-written equivalent of `#[derive(Serialize)]`.
+    impl Serialize for Shape {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            use super::serde::ser::SerializeStructVariant;
+            match *self {
+                Shape::Point => s.serialize_unit_variant("Shape", 0, "Point"),
+                Shape::Circle(r) => s.serialize_newtype_variant("Shape", 1, "Circle", &r),
+                Shape::Rect { w, h } => {
+                    let mut sv = s.serialize_struct_variant("Shape", 2, "Rect", 2)?;
+                    sv.serialize_field("w", &w)?;
+                    sv.serialize_field("h", &h)?;
+                    sv.end()
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(enc(0x0102u16), vec![1, 2]);
+        assert_eq!(enc(-1i8), vec![0x7f]);
+        assert_eq!(enc(0i8), vec![0x80]);
+        assert!(order_preserved(i64::MIN, -1));
+        assert!(order_preserved(-1i32, 1));
+        assert!(order_preserved(255u32, 256));
+        assert!(order_preserved(u128::MAX, 0));
+    }
+
+    // Floats aren't `Eq`, so can't be `TrieKey`s by themselves.
+    fn enc_f64(x: f64) -> Vec<u8> {
+        SerdeKey(x).try_encode_bytes().unwrap()
+    }
+
+    #[test]
+    fn floats_total_order() {
+        let floats = [
+            f64::NEG_INFINITY,
+            -1e10,
+            -1.0,
+            -0.0,
+            0.0,
+            1e-300,
+            1.0,
+            f64::INFINITY,
+        ];
+        for w in floats.windows(2) {
+            assert!(enc_f64(w[0]) < enc_f64(w[1]));
+        }
+    }
+
+    #[test]
+    fn strings_and_sequences() {
+        assert_eq!(enc("ab"), vec![b'a', b'b', 0, 1]);
+        assert_eq!(enc("a\u{0}"), vec![b'a', 0, 0xff, 0, 1]);
+        assert!(order_preserved("b", "aa"));
+        assert!(order_preserved("a", "a\u{0}"));
+        assert!(order_preserved(vec![1u8, 2], vec![1u8, 2, 0]));
+        assert!(order_preserved(vec![2u8], vec![1u8, 2, 3]));
+        assert!(order_preserved(Vec::<u8>::new(), vec![0u8]));
+        assert!(order_preserved(vec!["a", ""], vec!["a"]));
+    }
+
+    #[test]
+    fn options_and_enums() {
+        assert!(order_preserved(None, Some(0u8)));
+        assert!(order_preserved(Some(1u8), Some(0u8)));
+        let shapes = vec![
+            Shape::Point,
+            Shape::Circle(0),
+            Shape::Circle(7),
+            Shape::Rect { w: -5, h: 9 },
+            Shape::Rect { w: 3, h: -2 },
+            Shape::Rect { w: 3, h: 2 },
+        ];
+        for a in &shapes {
+            for b in &shapes {
+                assert!(order_preserved(a.clone(), b.clone()), "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn maps() {
+        let mut a = BTreeMap::new();
+        a.insert("x".to_string(), 1u8);
+        let mut b = a.clone();
+        b.insert("y".to_string(), 0u8);
+        assert!(enc(a.clone()) < enc(b.clone()));
+        assert_ne!(enc(a), enc(b));
+    }
+
+    #[test]
+    fn serialize_error() {
+        struct Broken;
+        impl Serialize for Broken {
+            fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(super::serde::ser::Error::custom("nope"))
+            }
+        }
+        let err: SerdeKeyError = SerdeKey(Broken).try_encode_bytes().unwrap_err();
+        assert_eq!(err.to_string(), "nope");
+    }
+
+    #[test]
+    fn trie_iteration_order() {
+        let mut keys = vec![];
+        for &a in &[-300i32, -1, 0, 5, 70_000] {
+            for b in &["", "a", "a\u{0}", "ab", "b"] {
+                for &c in &[None, Some(false), Some(true)] {
+                    keys.push((a, b.to_string(), c));
+                }
+            }
+        }
+        let mut trie = Trie::new();
+        for k in keys.iter().rev() {
+            trie.insert(SerdeKey(k.clone()), ());
+        }
+        assert!(trie.check_integrity());
+        let observed: Vec<_> = trie.keys().map(|k| k.0.clone()).collect();
+        keys.sort();
+        assert_eq!(observed, keys);
+    }
+
+    #[test]
+    fn tuple_order_property() {
+        fn prop(a: (i16, String, u8), b: (i16, String, u8)) -> bool {
+            order_preserved(a, b)
+        }
+        quickcheck(prop as fn((i16, String, u8), (i16, String, u8)) -> bool);
+    }
+
+    #[test]
+    fn nested_order_property() {
+        fn prop(a: (Vec<String>, Option<i64>), b: (Vec<String>, Option<i64>)) -> bool {
+            order_preserved(a, b)
+        }
+        quickcheck(prop as fn((Vec<String>, Option<i64>), (Vec<String>, Option<i64>)) -> bool);
+    }
+
+    #[test]
+    fn injectivity_property() {
+        fn prop(a: (Vec<u8>, String), b: (Vec<u8>, String)) -> bool {
+            (a == b) == (enc(a.clone()) == enc(b.clone()))
+        }
+        quickcheck(prop as fn((Vec<u8>, String), (Vec<u8>, String)) -> bool);
+    }
+}
@@ -0,0 +1,343 @@
+//! A trie split into independently locked shards, for concurrent use (requires the
+//! `concurrent` feature).
+
+use iter::Iter;
+use std::borrow::Borrow;
+use std::ops::Range;
+use std::slice;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::vec;
+use traversal::DescendantResult::*;
+use {NibbleVec, Trie, TrieCommon, TrieKey};
+
+const POISONED: &str = "ShardedTrie shard lock poisoned by a panic";
+
+/// A trie that can be read and written from many threads at once.
+///
+/// The keys are split between a number of shards by the first byte of their encoding, and
+/// each shard is an ordinary `Trie` behind its own `RwLock`. Shards cover contiguous ranges of
+/// first bytes in order, so iterating over the shards one after the other visits keys in the
+/// same order as a single trie would, and a prefix only involves the shards for its first
+/// byte (or all the shards for an empty prefix).
+///
+/// The operations on a single key only lock that key's shard. Operations that cover many
+/// shards lock them one at a time, so they don't see a consistent snapshot of the whole trie
+/// if it's changed concurrently, but each shard is seen in a consistent state.
+pub struct ShardedTrie<K, V> {
+    shards: Vec<RwLock<Trie<K, V>>>,
+}
+
+impl<K, V> ShardedTrie<K, V>
+where
+    K: TrieKey,
+{
+    /// Create an empty trie with the given number of shards.
+    ///
+    /// **Panics** unless `shards` is between 1 and 256, as keys are sharded by byte.
+    pub fn new(shards: usize) -> ShardedTrie<K, V> {
+        assert!(
+            shards > 0 && shards <= 256,
+            "number of shards must be between 1 and 256, not {}",
+            shards
+        );
+        ShardedTrie {
+            shards: (0..shards).map(|_| RwLock::new(Trie::new())).collect(),
+        }
+    }
+
+    /// The number of shards.
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Insert the given key-value pair, returning any previous value associated with the key.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let shard = self.shard_for(&key.encode());
+        self.write(shard).insert(key, value)
+    }
+
+    /// Fetch a copy of the value for the given key, if any.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        V: Clone,
+    {
+        self.with_value(key, V::clone)
+    }
+
+    /// Call `f` on the value for the given key, if any, while its shard is locked for reading.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn with_value<Q, F, R>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&V) -> R,
+    {
+        let shard = self.shard_for(&key.encode());
+        self.read(shard).get(key).map(f)
+    }
+
+    /// Remove the value associated with the given key.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let shard = self.shard_for(&key.encode());
+        self.write(shard).remove(key)
+    }
+
+    /// Number of key/value pairs stored in the trie, summed over the shards.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|shard| self.read(shard).len())
+            .sum()
+    }
+
+    /// Determine if the trie contains 0 key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|shard| self.read(shard).is_empty())
+    }
+
+    /// Return an iterator over copies of the keys and values, in the same order as a single
+    /// trie.
+    ///
+    /// Each shard is copied while it's locked for reading, when the iterator reaches it.
+    pub fn iter(&self) -> SnapshotIter<'_, K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        SnapshotIter::new(&self.shards, NibbleVec::new())
+    }
+
+    /// Return an iterator over copies of the keys that start with `prefix` and their values,
+    /// in the same order as a single trie.
+    ///
+    /// Only the shards that can hold such keys are visited, and each is copied while it's
+    /// locked for reading, when the iterator reaches it.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn iter_prefix<Q>(&self, prefix: &Q) -> SnapshotIter<'_, K, V>
+    where
+        K: Borrow<Q> + Clone,
+        Q: ?Sized + TrieKey,
+        V: Clone,
+    {
+        let prefix = prefix.encode();
+        let shards = &self.shards[self.shard_range(&prefix)];
+        SnapshotIter::new(shards, prefix)
+    }
+
+    /// Count the keys that start with `prefix`, visiting only the shards that can hold them.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn count_prefix<Q>(&self, prefix: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.shard_range(&prefix.encode())
+            .map(|shard| self.read(shard).view(prefix).len())
+            .sum()
+    }
+
+    /// Merge the shards into a single trie.
+    pub fn into_trie(self) -> Trie<K, V> {
+        let mut trie = Trie::new();
+        for shard in self.shards {
+            let shard = shard.into_inner().expect(POISONED);
+            trie.extend(shard);
+        }
+        trie
+    }
+
+    // The shard for a key, by its first byte. A key with only one nibble goes with the keys
+    // that have a zero second nibble, which come just after it, and the empty key goes first.
+    fn shard_for(&self, nv: &NibbleVec) -> usize {
+        self.shard_range(nv).start
+    }
+
+    // The range of shards holding the keys that start with `nv`.
+    fn shard_range(&self, nv: &NibbleVec) -> Range<usize> {
+        let (first, last) = match nv.len() {
+            0 => (0x00, 0xff),
+            1 => (nv.get(0) << 4, nv.get(0) << 4 | 0x0f),
+            _ => (nv.as_bytes()[0], nv.as_bytes()[0]),
+        };
+        let shard = |byte: u8| usize::from(byte) * self.shards.len() / 256;
+        shard(first)..shard(last) + 1
+    }
+
+    // A shard whose lock was poisoned by a panic part way through a change may be left in an
+    // inconsistent state, so the panic is passed on.
+    fn read(&self, shard: usize) -> RwLockReadGuard<'_, Trie<K, V>> {
+        self.shards[shard].read().expect(POISONED)
+    }
+
+    fn write(&self, shard: usize) -> RwLockWriteGuard<'_, Trie<K, V>> {
+        self.shards[shard].write().expect(POISONED)
+    }
+}
+
+/// Iterator over copies of the keys and values of a `ShardedTrie`, one shard at a time.
+pub struct SnapshotIter<'a, K: 'a, V: 'a> {
+    shards: slice::Iter<'a, RwLock<Trie<K, V>>>,
+    prefix: NibbleVec,
+    current: vec::IntoIter<(K, V)>,
+}
+
+impl<'a, K, V> SnapshotIter<'a, K, V> {
+    fn new(shards: &'a [RwLock<Trie<K, V>>], prefix: NibbleVec) -> SnapshotIter<'a, K, V> {
+        SnapshotIter {
+            shards: shards.iter(),
+            prefix,
+            current: vec![].into_iter(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for SnapshotIter<'a, K, V>
+where
+    K: TrieKey + Clone,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(item) = self.current.next() {
+                return Some(item);
+            }
+            let shard = self.shards.next()?;
+            let trie = shard.read().expect(POISONED);
+            let items: Vec<_> = match trie.node.get_raw_descendant(&self.prefix) {
+                Some(NoModification(node)) | Some(ExtendKey(node, _, _)) => Iter::new(node)
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                None => vec![],
+            };
+            self.current = items.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShardedTrie;
+    use std::sync::Arc;
+    use std::thread;
+    use {Trie, TrieCommon};
+
+    const THREADS: u8 = 8;
+    const KEYS_PER_THREAD: u32 = 2000;
+
+    // The operations for one thread, as keys to insert a value for or (given `None`) remove:
+    // its own keys, spread over every shard, a third of which it removes again, plus a set of
+    // keys shared with the other threads (always with the same value, so the order the
+    // threads run in doesn't matter).
+    fn ops(thread: u8) -> Vec<(Vec<u8>, Option<u32>)> {
+        let mut ops = vec![];
+        for i in 0..KEYS_PER_THREAD {
+            let key = vec![(i * 37) as u8, thread, (i >> 8) as u8, i as u8];
+            ops.push((key.clone(), Some(i)));
+            if i % 3 == 0 {
+                ops.push((key, None));
+            }
+            ops.push((vec![i as u8], Some(i % 256)));
+        }
+        ops
+    }
+
+    #[test]
+    fn stress_matches_single_threaded() {
+        let sharded = Arc::new(ShardedTrie::new(16));
+        let mut handles = vec![];
+        for thread in 0..THREADS {
+            let sharded = sharded.clone();
+            handles.push(thread::spawn(move || {
+                for (key, value) in ops(thread) {
+                    match value {
+                        Some(value) => sharded.insert(key, value),
+                        None => sharded.remove(&key),
+                    };
+                }
+            }));
+        }
+        // Read while the writers are running: snapshots are still in order.
+        for _ in 0..20 {
+            let keys: Vec<_> = sharded.iter().map(|(k, _)| k).collect();
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(sharded.get(&vec![0u8]).is_none_or(|v| v == 0));
+            sharded.count_prefix(&vec![37u8]);
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut oracle = Trie::new();
+        for thread in 0..THREADS {
+            for (key, value) in ops(thread) {
+                match value {
+                    Some(value) => oracle.insert(key, value),
+                    None => oracle.remove(&key),
+                };
+            }
+        }
+
+        assert_eq!(sharded.len(), oracle.len());
+        let snapshot: Vec<_> = sharded.iter().collect();
+        let expected: Vec<_> = oracle.iter().map(|(k, &v)| (k.clone(), v)).collect();
+        assert_eq!(snapshot, expected);
+        for (key, value) in oracle.iter() {
+            assert_eq!(sharded.get(key), Some(*value));
+        }
+
+        let sharded = Arc::try_unwrap(sharded).ok().unwrap();
+        let trie = sharded.into_trie();
+        assert!(trie.check_integrity());
+        assert_eq!(trie, oracle);
+    }
+
+    #[test]
+    fn prefixes() {
+        let sharded = ShardedTrie::new(7);
+        for &key in &["", "a", "ab", "abc", "b", "bc", "\u{7f}", "zz"] {
+            sharded.insert(key.to_string(), key.len());
+        }
+        let keys =
+            |prefix: &str| -> Vec<String> { sharded.iter_prefix(prefix).map(|(k, _)| k).collect() };
+        assert_eq!(keys("a"), vec!["a", "ab", "abc"]);
+        assert_eq!(keys("ab"), vec!["ab", "abc"]);
+        assert_eq!(keys("abcd"), Vec::<String>::new());
+        assert_eq!(keys("").len(), 8);
+        assert_eq!(sharded.count_prefix("b"), 2);
+        assert_eq!(sharded.count_prefix(""), 8);
+        assert_eq!(sharded.count_prefix("q"), 0);
+        assert_eq!(sharded.remove("ab"), Some(2));
+        assert_eq!(sharded.with_value("abc", |v| v + 1), Some(4));
+        assert!(!sharded.is_empty());
+
+        // Encodings of one nibble take their shards from the range of a whole byte.
+        let nibbles: ShardedTrie<Vec<u8>, ()> = ShardedTrie::new(256);
+        assert_eq!(
+            nibbles.shard_range(&::NibbleVec::from_byte_vec(vec![0x35])),
+            0x35..0x36
+        );
+        let mut half = ::NibbleVec::new();
+        half.push(0x3);
+        assert_eq!(nibbles.shard_range(&half), 0x30..0x40);
+        assert_eq!(nibbles.shard_range(&::NibbleVec::new()), 0..256);
+    }
+}
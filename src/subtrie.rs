@@ -1,7 +1,9 @@
+use iter::{Iter, RelativeIter};
 use keys::*;
 use std::borrow::Borrow;
+use std::fmt;
 use trie_node::TrieNode;
-use {NibbleVec, SubTrie, SubTrieMut, SubTrieResult};
+use {NibbleVec, SubTrie, SubTrieMut, SubTrieResult, Trie};
 
 impl<'a, K, V> SubTrie<'a, K, V>
 where
@@ -18,6 +20,129 @@ where
     {
         subtrie_get(&self.prefix, self.node, key)
     }
+
+    /// Call `f` on the value for the given key, which should be an extension of this
+    /// subtrie's key, and return its result.
+    ///
+    /// See `Trie::with_value`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    #[allow(clippy::result_unit_err)]
+    pub fn with_value<Q, F, R>(&self, key: &Q, f: F) -> SubTrieResult<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&V) -> R,
+    {
+        self.get(key).map(|value| value.map(f))
+    }
+
+    /// Call `f` on the subtrie for the given key, which should be an extension of this
+    /// subtrie's key, and return its result.
+    ///
+    /// See `Trie::with_value` and `Trie::subtrie`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    #[allow(clippy::result_unit_err)]
+    pub fn with_subtrie<Q, F, R>(&self, prefix: &Q, f: F) -> SubTrieResult<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(SubTrie<'_, K, V>) -> R,
+    {
+        subtrie_descendant(&self.prefix, self.node, prefix).map(|subtrie| subtrie.map(f))
+    }
+
+    /// Copy this subtrie into a new, independent trie.
+    ///
+    /// Keys keep their full form, so they can be looked up in the new trie just as in the
+    /// original. The nodes are cloned directly, without re-encoding or re-inserting any keys.
+    pub fn to_trie(&self) -> Trie<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.node.clone().into_trie(self.prefix.clone())
+    }
+
+    /// Return an iterator over the keys and values of this subtrie, with each key rebuilt
+    /// from the rest of its encoding after this subtrie's prefix.
+    ///
+    /// A relative key is `None` if it doesn't decode, e.g. if it starts part way through a
+    /// byte, or (for `String` keys) part way through a character.
+    pub fn iter_relative(&self) -> RelativeIter<'a, K, V>
+    where
+        K: TrieKeyDecode,
+    {
+        RelativeIter::new(self.prefix.len(), Iter::new(self.node))
+    }
+
+    /// Get the child subtrie whose key continues with `nibble` (`0..16`) after this one's.
+    ///
+    /// This steps down one node, not one nibble: the child's `prefix()` is the whole of its
+    /// key fragment, which starts with `nibble` and may carry on for any number of nibbles.
+    pub fn child(&self, nibble: u8) -> Option<SubTrie<'a, K, V>> {
+        let child = self.node.children.get(nibble as usize)?.as_ref()?;
+        Some(child.as_subtrie(self.prefix.clone().join(&child.key)))
+    }
+
+    /// Number of child subtries of this subtrie.
+    pub fn child_count(&self) -> usize {
+        self.node.child_count
+    }
+
+    /// Find the longest key in this subtrie that is a prefix of `key`, along with its value.
+    ///
+    /// Unlike `Trie::get_ancestor`, this never finds a key above the subtrie's root: if no
+    /// key at or below the root is a prefix of `key` (including when `key` doesn't start
+    /// with the subtrie's prefix), the result is `None`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_ancestor_within<Q>(&self, key: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        subtrie_get_ancestor(&self.prefix, self.node, key)
+    }
+
+    /// Find the value for the longest key in this subtrie that is a prefix of `key`.
+    ///
+    /// See `get_ancestor_within`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_ancestor_value_within<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.get_ancestor_within(key).map(|(_, value)| value)
+    }
+}
+
+impl<'a, K, V> Clone for SubTrie<'a, K, V> {
+    fn clone(&self) -> Self {
+        SubTrie {
+            prefix: self.prefix.clone(),
+            node: self.node,
+            length: self.length,
+        }
+    }
+}
+
+/// Formats the subtrie's contents as a map, in iteration order.
+impl<'a, K, V> fmt::Debug for SubTrie<'a, K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(Iter::new(self.node)).finish()
+    }
 }
 
 fn subtrie_get<'a, K, Q: ?Sized, V>(
@@ -40,13 +165,163 @@ where
     }
 }
 
+fn subtrie_get_mut<'a, K, Q, V>(
+    prefix: &NibbleVec,
+    node: &'a mut TrieNode<K, V>,
+    key: &Q,
+) -> SubTrieResult<&'a mut V>
+where
+    K: TrieKey + Borrow<Q>,
+    Q: ?Sized + TrieKey,
+{
+    let key_enc = key.encode();
+    match match_keys(0, prefix, &key_enc) {
+        KeyMatch::Full => Ok(node.value_mut()),
+        KeyMatch::FirstPrefix => Ok(node
+            .get_mut(&stripped(key_enc, prefix))
+            .and_then(TrieNode::value_mut)),
+        _ => Err(()),
+    }
+}
+
+// Find the node at or below `node`, whose key is `prefix`, for the key `key`, as a subtrie.
+fn subtrie_descendant<'a, K, Q, V>(
+    prefix: &NibbleVec,
+    node: &'a TrieNode<K, V>,
+    key: &Q,
+) -> SubTrieResult<SubTrie<'a, K, V>>
+where
+    K: TrieKey + Borrow<Q>,
+    Q: ?Sized + TrieKey,
+{
+    let key_enc = key.encode();
+    match match_keys(0, prefix, &key_enc) {
+        KeyMatch::Full => Ok(Some(node.as_subtrie(key_enc))),
+        KeyMatch::FirstPrefix => Ok(node
+            .get(&stripped(key_enc.clone(), prefix))
+            .map(|node| node.as_subtrie(key_enc))),
+        _ => Err(()),
+    }
+}
+
+// Find the deepest node with a value at or below `node`, whose key is `prefix`, along the
+// path to `key`.
+fn subtrie_get_ancestor<'a, K, Q, V>(
+    prefix: &NibbleVec,
+    node: &'a TrieNode<K, V>,
+    key: &Q,
+) -> Option<(&'a K, &'a V)>
+where
+    K: TrieKey + Borrow<Q>,
+    Q: ?Sized + TrieKey,
+{
+    let key_enc = key.encode();
+    let ancestor = match match_keys(0, prefix, &key_enc) {
+        KeyMatch::Full => node.as_value_node(),
+        KeyMatch::FirstPrefix => node
+            .get_ancestor(&stripped(key_enc, prefix))
+            .map(|(node, _)| node),
+        _ => None,
+    };
+    ancestor
+        .and_then(|node| node.key_value.as_ref())
+        .map(|kv| (&kv.key, &kv.value))
+}
+
 impl<'a, K, V> SubTrieMut<'a, K, V>
 where
     K: TrieKey,
 {
+    fn node(&self) -> &TrieNode<K, V> {
+        self.parent.child_or_self(self.bucket)
+    }
+
+    fn node_mut(&mut self) -> &mut TrieNode<K, V> {
+        self.parent.child_or_self_mut(self.bucket)
+    }
+
+    // Whether a key belongs in this subtrie, i.e. starts with the prefix it was created for.
+    fn covers(&self, key_enc: &NibbleVec) -> bool {
+        match match_keys(0, &self.prefix, key_enc) {
+            KeyMatch::Full | KeyMatch::FirstPrefix => true,
+            KeyMatch::SecondPrefix => key_enc.len() >= self.scope,
+            KeyMatch::Partial(common) => common >= self.scope,
+        }
+    }
+
+    // The result for a key that doesn't lead to the node: nothing if the key belongs in this
+    // subtrie anyway, which it can once the node has been merged with its child.
+    fn outside<Q, T>(&self, key: &Q) -> SubTrieResult<T>
+    where
+        Q: ?Sized + TrieKey,
+    {
+        if self.covers(&key.encode()) {
+            Ok(None)
+        } else {
+            Err(())
+        }
+    }
+
+    // The encoding of a key that continues with `suffix` after the prefix this subtrie was
+    // created for.
+    fn relative(&self, suffix: &[u8]) -> NibbleVec {
+        let mut nv = self.prefix.clone();
+        nv.split(self.scope);
+        nv.join(&NibbleVec::from_byte_vec(suffix.to_vec()))
+    }
+
+    // Update `prefix` after the node below the parent's key (of length `depth`) was replaced.
+    fn reset_prefix(&mut self, depth: usize) {
+        let mut prefix = self.prefix.clone();
+        prefix.split(depth);
+        self.prefix = prefix.join(&self.node().key);
+    }
+
+    // Merge the node with its only child if it's been left with neither a value nor a second
+    // child, so that the whole trie stays valid while the subtrie is in use.
+    fn merge_node(&mut self) {
+        let depth = self.prefix.len() - self.node().key.len();
+        self.node_mut().merge_single_child();
+        self.reset_prefix(depth);
+    }
+
     /// Mutable reference to the node's value.
     pub fn value_mut(&mut self) -> Option<&mut V> {
-        self.node.value_mut()
+        self.node_mut().value_mut()
+    }
+
+    /// Get a mutable reference to the node's value, first inserting the result of `f` if it
+    /// has none.
+    ///
+    /// The key for a new value is decoded from the node's prefix, so this is `None` if no key
+    /// encodes to it, e.g. if it ends part way through a byte.
+    pub fn value_or_insert_with<F>(&mut self, f: F) -> Option<&mut V>
+    where
+        K: TrieKeyDecode,
+        F: FnOnce() -> V,
+    {
+        if self.node().key_value.is_none() {
+            let key = K::decode(&self.prefix)?;
+            self.node_mut().add_key_value(key, f());
+            *self.length += 1;
+        }
+        self.node_mut().value_mut()
+    }
+
+    /// Get the child subtrie whose key continues with `nibble` (`0..16`) after this one's.
+    ///
+    /// This steps down one node, not one nibble, as for `SubTrie::child`.
+    pub fn child(&mut self, nibble: u8) -> Option<SubTrieMut<'_, K, V>> {
+        let node = self.parent.child_or_self_mut(self.bucket);
+        let bucket = nibble as usize;
+        let child = node.children.get(bucket)?.as_ref()?;
+        let prefix = self.prefix.clone().join(&child.key);
+        Some(node.as_subtrie_mut(Some(bucket), prefix, self.length))
+    }
+
+    /// Number of child subtries of this subtrie.
+    pub fn child_count(&self) -> usize {
+        self.node().child_count
     }
 
     /// Look up the value for the given key, which should be an extension of this subtrie's key.
@@ -58,17 +333,116 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        subtrie_get(&self.prefix, &*self.node, key)
+        subtrie_get(&self.prefix, self.node(), key).or_else(|()| self.outside(key))
+    }
+
+    /// Find the longest key in this subtrie that is a prefix of `key`, along with its value.
+    ///
+    /// See `SubTrie::get_ancestor_within`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_ancestor_within<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        subtrie_get_ancestor(&self.prefix, self.node(), key)
+    }
+
+    /// Find the value for the longest key in this subtrie that is a prefix of `key`.
+    ///
+    /// See `SubTrie::get_ancestor_within`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_ancestor_value_within<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.get_ancestor_within(key).map(|(_, value)| value)
+    }
+
+    /// Call `f` on the value for the given key, which should be an extension of this
+    /// subtrie's key, and return its result.
+    ///
+    /// See `Trie::with_value`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    #[allow(clippy::result_unit_err)]
+    pub fn with_value<Q, F, R>(&self, key: &Q, f: F) -> SubTrieResult<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&V) -> R,
+    {
+        self.get(key).map(|value| value.map(f))
+    }
+
+    /// Call `f` on a mutable reference to the value for the given key, which should be an
+    /// extension of this subtrie's key, and return its result.
+    ///
+    /// See `Trie::with_value`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    #[allow(clippy::result_unit_err)]
+    pub fn with_value_mut<Q, F, R>(&mut self, key: &Q, f: F) -> SubTrieResult<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&mut V) -> R,
+    {
+        let outside = self.outside(key);
+        let prefix = &self.prefix;
+        let node = self.parent.child_or_self_mut(self.bucket);
+        subtrie_get_mut(prefix, node, key)
+            .or(outside)
+            .map(|value| value.map(f))
+    }
+
+    /// Call `f` on the subtrie for the given key, which should be an extension of this
+    /// subtrie's key, and return its result.
+    ///
+    /// See `Trie::with_value` and `Trie::subtrie`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    #[allow(clippy::result_unit_err)]
+    pub fn with_subtrie<Q, F, R>(&self, prefix: &Q, f: F) -> SubTrieResult<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(SubTrie<'_, K, V>) -> R,
+    {
+        subtrie_descendant(&self.prefix, self.node(), prefix)
+            .or_else(|()| self.outside(prefix))
+            .map(|subtrie| subtrie.map(f))
     }
 
     /// Insert a value in this subtrie. The key should be an extension of this subtrie's key.
     pub fn insert(&mut self, key: K, value: V) -> SubTrieResult<V> {
         let key_enc = key.encode();
         let previous = match match_keys(0, &self.prefix, &key_enc) {
-            KeyMatch::Full => self.node.replace_value(key, value),
-            KeyMatch::FirstPrefix => self
-                .node
-                .insert(key, value, stripped(key_enc, &self.prefix)),
+            KeyMatch::Full => self.node_mut().replace_value(key, value),
+            KeyMatch::FirstPrefix => {
+                let nv = stripped(key_enc, &self.prefix);
+                let previous = self.node_mut().insert(key, value, nv);
+                // A node left empty by `remove` now has a single child.
+                self.merge_node();
+                previous
+            }
+            // The key leaves the node's key after the prefix the subtrie was created for, so
+            // the node is split from its parent, which is never the case for the root.
+            _ if self.covers(&key_enc) => {
+                let depth = self.prefix.len() - self.node().key.len();
+                let mut key_enc = key_enc;
+                let previous = self.parent.insert(key, value, key_enc.split(depth));
+                self.reset_prefix(depth);
+                previous
+            }
             _ => {
                 return Err(());
             }
@@ -81,8 +455,26 @@ where
         Ok(previous)
     }
 
+    /// Insert a value in this subtrie, for a key whose encoding continues with the bytes of
+    /// `suffix` after the prefix this subtrie was created for.
+    ///
+    /// The key is stored as given, and must encode to the same nibbles, or `Err` is returned.
+    #[allow(clippy::result_unit_err)]
+    pub fn insert_relative(&mut self, suffix: &[u8], key: K, value: V) -> SubTrieResult<V> {
+        if key.encode() != self.relative(suffix) {
+            return Err(());
+        }
+        self.insert(key, value)
+    }
+
     /// Remove a value from this subtrie. The key should be an extension of this subtrie's key.
     ///
+    /// Removing a value can leave the subtrie's node without a value and with a single child,
+    /// in which case they're merged, so that `prefix()` grows longer. The subtrie still covers
+    /// every key that starts with the prefix it was created for. If the subtrie is left empty,
+    /// its node stays in the trie, empty, so that it can still be inserted into; use
+    /// `remove_value` or `prune` to remove it as well.
+    ///
     /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
     /// form *must* match those for the key type
     pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> SubTrieResult<V>
@@ -92,8 +484,11 @@ where
     {
         let key_enc = key.encode();
         let removed = match match_keys(0, &self.prefix, &key_enc) {
-            KeyMatch::Full => self.node.take_value(key),
-            KeyMatch::FirstPrefix => self.node.remove(key),
+            KeyMatch::Full | KeyMatch::FirstPrefix => {
+                let nv = stripped(key_enc, &self.prefix);
+                self.node_mut().remove_relative(&nv, key)
+            }
+            _ if self.covers(&key_enc) => None,
             _ => {
                 return Err(());
             }
@@ -101,10 +496,79 @@ where
 
         if removed.is_some() {
             *self.length -= 1;
+            self.merge_node();
         }
 
         Ok(removed)
     }
+
+    /// Remove the value for the key whose encoding continues with the bytes of `suffix` after
+    /// the prefix this subtrie was created for, whatever the key itself.
+    ///
+    /// See `remove` for how this can change the subtrie's node.
+    pub fn remove_relative(&mut self, suffix: &[u8]) -> Option<V> {
+        let nv = self.relative(suffix);
+        let removed = match match_keys(0, &self.prefix, &nv) {
+            KeyMatch::Full | KeyMatch::FirstPrefix => {
+                let nv = stripped(nv, &self.prefix);
+                self.node_mut().remove_nibbles(&nv)
+            }
+            _ => None,
+        };
+
+        if removed.is_some() {
+            *self.length -= 1;
+            self.merge_node();
+        }
+
+        removed
+    }
+
+    /// Remove this subtrie's value, keeping everything below it.
+    ///
+    /// This consumes the subtrie because its node may not survive: a node left without a
+    /// value is merged into its only child, or removed if it has no children.
+    pub fn remove_value(self) -> Option<V> {
+        let removed = match self.bucket {
+            Some(bucket) => self.parent.take_child_value(bucket),
+            None => self.parent.key_value.take().map(|kv| kv.value),
+        };
+
+        if removed.is_some() {
+            *self.length -= 1;
+        }
+
+        removed
+    }
+
+    /// Remove this subtrie's value and everything below it from the trie.
+    ///
+    /// Return the number of values removed.
+    pub fn prune(self) -> usize {
+        let removed = match self.bucket {
+            Some(bucket) => self.parent.prune_child(bucket),
+            None => {
+                let removed = self.parent.compute_size();
+                *self.parent = TrieNode::new();
+                removed
+            }
+        };
+
+        *self.length -= removed;
+        removed
+    }
+}
+
+/// Formats the subtrie's contents as a map, in iteration order.
+impl<'a, K, V> fmt::Debug for SubTrieMut<'a, K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let node = self.parent.child_or_self(self.bucket);
+        f.debug_map().entries(Iter::new(node)).finish()
+    }
 }
 
 fn stripped(mut key: NibbleVec, prefix: &NibbleVec) -> NibbleVec {
@@ -1,7 +1,13 @@
-use keys::TrieKey;
+use keys::{
+    compare_keys, match_keys, ByteKey, KeyBuf, KeyMatch, OrderedTrieKey, TrieKey, TrieKeyDecode,
+};
 use std::collections::HashSet;
 use std::iter::FromIterator;
-use {Trie, TrieCommon};
+use {
+    is_prefix_of, key_relationship, merge, Aggregate, AugmentedTrie, BranchSummary, BuildError,
+    CollisionPolicy, Divergence, ExtendPolicy, ExtendStats, InternedKey, KeyPool, KeyPoolStats,
+    NibbleVec, PrefixConflict, Trie, TrieBuilder, TrieCommon,
+};
 
 const TEST_DATA: [(&'static str, u32); 7] = [
     ("abcdefgh", 19),
@@ -31,6 +37,24 @@ fn get_nonexistant() {
     assert!(trie.get(&"").is_none());
 }
 
+#[test]
+fn contains_key() {
+    let trie = test_trie();
+    for &(key, _) in TEST_DATA.iter() {
+        assert!(trie.contains_key(&key));
+    }
+    assert!(!trie.contains_key(&"abc"));
+    assert!(!trie.contains_key(&"abcdefghi"));
+    assert!(!trie.contains_key(&""));
+
+    let strings: Trie<String, ()> = TEST_DATA
+        .iter()
+        .map(|&(k, _)| (k.to_string(), ()))
+        .collect();
+    assert!(strings.contains_key("acbdef"));
+    assert!(!strings.contains_key("acb"));
+}
+
 #[test]
 fn subtrie_nonexistant() {
     let trie = test_trie();
@@ -329,6 +353,68 @@ fn subtrie_mut_lifetime() {
     assert_eq!(*subtrie_value.unwrap(), 999);
 }
 
+#[test]
+fn child_by_nibble() {
+    let mut trie = test_trie();
+    // 'a' is 0x61 and 'b' is 0x62, so the root has a single child, for nibble 6.
+    assert_eq!(trie.child_count(), 1);
+    assert!(trie.child(5).is_none());
+    assert!(trie.child(16).is_none());
+    let six = trie.child(6).unwrap();
+    assert_eq!((six.prefix().len(), six.prefix().get(0)), (1, 6));
+    assert_eq!(six.child_count(), 2);
+
+    // One step covers the whole edge from "a" down to "ab".
+    let a = six.child(1).unwrap();
+    assert_eq!(a.key(), Some(&"a"));
+    let ab = a.child(6).unwrap().child(2).unwrap();
+    assert_eq!(ab.key(), Some(&"ab"));
+    assert_eq!(ab.prefix().len(), 1);
+    let abcd = ab.child(6).unwrap();
+    assert_eq!(abcd.key(), Some(&"abcd"));
+    assert_eq!(abcd.prefix().len(), 4);
+    assert_eq!(abcd.child_count(), 1);
+
+    let mut sub = trie.subtrie_mut(&"abcd").unwrap();
+    assert_eq!(sub.child_count(), 1);
+    *sub.child(6).unwrap().value_mut().unwrap() = 99;
+    assert!(sub.child(7).is_none());
+    assert_eq!(sub.child(6).unwrap().remove_value(), Some(99));
+    assert_eq!(trie.get("abcdef"), None);
+    assert_eq!(trie.get("abcdefgh"), Some(&19));
+    assert_eq!(trie.len(), 6);
+    assert!(trie.check_integrity());
+}
+
+#[test]
+fn count_at_most_stops_early() {
+    let mut trie = Trie::new();
+    for ns in 0..10 {
+        for item in 0..1000 {
+            trie.insert(format!("ns{}/item{}", ns, item), item);
+        }
+    }
+    let subtrie = trie.get_raw_descendant("ns3/item").unwrap();
+    assert_eq!(subtrie.count_at_most(99), 99);
+    assert_eq!(subtrie.count_at_most(5000), 1000);
+    assert_eq!(subtrie.count_at_most(0), 0);
+    assert!(!subtrie.is_empty_fast());
+    assert!(Trie::<&str, ()>::new().is_empty_fast());
+
+    let mut visited = 0;
+    subtrie.node.count_at_most(usize::MAX, &mut visited);
+    assert!(visited > 1000);
+
+    let mut visited = 0;
+    subtrie.node.count_at_most(99, &mut visited);
+    assert!(visited < 150, "visited {} nodes", visited);
+
+    // Finding the first value only takes a walk down to it.
+    let mut visited = 0;
+    subtrie.node.count_at_most(1, &mut visited);
+    assert!(visited <= 2, "visited {} nodes", visited);
+}
+
 #[test]
 fn ancestor_key() {
     let trie = test_trie();
@@ -350,6 +436,41 @@ fn child_subtrie_keys() {
     }
 }
 
+#[test]
+fn subtrie_iter_relative() {
+    let mut trie = Trie::new();
+    for &(key, val) in &TEST_DATA {
+        trie.insert(key.to_string(), val);
+    }
+    let subtrie = trie.subtrie("abcd").unwrap();
+    let relative: Vec<_> = subtrie.iter_relative().collect();
+    assert_eq!(
+        relative,
+        vec![
+            (Some(String::new()), &17),
+            (Some("ef".to_string()), &18),
+            (Some("efgh".to_string()), &19),
+        ]
+    );
+
+    // "a" and "acbdef" branch after the first nibble of 'b' and 'c'.
+    let a = trie.subtrie("a").unwrap();
+    let branch = a.children().find(|child| child.key().is_none()).unwrap();
+    assert!(branch.iter_relative().all(|(key, _)| key.is_none()));
+}
+
+#[test]
+fn decode_invalid() {
+    assert_eq!(u32::decode_bytes(&[1, 2, 3]), None);
+    assert_eq!(u16::decode(&NibbleVec::from_byte_vec(vec![1])), None);
+    assert_eq!(Vec::<u16>::decode_bytes(&[1, 2, 3]), None);
+    assert_eq!(String::decode_bytes(&[0xff]), None);
+    let mut odd = "ab".encode();
+    odd.split(3);
+    assert_eq!(String::decode(&odd), None);
+    assert_eq!(NibbleVec::decode(&odd), Some(odd));
+}
+
 #[test]
 fn int_keys() {
     let mut trie = Trie::new();
@@ -358,6 +479,36 @@ fn int_keys() {
     assert!(trie.check_integrity());
 }
 
+#[test]
+fn ordered_int_keys() {
+    use endian_type::LittleEndian;
+    use OrderedKey;
+
+    let numbers = [-300i32, -1, 0, 1, 255, 256, i32::MIN, i32::MAX];
+    let mut sorted = numbers.to_vec();
+    sorted.sort();
+
+    let ordered: Trie<_, _> = numbers.iter().map(|&x| (OrderedKey(x), x)).collect();
+    assert_eq!(ordered.values().cloned().collect::<Vec<_>>(), sorted);
+    assert_eq!(
+        ordered.next_after(&OrderedKey(2)),
+        Some((&OrderedKey(255), &255))
+    );
+    assert_eq!(
+        ordered.next_after(&OrderedKey(-2)),
+        Some((&OrderedKey(-1), &-1))
+    );
+
+    // Neither the plain nor the little-endian encoding sorts numerically.
+    let plain: Trie<_, _> = numbers.iter().map(|&x| (x, x)).collect();
+    assert_ne!(plain.values().cloned().collect::<Vec<_>>(), sorted);
+    let little: Trie<_, _> = numbers
+        .iter()
+        .map(|&x| (LittleEndian::from(x), x))
+        .collect();
+    assert_ne!(little.values().cloned().collect::<Vec<_>>(), sorted);
+}
+
 #[test]
 fn from_iter() {
     let trie: Trie<&str, u32> = Trie::from_iter(vec![("test", 10), ("hello", 12)]);
@@ -478,3 +629,3107 @@ fn clone() {
 
     assert_eq!(t1, t2);
 }
+
+#[cfg(unix)]
+#[test]
+fn os_string_keys_non_utf8() {
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let mut trie = Trie::new();
+    let invalid = OsString::from_vec(vec![b'a', 0xff, 0xfe]);
+    let prefix = OsString::from_vec(vec![b'a', 0xff]);
+    trie.insert(invalid.clone(), 1);
+    trie.insert(prefix.clone(), 2);
+    trie.insert(OsString::from("abc"), 3);
+    assert!(trie.check_integrity());
+
+    assert_eq!(trie.get(&invalid), Some(&1));
+    assert_eq!(trie.get(OsStr::from_bytes(&[b'a', 0xff])), Some(&2));
+    assert_eq!(trie.get(OsStr::new("abc")), Some(&3));
+    assert_eq!(invalid.encode_bytes(), vec![b'a', 0xff, 0xfe]);
+    assert_eq!(invalid.encode(), invalid.as_os_str().encode());
+    assert_eq!(
+        trie.get_ancestor_value(OsStr::from_bytes(&[b'a', 0xff, 0])),
+        Some(&2)
+    );
+    assert_eq!(trie.remove(prefix.as_os_str()), Some(2));
+    assert_eq!(trie.len(), 2);
+}
+
+#[cfg(windows)]
+#[test]
+fn os_string_keys_unpaired_surrogates() {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+
+    let mut trie = Trie::new();
+    // A lone high surrogate, which isn't valid UTF-16.
+    let lone = OsString::from_wide(&[0x61, 0xd800]);
+    let lone_low = OsString::from_wide(&[0x61, 0xdc00]);
+    let prefix = OsString::from_wide(&[0x61]);
+    trie.insert(lone.clone(), 1);
+    trie.insert(lone_low.clone(), 2);
+    trie.insert(prefix.clone(), 3);
+    assert!(trie.check_integrity());
+
+    assert_eq!(lone.encode_bytes(), vec![0x00, 0x61, 0xd8, 0x00]);
+    assert_eq!(lone.encode(), lone.as_os_str().encode());
+    assert_eq!(trie.get(lone.as_os_str()), Some(&1));
+    assert_eq!(trie.get(&lone_low), Some(&2));
+    assert_eq!(trie.get_ancestor_value(lone.as_os_str()), Some(&1));
+    assert_eq!(trie.subtrie(prefix.as_os_str()).unwrap().len(), 3);
+}
+
+#[test]
+fn c_string_keys() {
+    use std::ffi::{CStr, CString};
+
+    let mut trie = Trie::new();
+    let hello = CString::new("hello").unwrap();
+    trie.insert(hello.clone(), 1);
+    trie.insert(CString::new("hell").unwrap(), 2);
+    trie.insert(CString::new("").unwrap(), 3);
+    assert!(trie.check_integrity());
+
+    // The trailing NUL isn't part of the encoding.
+    assert_eq!(hello.encode_bytes(), b"hello".to_vec());
+    assert_eq!(hello.encode(), b"hello"[..].encode());
+    assert_eq!(hello.encode(), hello.as_c_str().encode());
+
+    let borrowed = CStr::from_bytes_with_nul(b"hello\0").unwrap();
+    assert_eq!(trie.get(borrowed), Some(&1));
+    assert_eq!(trie.get(hello.as_c_str()), Some(&1));
+    assert_eq!(
+        trie.get_ancestor_value(CStr::from_bytes_with_nul(b"hellish\0").unwrap()),
+        Some(&2)
+    );
+    assert_eq!(
+        trie.get(CStr::from_bytes_with_nul(b"\0").unwrap()),
+        Some(&3)
+    );
+    assert_eq!(trie.remove(borrowed), Some(1));
+    assert_eq!(trie.len(), 2);
+    assert!(trie.check_integrity());
+}
+
+#[cfg(feature = "uuid")]
+fn v7_uuid(millis: u64, counter: u8) -> ::uuid::Uuid {
+    let mut random = [0x5a; 10];
+    random[9] = counter;
+    ::uuid::Builder::from_unix_timestamp_millis(millis, &random).into_uuid()
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn uuid_v7_keys_iterate_in_time_order() {
+    use uuid::Uuid;
+
+    let times = [1_000_000u64, 1_000_001, 1_000_256, 2_000_000, 1 << 40];
+    let mut expected = vec![];
+    for &t in &times {
+        for c in 0..3 {
+            expected.push(v7_uuid(t, c));
+        }
+    }
+
+    // Insert in a scrambled order.
+    let mut trie = Trie::new();
+    for (i, u) in expected.iter().enumerate().rev() {
+        trie.insert(*u, i);
+    }
+    for (i, u) in expected.iter().enumerate().filter(|&(i, _)| i % 2 == 0) {
+        assert_eq!(trie.insert(*u, i), Some(i));
+    }
+    assert!(trie.check_integrity());
+
+    let keys: Vec<Uuid> = trie.keys().cloned().collect();
+    assert_eq!(keys, expected);
+    assert_eq!(trie.get(&expected[4]), Some(&4));
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn uuid_keys_are_ordered() {
+    let mut uuids = vec![::uuid::Uuid::nil(), ::uuid::Uuid::max(), v7_uuid(7, 0)];
+    uuids.extend((0..4).map(|t| v7_uuid(5_000 - t, 3 - t as u8)));
+    assert_ord_order(uuids);
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn uuid_timestamp_prefix_queries() {
+    let uuids: Vec<_> = (0..4)
+        .flat_map(|t| (0..4).map(move |c| v7_uuid(5_000 + t, c)))
+        .collect();
+
+    // The encoding is the same as the raw bytes, so raw byte prefixes can be used to query
+    // a byte-keyed trie of UUIDs.
+    let mut trie = Trie::new();
+    for u in &uuids {
+        assert_eq!(u.encode(), u.as_bytes().to_vec().encode());
+        trie.insert(u.as_bytes().to_vec(), *u);
+    }
+
+    // The first 6 bytes are the millisecond timestamp.
+    let millis_prefix = uuids[5].as_bytes()[..6].to_vec();
+    let bucket: Vec<_> = trie
+        .get_raw_descendant(&millis_prefix)
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    assert_eq!(bucket, uuids[4..8].to_vec());
+
+    // Storing a truncated timestamp key makes it the ancestor of all UUIDs in its millisecond.
+    trie.insert(millis_prefix.clone(), uuids[0]);
+    assert!(trie.check_integrity());
+    let anc = trie.get_ancestor(&uuids[6].as_bytes().to_vec()).unwrap();
+    assert_eq!(anc.key(), Some(&uuids[6].as_bytes().to_vec()));
+    let next_millis = v7_uuid(5_001, 0xff).as_bytes().to_vec();
+    assert_eq!(
+        trie.get_ancestor(&next_millis).unwrap().key(),
+        Some(&millis_prefix)
+    );
+    assert!(trie
+        .get_ancestor(&v7_uuid(5_002, 0xff).as_bytes().to_vec())
+        .is_none());
+}
+
+// Run the basic operations on a trie whose keys are made from TEST_DATA's bytes by `key`, for
+// key types that are encoded like byte slices.
+#[cfg(any(feature = "bytes", feature = "smallvec"))]
+fn check_byte_slice_keys<K, F>(key: F)
+where
+    K: TrieKey + ::std::borrow::Borrow<[u8]> + ::std::fmt::Debug,
+    F: Fn(&[u8]) -> K,
+{
+    let mut trie = Trie::new();
+    for &(k, v) in &TEST_DATA {
+        assert_eq!(trie.insert(key(k.as_bytes()), v), None);
+    }
+    assert!(trie.check_integrity());
+
+    for &(k, v) in &TEST_DATA {
+        assert_eq!(trie.get::<K>(&key(k.as_bytes())), Some(&v));
+        assert_eq!(trie.get(k.as_bytes()), Some(&v));
+    }
+    assert_eq!(trie.insert(key(b"abcd"), 1), Some(17));
+    assert_eq!(trie.get(&b"abc"[..]), None);
+
+    let subtrie = trie.subtrie(&b"abcd"[..]).unwrap();
+    let keys: Vec<&[u8]> = subtrie
+        .keys()
+        .map(|k| ::std::borrow::Borrow::borrow(k))
+        .collect();
+    assert_eq!(keys, vec![&b"abcd"[..], b"abcdef", b"abcdefgh"]);
+    assert_eq!(trie.get_ancestor_value(&b"abcdeXX"[..]), Some(&1));
+
+    assert_eq!(trie.remove(&b"ab"[..]), Some(16));
+    assert_eq!(trie.remove::<K>(&key(b"bcdefgh")), Some(29));
+    assert_eq!(trie.remove(&b"ab"[..]), None);
+    assert_eq!(trie.len(), 5);
+    assert!(trie.check_integrity());
+}
+
+#[cfg(feature = "bytes")]
+mod bytes_keys {
+    use super::check_byte_slice_keys;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn bytes() {
+        check_byte_slice_keys(Bytes::copy_from_slice);
+    }
+
+    #[test]
+    fn bytes_mut() {
+        check_byte_slice_keys(|k| BytesMut::from(k));
+    }
+
+    #[test]
+    fn ordered() {
+        let keys = [&b"b"[..], b"", b"ab", b"a", b"\xff"];
+        super::assert_ord_order(keys.iter().map(|k| Bytes::copy_from_slice(k)).collect());
+        super::assert_ord_order(keys.iter().map(|&k| BytesMut::from(k)).collect());
+    }
+
+    #[test]
+    fn bytes_slices() {
+        // Slices share the buffer they were cut from.
+        let buf = Bytes::from_static(b"GET /a GET /ab GET /b");
+        let mut trie = ::Trie::new();
+        for (i, range) in [4..6, 11..14, 19..21].iter().enumerate() {
+            trie.insert(buf.slice(range.clone()), i);
+        }
+        assert_eq!(trie.get(&b"/ab"[..]), Some(&1));
+        assert_eq!(trie.get_ancestor_value(&b"/abc"[..]), Some(&1));
+    }
+}
+
+#[cfg(feature = "smallvec")]
+mod smallvec_keys {
+    use super::check_byte_slice_keys;
+    use smallvec::SmallVec;
+
+    #[test]
+    fn inline() {
+        check_byte_slice_keys(SmallVec::<[u8; 16]>::from_slice);
+    }
+
+    #[test]
+    fn ordered() {
+        let keys = vec![&b"bcd"[..], b"", b"ab", b"a", b"\xff"];
+        super::assert_ord_order(
+            keys.into_iter()
+                .map(SmallVec::<[u8; 2]>::from_slice)
+                .collect(),
+        );
+    }
+
+    #[test]
+    fn spilled() {
+        // Keys longer than the inline capacity live on the heap, but encode the same.
+        check_byte_slice_keys(SmallVec::<[u8; 2]>::from_slice);
+    }
+}
+
+#[test]
+fn ip_addr_keys() {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let v4 = Ipv4Addr::new(10, 0, 0, 1);
+    let v6 = Ipv6Addr::new(0x0a00, 0x0001, 0, 0, 0, 0, 0, 0);
+    assert_eq!(v4.encode(), IpAddr::V4(v4).encode());
+    assert_eq!(v6.encode(), IpAddr::V6(v6).encode());
+
+    // The raw bytes of the v4 address are a prefix of the v6 one, but the tag keeps them apart.
+    let mut trie = Trie::new();
+    trie.insert(IpAddr::V4(v4), 4);
+    trie.insert(IpAddr::V6(v6), 6);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.get(&IpAddr::V4(v4)), Some(&4));
+    assert_eq!(trie.get(&IpAddr::V6(v6)), Some(&6));
+    assert!(trie.subtrie(&IpAddr::V4(v4)).unwrap().is_leaf());
+}
+
+#[test]
+fn socket_addr_keys() {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+    let host_a = Ipv4Addr::new(192, 168, 0, 1);
+    let host_b = Ipv4Addr::new(192, 168, 0, 2);
+    let host_c = Ipv6Addr::new(0xc0a8, 0x0001, 0, 0, 0, 0, 0, 1);
+
+    let sockets = [
+        SocketAddr::V4(SocketAddrV4::new(host_b, 80)),
+        SocketAddr::V4(SocketAddrV4::new(host_a, 8080)),
+        SocketAddr::V6(SocketAddrV6::new(host_c, 80, 0, 0)),
+        SocketAddr::V4(SocketAddrV4::new(host_a, 80)),
+        SocketAddr::V6(SocketAddrV6::new(host_c, 80, 0, 2)),
+        SocketAddr::V4(SocketAddrV4::new(host_b, 443)),
+        SocketAddr::V4(SocketAddrV4::new(host_a, 443)),
+    ];
+
+    let mut trie = Trie::new();
+    for (i, s) in sockets.iter().enumerate() {
+        assert_eq!(trie.insert(*s, i), None);
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), sockets.len());
+
+    // Only differing in scope ID: distinct keys, no collision.
+    let scoped = SocketAddr::V6(SocketAddrV6::new(host_c, 80, 0, 2));
+    assert_eq!(trie.get(&scoped), Some(&4));
+
+    // Variants encode like the enum.
+    let a80 = SocketAddrV4::new(host_a, 80);
+    assert_eq!(a80.encode(), SocketAddr::V4(a80).encode());
+
+    // Iteration groups by host, then orders by port.
+    let order: Vec<SocketAddr> = trie.keys().cloned().collect();
+    assert_eq!(order[0], SocketAddr::V4(SocketAddrV4::new(host_a, 80)));
+    assert_eq!(order[1], SocketAddr::V4(SocketAddrV4::new(host_a, 443)));
+    assert_eq!(order[2], SocketAddr::V4(SocketAddrV4::new(host_a, 8080)));
+    assert_eq!(order[3], SocketAddr::V4(SocketAddrV4::new(host_b, 80)));
+    assert_eq!(order[4], SocketAddr::V4(SocketAddrV4::new(host_b, 443)));
+    assert!(order[5..].iter().all(|s| s.is_ipv6()));
+
+    // The host's encoding is a prefix of all of its sockets' encodings.
+    let by_bytes: Trie<Vec<u8>, SocketAddr> =
+        sockets.iter().map(|s| (s.encode_bytes(), *s)).collect();
+    let on_host_a: Vec<SocketAddr> = by_bytes
+        .get_raw_descendant(&host_a.encode_bytes())
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    assert_eq!(on_host_a, order[..3].to_vec());
+    let on_host_c = by_bytes
+        .get_raw_descendant(&host_c.encode_bytes())
+        .unwrap()
+        .len();
+    assert_eq!(on_host_c, 2);
+}
+
+#[test]
+fn remove_all() {
+    let mut trie = test_trie();
+    trie.insert("abcdefz", 1);
+    trie.insert("b", 2);
+
+    let mut keys = vec![
+        "", "a", "abcd", "abcde", "abcdefgh", "abcdefz", "acbdef", "zzz",
+    ];
+    keys.sort();
+    assert_eq!(trie.remove_all(keys), 5);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+
+    let mut remaining = trie.keys().cloned().collect::<Vec<_>>();
+    remaining.sort();
+    assert_eq!(remaining, vec!["ab", "abcdef", "b", "bcdefgh"]);
+
+    // Unsorted, and including keys that have already gone.
+    assert_eq!(trie.remove_all(vec!["bcdefgh", "a", "ab", "abcdef"]), 3);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 1);
+    assert_eq!(trie.get(&"b"), Some(&2));
+
+    assert_eq!(trie.remove_all(vec!["b"]), 1);
+    assert!(trie.is_empty());
+    assert!(trie.check_integrity());
+    assert_eq!(trie.remove_all(Vec::new()), 0);
+}
+
+#[test]
+fn duration_keys_chronological() {
+    use std::time::Duration;
+
+    let mut expected = vec![
+        Duration::new(0, 0),
+        Duration::new(0, 1),
+        Duration::new(0, 999_999_999),
+        Duration::new(1, 0),
+        Duration::new(1, 500),
+        Duration::new(256, 0),
+        Duration::new(u64::MAX, 999_999_999),
+    ];
+
+    let mut trie = Trie::new();
+    for (i, d) in expected.iter().enumerate().rev() {
+        trie.insert(*d, i);
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.get(&Duration::new(1, 500)), Some(&4));
+    assert_eq!(trie.keys().cloned().collect::<Vec<_>>(), expected);
+
+    expected.remove(0);
+    trie.remove(&Duration::new(0, 0));
+    assert_eq!(trie.keys().cloned().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn system_time_keys_chronological() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let expected = vec![
+        UNIX_EPOCH - Duration::new(1_000, 0),
+        UNIX_EPOCH - Duration::new(1, 1),
+        UNIX_EPOCH - Duration::new(1, 0),
+        UNIX_EPOCH - Duration::new(0, 1),
+        UNIX_EPOCH,
+        UNIX_EPOCH + Duration::new(0, 1),
+        UNIX_EPOCH + Duration::new(1, 0),
+        UNIX_EPOCH + Duration::new(1_600_000_000, 123),
+    ];
+
+    let mut trie = Trie::new();
+    for (i, t) in expected.iter().enumerate() {
+        if i % 2 == 1 {
+            trie.insert(*t, i);
+        }
+    }
+    for (i, t) in expected.iter().enumerate() {
+        if i % 2 == 0 {
+            trie.insert(*t, i);
+        }
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), expected.len());
+    assert_eq!(trie.keys().cloned().collect::<Vec<_>>(), expected);
+    assert_eq!(
+        trie.values().cloned().collect::<Vec<_>>(),
+        (0..8).collect::<Vec<_>>()
+    );
+    assert_eq!(trie.get(&(UNIX_EPOCH - Duration::new(1, 1))), Some(&1));
+
+    // Pre-epoch times are in their own subtree.
+    assert_eq!((UNIX_EPOCH - Duration::new(5, 0)).encode_bytes()[0], 0);
+    assert_eq!(UNIX_EPOCH.encode_bytes()[0], 1);
+}
+
+fn keybuf_key(tenant: &[u8], table: &str, id: i64) -> Vec<u8> {
+    KeyBuf::new()
+        .push_bytes(tenant)
+        .push_str(table)
+        .push_i64(id)
+        .finish()
+}
+
+#[test]
+fn keybuf_injective() {
+    // Field values containing the escape and terminator bytes, which would collide if the
+    // fields were naively concatenated.
+    let fields: &[&[u8]] = &[
+        b"",
+        b"\x00",
+        b"\x00\x00",
+        b"\x01",
+        b"\x00\x01",
+        b"\x00\xff",
+        b"\xff",
+        b"a",
+        b"a\x00",
+        b"a\x00\x01",
+        b"a\x01",
+    ];
+    let mut seen = HashSet::new();
+    for &first in fields {
+        for &second in fields {
+            let key = KeyBuf::new().push_bytes(first).push_bytes(second).finish();
+            assert!(seen.insert(key), "{:?} {:?}", first, second);
+        }
+    }
+}
+
+#[test]
+fn keybuf_order_preserving() {
+    let mut tuples = vec![];
+    for &tenant in &[
+        &b""[..],
+        b"\x00",
+        b"\x00\x01",
+        b"\x01",
+        b"a",
+        b"a\x00",
+        b"ab",
+    ] {
+        for &table in &["", "\u{0}", "t", "t\u{0}\u{1}", "tt"] {
+            for &id in &[i64::MIN, -1, 0, 1, 255, 256, i64::MAX] {
+                tuples.push((tenant, table, id));
+            }
+        }
+    }
+    let mut by_encoding = tuples.clone();
+    by_encoding.sort_by_key(|&(tenant, table, id)| keybuf_key(tenant, table, id));
+    tuples.sort();
+    assert_eq!(tuples, by_encoding);
+
+    // Iterating over a trie yields the natural order of the fields.
+    let trie: Trie<Vec<u8>, (&[u8], &str, i64)> = tuples
+        .iter()
+        .rev()
+        .map(|&(tenant, table, id)| (keybuf_key(tenant, table, id), (tenant, table, id)))
+        .collect();
+    assert!(trie.check_integrity());
+    assert_eq!(trie.values().cloned().collect::<Vec<_>>(), tuples);
+}
+
+#[test]
+fn keybuf_prefix_grouping() {
+    let tenants: &[&[u8]] = &[b"a", b"a\x00", b"a\x00\x01", b"a\x01", b"ab"];
+    let tables = ["", "\u{0}", "t", "t\u{0}", "tt"];
+    let mut trie = Trie::new();
+    for &tenant in tenants {
+        for &table in &tables {
+            for id in -2..3 {
+                trie.insert(keybuf_key(tenant, table, id), (tenant, table, id));
+            }
+        }
+    }
+    assert!(trie.check_integrity());
+
+    for &tenant in tenants {
+        let prefix = KeyBuf::new().push_bytes(tenant).prefix();
+        let group = trie.get_raw_descendant(&prefix).unwrap();
+        assert_eq!(group.len(), tables.len() * 5);
+        assert!(group.values().all(|&(t, _, _)| t == tenant));
+
+        for &table in &tables {
+            let prefix = KeyBuf::new().push_bytes(tenant).push_str(table).prefix();
+            let group = trie.get_raw_descendant(&prefix).unwrap();
+            let ids: Vec<i64> = group.values().map(|&(_, _, id)| id).collect();
+            assert_eq!(ids, vec![-2, -1, 0, 1, 2]);
+            assert!(group.values().all(|&(t, tb, _)| t == tenant && tb == table));
+        }
+    }
+}
+
+// Run the standard insert/get/remove/iterate checks for byte keys of type `T`.
+fn byte_key_suite<T, F>(make: F)
+where
+    T: AsRef<[u8]> + Clone + ::std::fmt::Debug,
+    F: Fn(u64) -> T,
+{
+    let keys: Vec<T> = [0u64, 1, 0xff, 0x100, 0xdead_beef, 0xdead_beef_0000_0000]
+        .iter()
+        .map(|&x| make(x))
+        .collect();
+
+    let mut trie = Trie::new();
+    for (i, k) in keys.iter().enumerate() {
+        assert_eq!(trie.insert(ByteKey(k.clone()), i), None);
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), keys.len());
+
+    for (i, k) in keys.iter().enumerate() {
+        // Encodes exactly like the plain byte slice.
+        assert_eq!(ByteKey(k.clone()).encode(), k.as_ref().encode());
+        assert_eq!(trie.get(&ByteKey(k.clone())), Some(&i));
+        assert_eq!(trie.get(k.as_ref()), Some(&i));
+    }
+
+    let mut sorted: Vec<&[u8]> = keys.iter().map(|k| k.as_ref()).collect();
+    sorted.sort();
+    let iterated: Vec<&[u8]> = trie.keys().map(|k| k.as_ref()).collect();
+    assert_eq!(iterated, sorted);
+
+    assert_eq!(trie.remove(keys[1].as_ref()), Some(1));
+    assert_eq!(trie.remove(&ByteKey(keys[4].clone())), Some(4));
+    assert_eq!(trie.remove(&ByteKey(keys[4].clone())), None);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), keys.len() - 2);
+}
+
+#[test]
+fn byte_key_vec() {
+    byte_key_suite(|x| {
+        let mut v = x.to_be_bytes().to_vec();
+        let zeros = v.iter().take_while(|&&b| b == 0).count();
+        v.drain(..zeros);
+        v
+    });
+}
+
+#[test]
+fn byte_key_array() {
+    byte_key_suite(|x| x.to_be_bytes());
+}
+
+#[test]
+fn byte_key_newtype() {
+    #[derive(Clone, Debug)]
+    struct Id(Vec<u8>);
+
+    impl AsRef<[u8]> for Id {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    byte_key_suite(|x| Id(format!("id-{:x}", x).into_bytes()));
+}
+
+fn nibbles(ns: &[u8]) -> NibbleVec {
+    let mut nv = NibbleVec::new();
+    for &n in ns {
+        nv.push(n);
+    }
+    nv
+}
+
+#[test]
+fn nibble_vec_odd_length_keys() {
+    let keys = vec![
+        nibbles(&[0xa]),
+        nibbles(&[0xa, 0xb, 0xc]),
+        nibbles(&[0xa, 0xb, 0xd]),
+        nibbles(&[0xa, 0xb, 0xc, 0xd, 0xe]),
+        nibbles(&[0xa, 0xb, 0xc, 0xd, 0xf]),
+        nibbles(&[0xa, 0xb]),
+    ];
+
+    let mut trie = Trie::new();
+    for (i, k) in keys.iter().enumerate() {
+        assert_eq!(trie.insert(k.clone(), i), None);
+        assert!(trie.check_integrity());
+    }
+
+    for (i, k) in keys.iter().enumerate() {
+        assert_eq!(trie.get(k), Some(&i));
+    }
+    // Byte-aligned versions of the same nibbles are different keys.
+    assert_eq!(trie.get(&nibbles(&[0xa, 0x0])), None);
+    assert_eq!(trie.get(&nibbles(&[0xa, 0xb, 0xc, 0x0])), None);
+
+    // Iteration returns the stored keys, in order.
+    let iterated: Vec<NibbleVec> = trie.keys().cloned().collect();
+    let mut expected = keys.clone();
+    expected.sort_by_key(|nv| (0..nv.len()).map(|i| nv.get(i)).collect::<Vec<_>>());
+    assert_eq!(iterated, expected);
+
+    // Ancestors, including from within a split edge.
+    assert_eq!(
+        trie.get_ancestor_value(&nibbles(&[0xa, 0xb, 0xc, 0xd])),
+        Some(&1)
+    );
+    assert_eq!(
+        trie.get_ancestor_value(&nibbles(&[0xa, 0xb, 0xc, 0xd, 0xe, 0x1])),
+        Some(&3)
+    );
+    assert_eq!(trie.get_ancestor_value(&nibbles(&[0xa, 0xc])), Some(&0));
+    assert_eq!(trie.get_ancestor_value(&nibbles(&[0xb])), None);
+
+    // Removals merge odd-length edges back together.
+    assert_eq!(trie.remove(&nibbles(&[0xa, 0xb, 0xc])), Some(1));
+    assert!(trie.check_integrity());
+    assert_eq!(trie.remove(&nibbles(&[0xa, 0xb, 0xc, 0xd, 0xf])), Some(4));
+    assert!(trie.check_integrity());
+    assert_eq!(trie.remove(&nibbles(&[0xa, 0xb])), Some(5));
+    assert!(trie.check_integrity());
+    assert_eq!(trie.get(&nibbles(&[0xa, 0xb, 0xc, 0xd, 0xe])), Some(&3));
+    assert_eq!(trie.get(&nibbles(&[0xa, 0xb, 0xd])), Some(&2));
+    assert_eq!(trie.len(), 3);
+
+    for k in &keys {
+        trie.remove(k);
+        assert!(trie.check_integrity());
+    }
+    assert!(trie.is_empty());
+}
+
+#[test]
+fn key_relationships() {
+    let rel = |a: &str, b: &str| {
+        let rel = key_relationship(a, b);
+        (rel.kind, rel.common_nibbles, rel.common_bytes())
+    };
+    assert_eq!(rel("", ""), (KeyMatch::Full, 0, 0));
+    assert_eq!(rel("", "a"), (KeyMatch::FirstPrefix, 0, 0));
+    assert_eq!(rel("a", ""), (KeyMatch::SecondPrefix, 0, 0));
+    assert_eq!(rel("abc", "abc"), (KeyMatch::Full, 6, 3));
+    assert_eq!(rel("ab", "abc"), (KeyMatch::FirstPrefix, 4, 2));
+    assert_eq!(rel("abc", "ab"), (KeyMatch::SecondPrefix, 4, 2));
+    // 'a' is 0x61 and 'q' is 0x71, so these differ in the first nibble.
+    assert_eq!(rel("a", "q"), (KeyMatch::Partial(0), 0, 0));
+    // 'c' is 0x63 and 'd' is 0x64, so these differ only in the final nibble.
+    assert_eq!(rel("abc", "abd"), (KeyMatch::Partial(5), 5, 2));
+
+    assert!(is_prefix_of("", ""));
+    assert!(is_prefix_of("", "abc"));
+    assert!(is_prefix_of("ab", "abc"));
+    assert!(is_prefix_of("abc", "abc"));
+    assert!(!is_prefix_of("abc", "ab"));
+    assert!(!is_prefix_of("abc", "abd"));
+
+    // Keys with an odd number of nibbles.
+    let (odd, even) = (nibbles(&[0x6, 0x1, 0x6]), nibbles(&[0x6, 0x1, 0x6, 0x2]));
+    let rel = key_relationship(&odd, &even);
+    assert_eq!(
+        (rel.kind, rel.common_nibbles, rel.common_bytes()),
+        (KeyMatch::FirstPrefix, 3, 1)
+    );
+    assert!(is_prefix_of(&odd, &even));
+    assert!(!is_prefix_of(&even, &odd));
+    let other = nibbles(&[0x6, 0x1, 0x7]);
+    assert_eq!(key_relationship(&odd, &other).kind, KeyMatch::Partial(2));
+}
+
+// The straightforward nibble-by-nibble version of `match_keys`.
+fn match_keys_reference(start_idx: usize, first: &NibbleVec, second: &NibbleVec) -> KeyMatch {
+    let first_len = first.len() - start_idx;
+    let min_length = ::std::cmp::min(first_len, second.len());
+
+    for i in 0..min_length {
+        if first.get(start_idx + i) != second.get(i) {
+            return KeyMatch::Partial(i);
+        }
+    }
+
+    match (first_len, second.len()) {
+        (x, y) if x < y => KeyMatch::FirstPrefix,
+        (x, y) if x == y => KeyMatch::Full,
+        _ => KeyMatch::SecondPrefix,
+    }
+}
+
+// All nibble vectors up to length `max_len` over the given alphabet.
+fn all_nibble_vecs(alphabet: &[u8], max_len: usize) -> Vec<NibbleVec> {
+    let mut all = vec![NibbleVec::new()];
+    let mut last = vec![NibbleVec::new()];
+    for _ in 0..max_len {
+        let mut next = vec![];
+        for nv in &last {
+            for &n in alphabet {
+                let mut ext = nv.clone();
+                ext.push(n);
+                next.push(ext);
+            }
+        }
+        all.extend(next.iter().cloned());
+        last = next;
+    }
+    all
+}
+
+#[test]
+fn match_keys_exhaustive_small() {
+    let vecs = all_nibble_vecs(&[0x0, 0x1, 0xf], 5);
+    for first in &vecs {
+        for second in &vecs {
+            for start_idx in 0..first.len() + 1 {
+                assert_eq!(
+                    match_keys(start_idx, first, second),
+                    match_keys_reference(start_idx, first, second),
+                    "{:?}[{}..] vs {:?}",
+                    first,
+                    start_idx,
+                    second
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn nibble_prefix_queries() {
+    // Bytes made of the nibbles 0x0, 0x1 and 0xf, so that short nibble prefixes hit every
+    // kind of position: at nodes, inside edges, and past the ends of keys.
+    let bytes = [0x00, 0x01, 0x0f, 0x10, 0x1f, 0xf1];
+    let mut keys: Vec<Vec<u8>> = vec![vec![]];
+    for &a in &bytes[..] {
+        keys.push(vec![a]);
+        for &b in &bytes[..3] {
+            keys.push(vec![a, b]);
+        }
+    }
+    keys.retain(|k| k.len() != 1 || k[0] != 0x01);
+    let trie: Trie<Vec<u8>, usize> = keys.iter().cloned().zip(0..).collect();
+
+    for prefix in all_nibble_vecs(&[0x0, 0x1, 0xf], 5) {
+        let mut expected: Vec<&Vec<u8>> = keys
+            .iter()
+            .filter(|k| is_prefix_of(&prefix, &k.encode()))
+            .collect();
+        expected.sort();
+        let found: Vec<&Vec<u8>> = trie
+            .subtrie_nibbles(&prefix)
+            .map_or(vec![], |subtrie| subtrie.keys().collect());
+        assert_eq!(found, expected, "prefix {:?}", prefix);
+
+        let ancestor = keys
+            .iter()
+            .filter(|k| is_prefix_of(&k.encode(), &prefix))
+            .max_by_key(|k| k.len());
+        let found = trie.ancestor_nibbles(&prefix).and_then(|t| t.key());
+        assert_eq!(found, ancestor, "key {:?}", prefix);
+    }
+
+    assert_eq!(
+        trie.subtrie_nibbles(&NibbleVec::new()).unwrap().len(),
+        keys.len()
+    );
+
+    // Odd-length prefixes ending at a node, and inside the edges "ab" and "cdef".
+    let trie: Trie<Vec<u8>, usize> = vec![vec![0xab], vec![0xab, 0x12], vec![0xab, 0xcd, 0xef]]
+        .into_iter()
+        .zip(0..)
+        .collect();
+    let keys_below = |ns: &[u8]| {
+        trie.subtrie_nibbles(&nibbles(ns))
+            .map(|t| t.keys().cloned().collect::<Vec<_>>())
+    };
+    assert_eq!(keys_below(&[0xa]).unwrap().len(), 3);
+    assert_eq!(keys_below(&[0xa, 0xb, 0x1]), Some(vec![vec![0xab, 0x12]]));
+    assert_eq!(
+        keys_below(&[0xa, 0xb, 0xc]),
+        Some(vec![vec![0xab, 0xcd, 0xef]])
+    );
+    assert_eq!(
+        keys_below(&[0xa, 0xb, 0xc, 0xd, 0xe]),
+        Some(vec![vec![0xab, 0xcd, 0xef]])
+    );
+    assert_eq!(keys_below(&[0xa, 0xb, 0xc, 0xe]), None);
+
+    let ancestor = |ns: &[u8]| trie.ancestor_nibbles(&nibbles(ns)).and_then(|t| t.value());
+    assert_eq!(ancestor(&[0xa]), None);
+    assert_eq!(ancestor(&[0xa, 0xb, 0xc]), Some(&0));
+    assert_eq!(ancestor(&[0xa, 0xb, 0xc, 0xd, 0xe, 0xf, 0x1]), Some(&2));
+}
+
+#[test]
+fn match_keys_long() {
+    // Keys long enough to use whole words, differing at every possible nibble.
+    let base: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(37)).collect();
+    for len in 0..129 {
+        let mut first = NibbleVec::from_byte_vec(base.clone());
+        first.split(len);
+        for diff in 0..len + 1 {
+            let mut second = first.clone();
+            if diff < len {
+                let mut tail = second.split(diff);
+                let mut changed = NibbleVec::new();
+                changed.push(tail.get(0) ^ 0x8);
+                second = second.join(&changed).join(&tail.split(1));
+            }
+            for &start_idx in &[0, 1, 2, 3, 16, 17] {
+                if start_idx > len {
+                    continue;
+                }
+                let mut shifted = NibbleVec::from_byte_vec(base.clone());
+                shifted.split(start_idx);
+                let prefixed = shifted.join(&second);
+                assert_eq!(
+                    match_keys(start_idx, &prefixed, &first),
+                    match_keys_reference(start_idx, &prefixed, &first)
+                );
+            }
+            assert_eq!(
+                match_keys(0, &first, &second),
+                match_keys_reference(0, &first, &second)
+            );
+            assert_eq!(
+                match_keys(0, &second, &first),
+                match_keys_reference(0, &second, &first)
+            );
+        }
+    }
+}
+
+#[test]
+fn iter_leaves_and_internal() {
+    let trie: Trie<&str, ()> = vec!["a", "ab", "b"].into_iter().map(|k| (k, ())).collect();
+    let leaves: Vec<_> = trie.iter_leaves().map(|(k, _)| *k).collect();
+    let internal: Vec<_> = trie.iter_internal().map(|(k, _)| *k).collect();
+    assert_eq!(leaves, vec!["ab", "b"]);
+    assert_eq!(internal, vec!["a"]);
+
+    // "a" only reaches its descendants through a value-less branch node.
+    let trie: Trie<&str, ()> = vec!["a", "abc", "abd", "b"]
+        .into_iter()
+        .map(|k| (k, ()))
+        .collect();
+    let leaves: Vec<_> = trie.iter_leaves().map(|(k, _)| *k).collect();
+    let internal: Vec<_> = trie.iter_internal().map(|(k, _)| *k).collect();
+    assert_eq!(leaves, vec!["abc", "abd", "b"]);
+    assert_eq!(internal, vec!["a"]);
+
+    let sub = trie.subtrie("a").unwrap();
+    assert_eq!(sub.iter_leaves().count(), 2);
+    assert_eq!(
+        sub.iter_internal().map(|(k, _)| *k).collect::<Vec<_>>(),
+        vec!["a"]
+    );
+
+    let empty: Trie<&str, ()> = Trie::new();
+    assert_eq!(empty.iter_leaves().count(), 0);
+    assert_eq!(empty.iter_internal().count(), 0);
+}
+
+#[test]
+fn children_at_fixed_width_levels() {
+    let keys = [
+        "org/example/a",
+        "org/example/b/1",
+        "org/example/b/2",
+        "org/example/bc",
+        "org/example/c",
+        "org/examples",
+        "org/other",
+    ];
+    let trie: Trie<&str, usize> = keys.iter().cloned().zip(0..).collect();
+
+    fn summary<'a>(
+        continuation: &str,
+        count: usize,
+        value: Option<&'a usize>,
+    ) -> BranchSummary<'a, usize> {
+        BranchSummary {
+            continuation: continuation.as_bytes().to_vec(),
+            count,
+            value,
+        }
+    }
+
+    // The whole "org/example" run is a single radix edge, but levels are fixed-width.
+    assert_eq!(
+        trie.children_at("org/", 1),
+        vec![summary("e", 6, None), summary("o", 1, None)]
+    );
+    assert_eq!(
+        trie.children_at("org/example", 1),
+        vec![summary("/", 5, None), summary("s", 1, Some(&5))]
+    );
+    assert_eq!(
+        trie.children_at("org/example/", 1),
+        vec![
+            summary("a", 1, Some(&0)),
+            summary("b", 3, None),
+            summary("c", 1, Some(&4)),
+        ]
+    );
+
+    // Wider levels, where one key ends before the boundary.
+    assert_eq!(
+        trie.children_at("org/example/", 2),
+        vec![
+            summary("a", 1, Some(&0)),
+            summary("b/", 2, None),
+            summary("bc", 1, Some(&3)),
+            summary("c", 1, Some(&4)),
+        ]
+    );
+
+    // A prefix ending part-way along an edge.
+    assert_eq!(trie.children_at("org/ex", 3), vec![summary("amp", 6, None)]);
+
+    assert_eq!(trie.children_at("net/", 1), vec![]);
+    assert_eq!(trie.children_at("org/other", 1), vec![]);
+
+    // The same summaries come from the counts of a trie that keeps them.
+    let counted = TrieBuilder::new()
+        .count_subtrees(true)
+        .build_from_sorted(keys.iter().cloned().zip(0..))
+        .unwrap();
+    for &(prefix, width) in &[("org/", 1), ("org/example/", 2), ("org/ex", 3), ("n", 1)] {
+        assert_eq!(
+            counted.children_at(prefix, width),
+            trie.children_at(prefix, width)
+        );
+    }
+}
+
+#[test]
+fn children_at_empty_leaf() {
+    let mut trie: Trie<&str, usize> = vec![("ab", 1), ("ac", 2), ("ad", 3)].into_iter().collect();
+    assert_eq!(trie.subtrie_mut("ad").unwrap().remove("ad"), Ok(Some(3)));
+    let summaries = trie.children_at("a", 1);
+    assert_eq!(
+        summaries
+            .iter()
+            .map(|s| (s.continuation.clone(), s.count))
+            .collect::<Vec<_>>(),
+        vec![(b"b".to_vec(), 1), (b"c".to_vec(), 1)]
+    );
+}
+
+#[test]
+fn group_by_prefix_first_letter() {
+    let words = ["a", "ant", "apple", "bee", "bear", "cat", "cow", "dog"];
+    let trie: Trie<&str, usize> = words.iter().cloned().zip(0..).collect();
+
+    let groups = trie.group_by_prefix(1);
+    let prefixes: Vec<&[u8]> = groups.iter().map(|g| &g.0[..]).collect();
+    assert_eq!(prefixes, vec![b"a", b"b", b"c", b"d"]);
+    let sizes: Vec<usize> = groups.iter().map(|g| g.1.len()).collect();
+    assert_eq!(sizes, vec![3, 2, 2, 1]);
+    assert!(trie.short_keys(1).is_empty());
+
+    // The union of the groups is the whole trie.
+    let mut union: Vec<(&&str, &usize)> = groups.iter().flat_map(|g| g.1.iter()).collect();
+    union.sort();
+    let mut all: Vec<_> = trie.iter().collect();
+    all.sort();
+    assert_eq!(union, all);
+}
+
+#[test]
+fn group_by_prefix_inside_edges() {
+    let trie = namespace_trie();
+
+    // "app/" and "apple" only branch after the cut at "app".
+    let groups = trie.group_by_prefix(3);
+    let prefixes: Vec<&[u8]> = groups.iter().map(|g| &g.0[..]).collect();
+    assert_eq!(prefixes, vec![b"app", b"lib"]);
+    assert_eq!(groups[0].1.len(), 5);
+    assert_eq!(groups[1].1.key(), None);
+
+    // A cut past the end of "apple", within "app/config" and "app/data" and the "lib/" keys.
+    let groups = trie.group_by_prefix(6);
+    let prefixes: Vec<&[u8]> = groups.iter().map(|g| &g.0[..]).collect();
+    assert_eq!(
+        prefixes,
+        vec![&b"app/co"[..], b"app/da", b"lib/co", b"lib/ut"]
+    );
+    assert_eq!(groups[0].1.key(), Some(&"app/config"));
+    assert_eq!(groups[0].1.len(), 3);
+    assert_eq!(trie.short_keys(6), vec![(&"apple", &4)]);
+
+    assert_eq!(trie.group_by_prefix(0).len(), 1);
+    assert!(Trie::<&str, u32>::new().group_by_prefix(0).is_empty());
+}
+
+#[test]
+fn group_by_prefix_empty_leaf() {
+    let mut trie: Trie<&str, usize> = vec![("ab", 1), ("ac", 2), ("ad", 3)].into_iter().collect();
+    assert_eq!(trie.subtrie_mut("ad").unwrap().remove("ad"), Ok(Some(3)));
+
+    let groups = trie.group_by_prefix(2);
+    let prefixes: Vec<&[u8]> = groups.iter().map(|g| &g.0[..]).collect();
+    assert_eq!(prefixes, vec![b"ab", b"ac"]);
+    assert!(trie.short_keys(2).is_empty());
+    assert_eq!(trie.group_by_prefix(1).len(), 1);
+}
+
+#[test]
+fn map_keys_order_preserving() {
+    let trie = namespace_trie().map_keys(|key| format!("tenant/{}", key));
+    assert_eq!(trie.len(), 7);
+    assert!(trie.check_integrity());
+    for (key, value) in namespace_trie().iter() {
+        assert_eq!(trie.get(&format!("tenant/{}", key)), Some(value));
+    }
+    assert_eq!(trie.subtrie("tenant/app/config").unwrap().len(), 3);
+}
+
+#[test]
+fn map_keys_hashed_segments() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn pseudonym(key: &&str) -> String {
+        let segments: Vec<String> = key
+            .split('/')
+            .map(|segment| {
+                let mut hasher = DefaultHasher::new();
+                segment.hash(&mut hasher);
+                format!("{:016x}", hasher.finish())
+            })
+            .collect();
+        segments.join("/")
+    }
+
+    let trie = namespace_trie().map_keys(pseudonym);
+    assert_eq!(trie.len(), 7);
+    assert!(trie.check_integrity());
+    for (key, value) in namespace_trie().iter() {
+        assert_eq!(trie.get(&pseudonym(key)), Some(value));
+    }
+    // Keys that shared whole segments still do.
+    let config = pseudonym(&"app/config");
+    assert_eq!(trie.subtrie(&config).unwrap().len(), 3);
+}
+
+// Keys that ignore case in their encoding, but not in comparisons.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CaseInsensitive(&'static str);
+
+impl TrieKey for CaseInsensitive {
+    fn encode_bytes(&self) -> Vec<u8> {
+        self.0.to_lowercase().into_bytes()
+    }
+}
+
+#[test]
+fn builder_defaults_match_insert() {
+    let builder = TrieBuilder::new();
+    assert!(builder.build().is_empty());
+
+    let entries = vec![("b", 1), ("a", 2), ("ab", 3), ("b", 4)];
+    let built = builder.build_from_sorted(entries.clone()).unwrap();
+    let inserted: Trie<_, _> = entries.into_iter().collect();
+    assert_eq!(built, inserted);
+    assert_eq!(built.get("b"), Some(&4));
+    assert!(built.check_integrity());
+}
+
+#[test]
+#[should_panic(expected = "multiple-keys with the same bit representation.")]
+fn builder_collision_panics() {
+    let entries = vec![(CaseInsensitive("Key"), 1), (CaseInsensitive("kEY"), 2)];
+    let _ = TrieBuilder::new().build_from_sorted(entries);
+}
+
+#[test]
+#[should_panic(
+    expected = "multiple-keys with the same bit representation. Both encode to 0x6b6579"
+)]
+fn insert_collision_panics() {
+    let mut trie = Trie::new();
+    trie.insert(CaseInsensitive("Key"), 1);
+    trie.insert(CaseInsensitive("kEY"), 2);
+}
+
+#[test]
+fn find_encoding_collision() {
+    let mut trie = Trie::new();
+    trie.insert(CaseInsensitive("Key"), 1);
+    trie.insert(CaseInsensitive("Keys"), 2);
+    assert_eq!(
+        trie.find_encoding_collision(&CaseInsensitive("kEY")),
+        Some(&CaseInsensitive("Key"))
+    );
+    assert_eq!(trie.find_encoding_collision(&CaseInsensitive("Key")), None);
+    assert_eq!(trie.find_encoding_collision(&CaseInsensitive("Ke")), None);
+    assert_eq!(
+        trie.find_encoding_collision(&CaseInsensitive("KEYs")),
+        Some(&CaseInsensitive("Keys"))
+    );
+}
+
+#[test]
+fn builder_collision_error() {
+    let entries = vec![
+        (CaseInsensitive("a"), 0),
+        (CaseInsensitive("Key"), 1),
+        (CaseInsensitive("kEY"), 2),
+    ];
+    let result = TrieBuilder::new()
+        .collision_policy(CollisionPolicy::Error)
+        .build_from_sorted(entries);
+    let err = result.unwrap_err();
+    assert_eq!(
+        err,
+        BuildError::Collision(CaseInsensitive("Key"), CaseInsensitive("kEY"))
+    );
+    assert_eq!(
+        err.to_string(),
+        "keys CaseInsensitive(\"Key\") and CaseInsensitive(\"kEY\") have the same encoding \
+         0x6b6579"
+    );
+
+    // Repeating the same key isn't a collision.
+    let repeated = vec![(CaseInsensitive("Key"), 1), (CaseInsensitive("Key"), 2)];
+    let trie = TrieBuilder::new()
+        .collision_policy(CollisionPolicy::Error)
+        .build_from_sorted(repeated)
+        .unwrap();
+    assert_eq!(trie.get(&CaseInsensitive("Key")), Some(&2));
+}
+
+#[test]
+fn builder_prefix_free() {
+    let entries = vec![("ab", 1), ("b", 2), ("abc", 3)];
+    assert_eq!(
+        TrieBuilder::new()
+            .prefix_free(true)
+            .build_from_sorted(entries.clone()),
+        Err(BuildError::NotPrefixFree("ab", "abc"))
+    );
+    let trie = TrieBuilder::new().build_from_sorted(entries).unwrap();
+    assert!(!trie.is_prefix_free());
+
+    let trie = TrieBuilder::new()
+        .prefix_free(true)
+        .build_from_sorted(vec![("ab", 1), ("b", 2), ("ac", 3)])
+        .unwrap();
+    assert!(trie.is_prefix_free());
+    assert_eq!(trie.len(), 3);
+}
+
+#[test]
+fn builder_count_subtrees() {
+    let entries = vec![("a", 1), ("ab", 2), ("abc", 3), ("abd", 4), ("b", 5)];
+    let mut trie = TrieBuilder::new()
+        .count_subtrees(true)
+        .build_from_sorted(entries.clone())
+        .unwrap();
+    let lens = |trie: &Trie<&str, i32>| {
+        ["a", "ab", "abc", "b"]
+            .iter()
+            .map(|&key| trie.subtrie(key).map(|subtrie| subtrie.len()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lens(&trie), vec![Some(4), Some(3), Some(1), Some(1)]);
+    assert!(trie.check_integrity());
+
+    // `insert` and `remove` keep the counts up to date.
+    trie.insert("abcd", 6);
+    assert_eq!(trie.remove("b"), Some(5));
+    assert_eq!(lens(&trie), vec![Some(5), Some(4), Some(2), None]);
+    assert!(trie.check_integrity());
+
+    // Other changes throw them away, and they're counted again when they're next needed.
+    trie.subtrie_mut("abc").unwrap().insert("abce", 7).unwrap();
+    assert!(trie.remove_subtrie("abd").is_some());
+    assert_eq!(lens(&trie), vec![Some(5), Some(4), Some(3), None]);
+    assert!(trie.check_integrity());
+
+    // Removing everything leaves a trie that still counts.
+    let mut rest = trie.remove_subtrie("").unwrap();
+    assert_eq!(rest.len(), 5);
+    rest.insert("b", 8);
+    assert_eq!(rest.subtrie("a").unwrap().len(), 5);
+    assert!(rest.check_integrity());
+    trie.insert("a", 9);
+    assert_eq!(lens(&trie), vec![Some(1), None, None, None]);
+    assert!(trie.check_integrity());
+
+    // The lengths are the same without the counts.
+    let uncounted = TrieBuilder::new().build_from_sorted(entries).unwrap();
+    assert_eq!(uncounted.subtrie("ab").unwrap().len(), 3);
+}
+
+fn namespace_trie() -> Trie<&'static str, usize> {
+    [
+        "app/config",
+        "app/config/db",
+        "app/config/log",
+        "app/data",
+        "apple",
+        "lib/core",
+        "lib/util",
+    ]
+    .iter()
+    .cloned()
+    .zip(0..)
+    .collect()
+}
+
+fn filter_rebuild(trie: &Trie<&'static str, usize>, prefix: &str) -> Trie<&'static str, usize> {
+    trie.iter()
+        .filter(|&(k, _)| k.starts_with(prefix))
+        .map(|(&k, &v)| (k, v))
+        .collect()
+}
+
+#[test]
+fn retain_prefixes() {
+    let mut trie = namespace_trie();
+    // Nested and repeated prefixes, one ending inside an edge, and one that matches nothing.
+    let prefixes = ["app/config/db", "app/con", "lib/u", "app/con", "zzz"];
+    assert_eq!(trie.retain_prefixes(&prefixes), 3);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+    let mut expected = filter_rebuild(&namespace_trie(), "app/con");
+    expected.extend(filter_rebuild(&namespace_trie(), "lib/u"));
+    assert_eq!(trie, expected);
+
+    assert_eq!(trie.retain_prefixes(&["", "nothing"]), 0);
+    assert_eq!(trie.len(), 4);
+    assert_eq!(trie.retain_prefixes(&Vec::<&str>::new()), 4);
+    assert!(trie.is_empty());
+    assert!(trie.check_integrity());
+}
+
+#[test]
+fn subtrie_to_trie() {
+    let trie = namespace_trie();
+    let original = trie.clone();
+
+    for &prefix in &["", "app/config", "app/config/db", "lib/core"] {
+        let extracted = trie.subtrie(prefix).unwrap().to_trie();
+        assert!(extracted.check_integrity());
+        assert_eq!(extracted, filter_rebuild(&trie, prefix));
+        assert_eq!(extracted.len(), filter_rebuild(&trie, prefix).len());
+    }
+
+    // Subtries found by raw descendant lookup can start part-way along an edge.
+    let extracted = trie.get_raw_descendant("lib/").unwrap().to_trie();
+    assert!(extracted.check_integrity());
+    assert_eq!(extracted, filter_rebuild(&trie, "lib/"));
+    assert_eq!(extracted.get("lib/util"), Some(&6));
+
+    assert!(trie.check_integrity());
+    assert_eq!(trie, original);
+    assert_eq!(trie.len(), 7);
+}
+
+// A queue for each tenant, keyed by the tenant's prefix and a big-endian sequence number.
+fn tenant_queues() -> Trie<Vec<u8>, u32> {
+    let mut trie = Trie::new();
+    for &tenant in &[&b"t1/"[..], b"t2/", b"t10/"] {
+        for seq in &[3u32, 1, 256, 2] {
+            let mut key = tenant.to_vec();
+            key.extend_from_slice(&seq.to_be_bytes());
+            trie.insert(key, *seq);
+        }
+    }
+    trie.insert(b"t1/".to_vec(), 0);
+    trie
+}
+
+#[test]
+fn pop_prefix_min_max() {
+    let mut trie = tenant_queues();
+    let mut popped = vec![];
+    while let Some((key, seq)) = trie.pop_prefix_min(&b"t1/"[..]) {
+        assert_eq!(key[3..], seq.to_be_bytes());
+        assert!(trie.check_integrity());
+        popped.push(seq);
+    }
+    assert_eq!(popped, vec![1, 2, 3, 256]);
+    // The prefix's own key and the other tenants' queues are untouched.
+    assert_eq!(trie.len(), 9);
+    assert_eq!(trie.get(&b"t1/"[..]), Some(&0));
+    assert_eq!(trie.view(&b"t10/"[..]).len(), 4);
+    assert_eq!(trie.view(&b"t2/"[..]).len(), 4);
+
+    let mut trie = tenant_queues();
+    let mut popped = vec![];
+    while let Some((_, seq)) = trie.pop_prefix_max(&b"t2/"[..]) {
+        assert!(trie.check_integrity());
+        popped.push(seq);
+    }
+    assert_eq!(popped, vec![256, 3, 2, 1]);
+    assert_eq!(trie.len(), 9);
+
+    // A prefix ending part way along a key, and one that nothing starts with.
+    let mut trie = tenant_queues();
+    assert_eq!(trie.pop_prefix_min(&b"t1"[..]), Some((b"t1/".to_vec(), 0)));
+    assert_eq!(
+        trie.pop_prefix_max(&b"t1"[..]).map(|(k, _)| k),
+        Some(b"t10/\0\0\x01\0".to_vec())
+    );
+    assert_eq!(trie.pop_prefix_min(&b"t3"[..]), None);
+    assert_eq!(trie.len(), 11);
+}
+
+#[test]
+fn pop_prefix_empty_leaf() {
+    let abc =
+        || -> Trie<&str, usize> { vec![("ab", 1), ("ac", 2), ("ad", 3)].into_iter().collect() };
+
+    // The last leaf is empty, so the last key is in the one before it.
+    for &prefix in &["a", ""] {
+        let mut trie = abc();
+        assert_eq!(trie.subtrie_mut("ad").unwrap().remove("ad"), Ok(Some(3)));
+        assert_eq!(trie.pop_prefix_max(prefix), Some(("ac", 2)));
+        assert_eq!(trie.len(), 1);
+        assert!(trie.check_integrity());
+    }
+
+    // Likewise for the first leaf.
+    let mut trie = abc();
+    assert_eq!(trie.subtrie_mut("ab").unwrap().remove("ab"), Ok(Some(1)));
+    assert_eq!(trie.pop_prefix_min("a"), Some(("ac", 2)));
+    assert_eq!(trie.pop_prefix_min("a"), Some(("ad", 3)));
+    assert_eq!(trie.pop_prefix_min("a"), None);
+    assert!(trie.is_empty());
+    assert!(trie.check_integrity());
+
+    // A node whose children are all empty has the last key itself.
+    let mut trie: Trie<&str, usize> = vec![("a", 1), ("ab", 2)].into_iter().collect();
+    assert_eq!(trie.subtrie_mut("ab").unwrap().remove("ab"), Ok(Some(2)));
+    assert_eq!(trie.pop_prefix_max(""), Some(("a", 1)));
+    assert!(trie.is_empty());
+    assert!(trie.check_integrity());
+}
+
+#[test]
+fn remove_subtrie() {
+    for &prefix in &["", "app/config", "app/config/log", "app/data", "lib/core"] {
+        let mut trie = namespace_trie();
+        let expected = filter_rebuild(&trie, prefix);
+
+        let removed = trie.remove_subtrie(prefix).unwrap();
+        assert!(removed.check_integrity());
+        assert_eq!(removed, expected);
+        assert_eq!(removed.len(), expected.len());
+
+        assert!(trie.check_integrity());
+        assert_eq!(trie.len(), 7 - expected.len());
+        assert!(trie.iter().all(|(k, _)| !k.starts_with(prefix)));
+    }
+
+    let mut trie = namespace_trie();
+    assert!(trie.remove_subtrie("app/conf").is_none());
+    assert!(trie.remove_subtrie("zzz").is_none());
+    assert_eq!(trie, namespace_trie());
+}
+
+#[test]
+fn subtrie_mut_prune() {
+    // At a leaf: the parent is left with a single child, and is merged with it.
+    let mut trie = namespace_trie();
+    assert_eq!(trie.subtrie_mut("app/config/db").unwrap().prune(), 1);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 6);
+    assert_eq!(trie.get("app/config/db"), None);
+    assert_eq!(trie.get("app/config/log"), Some(&2));
+
+    // At an internal node: its value goes too.
+    let mut trie = namespace_trie();
+    assert_eq!(trie.subtrie_mut("app/config").unwrap().prune(), 3);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+    assert!(trie.keys().all(|k| !k.starts_with("app/config")));
+
+    // At the root: everything goes.
+    let mut trie = namespace_trie();
+    trie.insert("", 99);
+    assert_eq!(trie.subtrie_mut("").unwrap().prune(), 8);
+    assert!(trie.check_integrity());
+    assert!(trie.is_empty());
+    trie.insert("new", 1);
+    assert_eq!(trie.get("new"), Some(&1));
+
+    // Part-way along an edge: not supported, as there's no node to get a subtrie for.
+    let mut trie = namespace_trie();
+    assert!(trie.subtrie_mut("app/conf").is_none());
+    assert!(trie.subtrie_mut("lib/co").is_none());
+    assert_eq!(trie, namespace_trie());
+}
+
+fn check_iter_encoded<K>(trie: &Trie<K, usize>)
+where
+    K: TrieKey,
+{
+    let expected: Vec<(Vec<u8>, &usize)> =
+        trie.iter().map(|(k, v)| (k.encode_bytes(), v)).collect();
+    assert_eq!(trie.iter_encoded().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn iter_encoded() {
+    let strings: Trie<String, usize> = TEST_DATA
+        .iter()
+        .map(|&(k, _)| k.to_string())
+        .zip(0..)
+        .collect();
+    check_iter_encoded(&strings);
+
+    let ints: Trie<u32, usize> = vec![0, 1, 255, 256, 65_536, u32::MAX]
+        .into_iter()
+        .zip(0..)
+        .collect();
+    check_iter_encoded(&ints);
+    assert_eq!(ints.iter_encoded().nth(3), Some((vec![0, 0, 1, 0], &3)));
+
+    let bytes: Trie<Vec<u8>, usize> = vec![vec![], vec![1], vec![1, 2], vec![3]]
+        .into_iter()
+        .zip(0..)
+        .collect();
+    check_iter_encoded(&bytes);
+
+    // Subtries give full keys.
+    let subtrie = strings.subtrie("ab").unwrap();
+    let keys: Vec<Vec<u8>> = subtrie.iter_encoded().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![&b"ab"[..], b"abcd", b"abcdef", b"abcdefgh"]);
+}
+
+#[test]
+fn subtrie_get_ancestor_within() {
+    // "/a/b/c" and "/a/bz" branch at "/a/b", so there's a node there (without a value).
+    let mut trie: Trie<&str, u32> = vec![("/a", 1), ("/a/b/c", 2), ("/a/bz", 3)]
+        .into_iter()
+        .collect();
+    assert_eq!(trie.get_ancestor_value("/a/b/d"), Some(&1));
+
+    {
+        let subtrie = trie.subtrie("/a/b").unwrap();
+        assert_eq!(
+            subtrie.get_ancestor_within("/a/b/c/d"),
+            Some((&"/a/b/c", &2))
+        );
+        assert_eq!(subtrie.get_ancestor_value_within("/a/b/c"), Some(&2));
+        // "/a" is above the subtrie, so isn't found.
+        assert_eq!(subtrie.get_ancestor_within("/a/b/d"), None);
+        assert_eq!(subtrie.get_ancestor_within("/a/b"), None);
+        assert_eq!(subtrie.get_ancestor_within("/a/x"), None);
+        assert_eq!(subtrie.get_ancestor_within("/a"), None);
+    }
+
+    // The root counts if it has a value.
+    let subtrie = trie.subtrie("/a").unwrap();
+    assert_eq!(subtrie.get_ancestor_within("/a/b/d"), Some((&"/a", &1)));
+    assert_eq!(subtrie.get_ancestor_value_within("/a/bzz"), Some(&3));
+
+    let subtrie = trie.subtrie_mut("/a/b").unwrap();
+    assert_eq!(
+        subtrie.get_ancestor_within("/a/b/c/d"),
+        Some((&"/a/b/c", &2))
+    );
+    assert_eq!(subtrie.get_ancestor_value_within("/a/b/d"), None);
+    assert_eq!(subtrie.get_ancestor_value_within("/a/bz"), Some(&3));
+}
+
+#[test]
+fn subtrie_mut_remove_value() {
+    // With several children, the node stays.
+    let mut trie = namespace_trie();
+    assert_eq!(
+        trie.subtrie_mut("app/config").unwrap().remove_value(),
+        Some(0)
+    );
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 6);
+    assert_eq!(trie.get("app/config"), None);
+    assert_eq!(trie.get("app/config/db"), Some(&1));
+
+    // With a single child, the node is merged with it.
+    let mut trie: Trie<&str, usize> = vec![("ab", 1), ("abcd", 2)].into_iter().collect();
+    assert_eq!(trie.subtrie_mut("ab").unwrap().remove_value(), Some(1));
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 1);
+    assert_eq!(trie.get("abcd"), Some(&2));
+
+    // With no children, the node is removed, and its parent merged.
+    let mut trie = namespace_trie();
+    assert_eq!(
+        trie.subtrie_mut("lib/core").unwrap().remove_value(),
+        Some(5)
+    );
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 6);
+    assert_eq!(trie.get("lib/util"), Some(&6));
+
+    // Nodes without values are left as they are.
+    let mut trie = namespace_trie();
+    trie.insert("", 99);
+    assert_eq!(trie.subtrie_mut("").unwrap().remove_value(), Some(99));
+    assert_eq!(trie.subtrie_mut("").unwrap().remove_value(), None);
+    assert!(trie.check_integrity());
+    assert_eq!(trie, namespace_trie());
+}
+
+#[test]
+fn subtrie_mut_remove_below() {
+    let mut trie: Trie<&str, u32> = vec![("abc", 1), ("abcd", 2), ("abce", 3)]
+        .into_iter()
+        .collect();
+    assert_eq!(trie.subtrie_mut("abc").unwrap().remove("abcd"), Ok(Some(2)));
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 2);
+    assert_eq!(trie.get("abcd"), None);
+
+    // '1' is 0x31 and 'z' is 0x7a, so "ab" has a node without a value and two children. Once
+    // one of them is removed, the node is merged with the other.
+    let mut trie: Trie<&str, u32> = vec![("ab1", 1), ("abz", 2), ("b", 3)].into_iter().collect();
+    {
+        let mut sub = trie.subtrie_mut("ab").unwrap();
+        assert_eq!(sub.remove("abz"), Ok(Some(2)));
+        assert_eq!(sub.child_count(), 0);
+        assert_eq!(sub.get("ab1"), Ok(Some(&1)));
+        assert_eq!(sub.get("abz"), Ok(None));
+        assert_eq!(sub.get("ab"), Ok(None));
+        assert_eq!(sub.get("b"), Err(()));
+        assert_eq!(sub.remove("abz"), Ok(None));
+        assert_eq!(sub.with_subtrie("ab", |s| s.len()), Ok(None));
+
+        // Inserting a key that leaves the merged node's key splits it again.
+        assert_eq!(sub.insert("abz", 4), Ok(None));
+        assert_eq!(sub.insert("ab", 5), Ok(None));
+        assert_eq!(sub.child_count(), 2);
+        assert_eq!(sub.get("abz"), Ok(Some(&4)));
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+    assert_eq!(trie.get("ab"), Some(&5));
+
+    // Emptying the subtrie keeps its node until it's pruned.
+    let mut trie: Trie<&str, u32> = vec![("ab", 1), ("b", 2)].into_iter().collect();
+    {
+        let mut sub = trie.subtrie_mut("ab").unwrap();
+        assert_eq!(sub.remove("ab"), Ok(Some(1)));
+        assert_eq!(sub.insert("abc", 3), Ok(None));
+        assert_eq!(sub.remove("abc"), Ok(Some(3)));
+        assert_eq!(sub.prune(), 0);
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 1);
+    assert!(trie.subtrie("ab").is_none());
+}
+
+#[test]
+fn subtrie_mut_relative() {
+    let mut trie: Trie<Vec<u8>, u32> = Trie::new();
+    trie.insert(b"ab".to_vec(), 1);
+    {
+        let mut sub = trie.subtrie_mut(&b"ab"[..]).unwrap();
+        assert_eq!(sub.insert_relative(b"c", b"abc".to_vec(), 2), Ok(None));
+        assert_eq!(sub.insert_relative(b"c", b"abc".to_vec(), 3), Ok(Some(2)));
+        assert_eq!(sub.insert_relative(b"", b"ab".to_vec(), 4), Ok(Some(1)));
+        assert_eq!(sub.insert_relative(b"d", b"abc".to_vec(), 5), Err(()));
+        assert_eq!(sub.insert_relative(b"de", b"abde".to_vec(), 6), Ok(None));
+        assert_eq!(sub.remove_relative(b"x"), None);
+        assert_eq!(sub.remove_relative(b"c"), Some(3));
+        assert_eq!(sub.remove_relative(b""), Some(4));
+
+        // The node has been merged with "abde", but suffixes are still relative to "ab".
+        assert_eq!(sub.remove_relative(b"de"), Some(6));
+        assert_eq!(sub.insert_relative(b"c", b"abc".to_vec(), 7), Ok(None));
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 1);
+    assert_eq!(trie.get(&b"abc"[..]), Some(&7));
+}
+
+#[test]
+fn subtrie_mut_value_or_insert_with() {
+    // '1' is 0x31 and 'x' is 0x78, so "a" has a node without a value.
+    let mut trie: Trie<String, u32> = vec![("a1", 1), ("ax", 2), ("ay", 3)]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+    {
+        let mut sub = trie.subtrie_mut("a").unwrap();
+        *sub.value_or_insert_with(|| 10).unwrap() += 1;
+        assert_eq!(sub.value_or_insert_with(|| 0), Some(&mut 11));
+        // 'y' is 0x79, so the child for nibble 7 ends part way through a byte.
+        assert_eq!(sub.child(7).unwrap().value_or_insert_with(|| 0), None);
+        let mut seven = sub.child(7).unwrap();
+        assert_eq!(
+            seven.child(8).unwrap().value_or_insert_with(|| 0),
+            Some(&mut 2)
+        );
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+    assert_eq!(trie.get("a"), Some(&11));
+}
+
+#[test]
+fn debug_and_clone_impls() {
+    let trie: Trie<&str, u32> = vec![("b", 2), ("a", 1), ("ab", 3)].into_iter().collect();
+    assert_eq!(format!("{:?}", trie), r#"{"a": 1, "ab": 3, "b": 2}"#);
+    assert_eq!(format!("{:?}", Trie::<&str, u32>::default()), "{}");
+
+    let sub = trie.subtrie("a").unwrap();
+    let sub_clone = sub.clone();
+    assert_eq!(format!("{:?}", sub_clone), r#"{"a": 1, "ab": 3}"#);
+    assert_eq!(sub_clone.len(), sub.len());
+
+    // Debug shows the remaining items, and doesn't affect the iterator.
+    let mut keys = trie.keys();
+    assert_eq!(keys.next(), Some(&"a"));
+    assert_eq!(format!("{:?}", keys), r#"Keys(["ab", "b"])"#);
+    assert_eq!(keys.clone().collect::<Vec<_>>(), vec![&"ab", &"b"]);
+    assert_eq!(keys.collect::<Vec<_>>(), vec![&"ab", &"b"]);
+    assert_eq!(format!("{:?}", trie.values()), "Values([1, 3, 2])");
+    assert_eq!(
+        format!("{:?}", trie.iter()),
+        r#"Iter([("a", 1), ("ab", 3), ("b", 2)])"#
+    );
+
+    let mut trie = trie;
+    let sub_mut = trie.subtrie_mut("ab").unwrap();
+    assert_eq!(format!("{:?}", sub_mut), r#"{"ab": 3}"#);
+
+    // Held iterators can live in user types that derive these traits.
+    #[derive(Debug, Clone)]
+    struct Walk<'a> {
+        trie: &'a Trie<&'static str, u32>,
+        keys: ::iter::Keys<'a, &'static str, u32>,
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Index {
+        trie: Trie<String, u32>,
+    }
+
+    let walk = Walk {
+        trie: &trie,
+        keys: trie.keys(),
+    };
+    assert_eq!(
+        format!("{:?}", walk.clone()),
+        r#"Walk { trie: {"a": 1, "ab": 3, "b": 2}, keys: Keys(["a", "ab", "b"]) }"#
+    );
+    assert_eq!(walk.trie.len(), walk.keys.count());
+    assert_eq!(format!("{:?}", Index::default()), "Index { trie: {} }");
+    assert!(Index::default().trie.is_empty());
+}
+
+#[test]
+fn remove_if() {
+    let mut trie = namespace_trie();
+
+    // Present, and removed, with the same restructuring as `remove`.
+    let mut expected = namespace_trie();
+    expected.remove("app/config/db");
+    assert_eq!(trie.remove_if("app/config/db", |&v| v == 1), Some(1));
+    assert!(trie.check_integrity());
+    assert_eq!(trie, expected);
+    assert_eq!(trie.len(), 6);
+
+    // Present, but kept.
+    assert_eq!(trie.remove_if("app/config/log", |&v| v > 100), None);
+    assert!(trie.check_integrity());
+    assert_eq!(trie, expected);
+    assert_eq!(trie.len(), 6);
+
+    // Absent, including part-way along an edge and at a value-less node.
+    for &key in &["app/config/db", "app/conf", "app/", "zzz", ""] {
+        let mut called = false;
+        assert_eq!(
+            trie.remove_if(key, |_| {
+                called = true;
+                true
+            }),
+            None
+        );
+        assert!(!called, "predicate called for absent key {:?}", key);
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie, expected);
+}
+
+#[test]
+fn next_after() {
+    let trie = namespace_trie();
+    let next = |key: &str| trie.next_after(key).map(|(&k, _)| k);
+
+    // Equal to an existing key.
+    assert_eq!(next("app/config"), Some("app/config"));
+    assert_eq!(next("lib/util"), Some("lib/util"));
+
+    // Between existing keys.
+    assert_eq!(next("app/config/e"), Some("app/config/log"));
+    assert_eq!(next("app/config/m"), Some("app/data"));
+    assert_eq!(next("app/d"), Some("app/data"));
+    assert_eq!(next("app/e"), Some("apple"));
+    assert_eq!(next("b"), Some("lib/core"));
+    assert_eq!(next("lib/d"), Some("lib/util"));
+
+    // Part-way along an edge, or before everything.
+    assert_eq!(next("app/conf"), Some("app/config"));
+    assert_eq!(next("ap"), Some("app/config"));
+    assert_eq!(next(""), Some("app/config"));
+    assert_eq!(next("a"), Some("app/config"));
+
+    // Past the last key.
+    assert_eq!(next("lib/utils"), None);
+    assert_eq!(next("lib/v"), None);
+    assert_eq!(next("z"), None);
+
+    assert_eq!(Trie::<&str, ()>::new().next_after(""), None);
+}
+
+#[test]
+fn next_after_empty_leaf() {
+    // Removing "ab" through its subtrie leaves an empty leaf before "ac".
+    let mut trie: Trie<&str, u32> = vec![("ab", 1), ("ac", 2)].into_iter().collect();
+    assert_eq!(trie.subtrie_mut("ab").unwrap().remove("ab"), Ok(Some(1)));
+    assert!(trie.check_integrity());
+
+    let next = |key: &str| trie.next_after(key).map(|(&k, &v)| (k, v));
+    assert_eq!(next(""), Some(("ac", 2)));
+    assert_eq!(next("a"), Some(("ac", 2)));
+    assert_eq!(next("aa"), Some(("ac", 2)));
+    assert_eq!(next("ab"), Some(("ac", 2)));
+    assert_eq!(next("ad"), None);
+}
+
+#[test]
+fn prefix_freedom() {
+    let trie: Trie<&str, ()> = vec![("a", ()), ("ab", ())].into_iter().collect();
+    assert!(!trie.is_prefix_free());
+    assert_eq!(trie.find_prefix_violation(), Some((&"a", &"ab")));
+
+    let trie: Trie<&str, ()> = vec![("ab", ()), ("ac", ()), ("b", ())]
+        .into_iter()
+        .collect();
+    assert!(trie.is_prefix_free());
+    assert_eq!(trie.find_prefix_violation(), None);
+    assert!(Trie::<&str, ()>::new().is_prefix_free());
+
+    // The prefix only reaches its extensions through a value-less branch node.
+    let trie: Trie<&str, ()> = vec![("b", ()), ("x", ()), ("xyz1", ()), ("xyz2", ())]
+        .into_iter()
+        .collect();
+    assert_eq!(trie.find_prefix_violation(), Some((&"x", &"xyz1")));
+
+    // The empty key is a prefix of everything.
+    let trie: Trie<&str, ()> = vec![("", ()), ("b", ())].into_iter().collect();
+    assert_eq!(trie.find_prefix_violation(), Some((&"", &"b")));
+}
+
+#[test]
+fn insert_prefix_free() {
+    let mut trie: Trie<&str, u32> = Trie::new();
+    assert_eq!(trie.insert_prefix_free("ab", 1), Ok(None));
+    assert_eq!(trie.insert_prefix_free("ac", 2), Ok(None));
+    assert_eq!(trie.insert_prefix_free("ab", 3), Ok(Some(1)));
+
+    // A prefix of an existing key, including part-way along an edge, or an extension of one.
+    for &key in &["a", "", "abc", "ab\0"] {
+        assert_eq!(
+            trie.insert_prefix_free(key, 4),
+            Err(PrefixConflict { key, value: 4 })
+        );
+    }
+    assert_eq!(trie.insert_prefix_free("acdc", 5).unwrap_err().value, 5);
+    trie.insert("long/key", 6);
+    assert!(trie.insert_prefix_free("long/", 0).is_err());
+    assert!(trie.insert_prefix_free("lo", 0).is_err());
+    assert_eq!(trie.insert_prefix_free("longer", 7), Ok(None));
+
+    assert!(trie.check_integrity());
+    assert!(trie.is_prefix_free());
+    assert_eq!(trie.len(), 4);
+}
+
+#[test]
+fn prefix_freedom_empty_leaf() {
+    // Removing "ab" through its subtrie leaves an empty leaf beside "ac".
+    let mut trie: Trie<&str, u32> = vec![("ab", 1), ("ac", 2)].into_iter().collect();
+    assert_eq!(trie.subtrie_mut("ab").unwrap().remove("ab"), Ok(Some(1)));
+
+    // The empty leaf neither hides "ac" from a prefix, nor conflicts with one.
+    trie.insert("a", 0);
+    assert_eq!(trie.find_prefix_violation(), Some((&"a", &"ac")));
+    trie.remove("a");
+    assert_eq!(trie.find_prefix_violation(), None);
+    assert_eq!(trie.subtrie_mut("ac").unwrap().remove("ac"), Ok(Some(2)));
+    assert_eq!(trie.insert_prefix_free("a", 3), Ok(None));
+    assert_eq!(
+        trie.insert_prefix_free("ab", 4),
+        Err(PrefixConflict {
+            key: "ab",
+            value: 4
+        })
+    );
+    assert!(trie.check_integrity());
+    assert!(trie.is_prefix_free());
+}
+
+fn persisted_trie() -> (Trie<Vec<u8>, u64>, Vec<u8>) {
+    let trie: Trie<Vec<u8>, u64> = vec![
+        (b"".to_vec(), 0),
+        (b"a".to_vec(), 1),
+        (b"ab".to_vec(), u64::MAX),
+        (vec![0, 255, 0], 3),
+    ]
+    .into_iter()
+    .collect();
+    let mut bytes = vec![];
+    trie.write_to(&mut bytes).unwrap();
+    (trie, bytes)
+}
+
+#[test]
+fn write_to_read_from() {
+    let (trie, bytes) = persisted_trie();
+    assert_eq!(&bytes[..5], b"RDXT\x01");
+    let read: Trie<Vec<u8>, u64> = Trie::read_from(&bytes[..]).unwrap();
+    assert!(read.check_integrity());
+    assert_eq!(read, trie);
+
+    let trie: Trie<String, i16> = vec![("négatif".to_string(), -5), ("zéro".to_string(), 0)]
+        .into_iter()
+        .collect();
+    let mut bytes = vec![];
+    trie.write_to(&mut bytes).unwrap();
+    assert_eq!(Trie::read_from(&bytes[..]).unwrap(), trie);
+
+    let empty: Trie<Vec<u8>, u8> = Trie::new();
+    let mut bytes = vec![];
+    empty.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 13);
+    assert!(Trie::<Vec<u8>, u8>::read_from(&bytes[..])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn read_from_bad_input() {
+    use std::io::ErrorKind;
+
+    let (_, bytes) = persisted_trie();
+    let read = |bytes: &[u8]| Trie::<Vec<u8>, u64>::read_from(bytes).map(|_| ());
+
+    // Truncated at every possible point.
+    for len in 0..bytes.len() {
+        assert_eq!(
+            read(&bytes[..len]).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    // Another format version.
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    let err = read(&newer).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("version 2"));
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(read(&bad_magic).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // A huge length, a value of the wrong width, and a duplicate key.
+    let mut header = b"RDXT\x01".to_vec();
+    header.extend_from_slice(&2u64.to_be_bytes());
+    let huge = [&header[..], &[0xff, 0xff, 0xff, 0xff, 1, 2]].concat();
+    assert_eq!(read(&huge).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    let entry = [0, 0, 0, 1, b'k', 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 7];
+    let short_value = [&header[..], &[0, 0, 0, 1, b'k', 0, 0, 0, 4, 0, 0, 0, 7]].concat();
+    assert_eq!(
+        read(&short_value).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+    let duplicate = [&header[..], &entry[..], &entry[..]].concat();
+    let err = read(&duplicate).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("duplicate"));
+
+    // Entries that aren't in iteration order, including a key after one it's a prefix of.
+    let later = [0, 0, 0, 1, b'l', 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 7];
+    let longer = [0, 0, 0, 2, b'k', b'k', 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 7];
+    for (first, second) in [(&later[..], &entry[..]), (&longer[..], &entry[..])] {
+        let unordered = [&header[..], first, second].concat();
+        let err = read(&unordered).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("out of order"));
+    }
+    let ordered = [&header[..], &entry[..], &longer[..]].concat();
+    let trie = Trie::<Vec<u8>, u64>::read_from(&ordered[..]).unwrap();
+    assert_eq!(trie.len(), 2);
+    assert!(trie.check_integrity());
+
+    let invalid_utf8 = [&header[..], &[0, 0, 0, 1, 0xff, 0, 0, 0, 1, 0]].concat();
+    let err = Trie::<String, u8>::read_from(&invalid_utf8[..]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+fn awkward_text_trie() -> Trie<String, String> {
+    vec![
+        ("", "empty"),
+        ("tab\there", "new\nline"),
+        ("back\\slash", "\\t is not a tab"),
+        ("crlf\r\n", ""),
+        ("ünïcödé", "\t\t"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect()
+}
+
+#[test]
+fn export_import_lines() {
+    use LineFormat;
+
+    let trie = awkward_text_trie();
+    let mut text = vec![];
+    trie.export_lines(&mut text, LineFormat::Tsv).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(text.lines().count(), trie.len());
+    assert!(text.lines().all(|line| line.matches('\t').count() == 1));
+    assert!(text.contains("tab\\there\tnew\\nline\n"));
+    assert!(text.contains("back\\\\slash\t\\\\t is not a tab\n"));
+
+    // Lines come out in iteration order.
+    let keys: Vec<String> = text
+        .lines()
+        .map(|line| line.split('\t').next().unwrap().to_string())
+        .collect();
+    let escaped: Vec<String> = trie
+        .keys()
+        .map(|k| k.replace('\\', "\\\\").replace('\t', "\\t"))
+        .map(|k| k.replace('\n', "\\n").replace('\r', "\\r"))
+        .collect();
+    assert_eq!(keys, escaped);
+
+    let read = Trie::<String, String>::import_lines(text.as_bytes(), LineFormat::Tsv).unwrap();
+    assert!(read.check_integrity());
+    assert_eq!(read, trie);
+
+    // Bytes that aren't UTF-8 are written as hex.
+    let trie: Trie<Vec<u8>, Vec<u8>> = vec![
+        (vec![0xff, b'\t', 0], vec![b'\n', 0xc3]),
+        (vec![], vec![]),
+        (vec![0xff], vec![0x80; 3]),
+    ]
+    .into_iter()
+    .collect();
+    let mut text = vec![];
+    trie.export_lines(&mut text, LineFormat::Tsv).unwrap();
+    assert_eq!(text, b"\t\nff\t808080\nff0900\t0ac3\n".to_vec());
+    assert_eq!(
+        Trie::import_lines(&text[..], LineFormat::Tsv).unwrap(),
+        trie
+    );
+
+    let trie: Trie<u32, i64> = vec![(7, -1), (300, i64::MIN), (0, 0)].into_iter().collect();
+    let mut text = vec![];
+    trie.export_lines(&mut text, LineFormat::Tsv).unwrap();
+    assert_eq!(
+        Trie::import_lines(&text[..], LineFormat::Tsv).unwrap(),
+        trie
+    );
+
+    // Unsorted input, repeated keys, and Windows line endings are fine too.
+    let text = "b\t2\r\na\t1\nb\t3";
+    let read = Trie::<String, u8>::import_lines(text.as_bytes(), LineFormat::Tsv).unwrap();
+    assert!(read.check_integrity());
+    assert_eq!(read.get("a"), Some(&1));
+    assert_eq!(read.get("b"), Some(&3));
+    assert_eq!(read.len(), 2);
+}
+
+#[test]
+fn import_lines_bad_input() {
+    use {ImportError, LineFormat};
+
+    fn line_of(text: &[u8]) -> usize {
+        match Trie::<String, u8>::import_lines(text, LineFormat::Tsv) {
+            Err(ImportError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other.map(|t| t.len())),
+        }
+    }
+
+    assert_eq!(line_of(b"a\t1\nb\n"), 2);
+    assert_eq!(line_of(b"a\t1\t2\n"), 1);
+    assert_eq!(line_of(b"a\t1\nb\t2\n\n"), 3);
+    assert_eq!(line_of(b"a\t1\nb\\x\t2\n"), 2);
+    assert_eq!(line_of(b"a\t1\nb\t2\\"), 2);
+    assert_eq!(line_of(b"a\t256\n"), 1);
+    assert_eq!(line_of(b"a\t1\n\xff\t2\n"), 2);
+    assert_eq!(
+        Trie::<Vec<u8>, u8>::import_lines(&b"00\t1\nabc\t2\n"[..], LineFormat::Tsv)
+            .unwrap_err()
+            .to_string(),
+        "line 2: odd number of hex digits in \"abc\""
+    );
+    assert!(Trie::<Vec<u8>, u8>::import_lines(&b"zz\t1\n"[..], LineFormat::Tsv).is_err());
+}
+
+#[cfg(feature = "ndjson")]
+#[test]
+fn export_import_ndjson() {
+    use {ImportError, LineFormat};
+
+    let trie = awkward_text_trie();
+    let mut text = vec![];
+    trie.export_lines(&mut text, LineFormat::Ndjson).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(text.lines().count(), trie.len());
+    assert!(text.starts_with(r#"{"key":"","value":"empty"}"#));
+    assert!(text.contains(r#"{"key":"tab\there","value":"new\nline"}"#));
+    let read = Trie::<String, String>::import_lines(text.as_bytes(), LineFormat::Ndjson).unwrap();
+    assert_eq!(read, trie);
+
+    let trie: Trie<Vec<u8>, u64> = vec![(vec![0xfe, 0xff], 1), (vec![], 2)]
+        .into_iter()
+        .collect();
+    let mut text = vec![];
+    trie.export_lines(&mut text, LineFormat::Ndjson).unwrap();
+    assert_eq!(
+        Trie::import_lines(&text[..], LineFormat::Ndjson).unwrap(),
+        trie
+    );
+
+    let line_of =
+        |text: &str| match Trie::<String, u8>::import_lines(text.as_bytes(), LineFormat::Ndjson) {
+            Err(ImportError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other.map(|t| t.len())),
+        };
+    assert_eq!(
+        line_of("{\"key\":\"a\",\"value\":\"1\"}\n{\"key\":\"b\"}\n"),
+        2
+    );
+    assert_eq!(line_of("{\"key\":\"a\",\"value\":1}\n"), 1);
+    assert_eq!(line_of("{\"key\":\"a\",\"value\":\"1\"}\nnot json\n"), 2);
+}
+
+#[test]
+fn int_sequence_keys() {
+    let mut trie: Trie<Vec<u32>, &str> = Trie::new();
+    trie.insert(vec![1, 2], "a");
+    trie.insert(vec![1, 2, 3], "b");
+    trie.insert(vec![1, 258], "c");
+    trie.insert(vec![], "empty");
+
+    // Element-wise prefixes, and nothing else, are ancestors.
+    assert_eq!(trie.get_ancestor_value(&[1u32, 2, 3][..]), Some(&"b"));
+    assert_eq!(trie.get_ancestor_value(&[1u32, 2, 3, 4][..]), Some(&"b"));
+    assert_eq!(trie.get_ancestor_value(&[1u32, 258][..]), Some(&"c"));
+    assert_eq!(trie.get_ancestor_value(&[1u32, 259][..]), Some(&"empty"));
+    assert_eq!(trie.get_ancestor_value(&[1u32][..]), Some(&"empty"));
+    let below: Vec<_> = trie.subtrie(&[1u32, 2][..]).unwrap().values().collect();
+    assert_eq!(below, vec![&"a", &"b"]);
+
+    // Borrowed and owned forms encode identically.
+    assert_eq!(trie.get(&[1u32, 258][..]), Some(&"c"));
+    assert_eq!(trie.get(&[][..] as &[u32]), Some(&"empty"));
+    assert_eq!([1u32, 258][..].encode(), vec![1u32, 258].encode());
+    assert_eq!(vec![0x0102u16].encode_bytes(), vec![1, 2]);
+    assert_eq!(vec![1u64].encode_bytes(), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    assert!(Vec::<u16>::new().encode_bytes().is_empty());
+
+    // Single elements encode the same as integer keys.
+    assert_eq!(vec![-2i16].encode_bytes(), (-2i16).encode_bytes());
+    assert_eq!(vec![7usize].encode_bytes(), 7usize.encode_bytes());
+}
+
+#[test]
+fn get_many_mut() {
+    let mut trie: Trie<&str, i32> = vec![("alice", 100), ("bob", 20), ("bobby", 5), ("", 1)]
+        .into_iter()
+        .collect();
+
+    // Transfer between two accounts.
+    {
+        let [alice, bob] = trie.get_many_mut(["alice", "bob"]).unwrap();
+        *alice -= 30;
+        *bob += 30;
+    }
+    assert_eq!(trie.get("alice"), Some(&70));
+    assert_eq!(trie.get("bob"), Some(&50));
+
+    // A key and its extension, the empty key, and keys given out of order.
+    {
+        let [bobby, empty, bob] = trie.get_many_mut(["bobby", "", "bob"]).unwrap();
+        ::std::mem::swap(bobby, bob);
+        *empty = 0;
+    }
+    assert_eq!(trie.get("bob"), Some(&5));
+    assert_eq!(trie.get("bobby"), Some(&50));
+    assert_eq!(trie.get(""), Some(&0));
+
+    // Duplicate or missing keys.
+    assert!(trie.get_many_mut(["bob", "alice", "bob"]).is_none());
+    assert!(trie.get_many_mut(["bob", "carol"]).is_none());
+    assert!(trie.get_many_mut(["bo", "alice"]).is_none());
+    assert!(trie.get_many_mut(["alice", "bobb"]).is_none());
+
+    let none: [&mut i32; 0] = trie.get_many_mut::<str, 0>([]).unwrap();
+    assert!(none.is_empty());
+    assert_eq!(trie.get_many_mut(["alice"]).map(|[v]| *v), Some(70));
+    assert!(trie.check_integrity());
+}
+
+#[test]
+fn std_map_conversions() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let btree: BTreeMap<&str, u32> = TEST_DATA.iter().cloned().collect();
+    let trie = Trie::from(btree.clone());
+    assert!(trie.check_integrity());
+    assert_eq!(trie, test_trie());
+    assert_eq!(BTreeMap::from(trie), btree);
+
+    let hash: HashMap<&str, u32> = TEST_DATA.iter().cloned().collect();
+    let trie = Trie::from(hash.clone());
+    assert!(trie.check_integrity());
+    assert_eq!(trie, test_trie());
+    assert_eq!(HashMap::from(trie), hash);
+
+    // Negative integers come first in a BTreeMap, but last in the trie.
+    let ints: BTreeMap<i16, i16> = (-500..500).map(|i| (i * 7, i)).collect();
+    let trie = Trie::from(ints.clone());
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), ints.len());
+    assert_eq!(BTreeMap::from(trie), ints);
+
+    let trie: Trie<String, u32> = Trie::from(BTreeMap::new());
+    assert!(trie.is_empty() && trie.check_integrity());
+}
+
+#[test]
+fn extend_from_map() {
+    let mut trie = Trie::new();
+    trie.extend_from_map(vec![("ab", 16), ("a", 15)]);
+    trie.extend_from_map(TEST_DATA.iter().cloned());
+    assert!(trie.check_integrity());
+    assert_eq!(trie, test_trie());
+
+    let mut trie = Trie::new();
+    trie.extend(TEST_DATA.iter().cloned());
+    assert_eq!(trie, test_trie());
+}
+
+// Extend the keys "ab" (16) and "a" (15) with entries that overlap them, and each other.
+fn extend_overlapping(
+    policy: ExtendPolicy<&'static str, u32>,
+) -> (Trie<&'static str, u32>, ExtendStats) {
+    let mut trie = Trie::new();
+    trie.insert("ab", 16);
+    trie.insert("a", 15);
+    let entries = vec![("a", 1), ("abc", 2), ("ab", 3), ("abc", 4), ("b", 5)];
+    let stats = trie.extend_with(entries, policy);
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+    (trie, stats)
+}
+
+#[test]
+fn extend_with_policies() {
+    // "a" and "ab" were already there, and the second "abc" follows the first.
+    let expected_stats = ExtendStats {
+        inserted: 2,
+        existing: 3,
+    };
+    let values =
+        |trie: &Trie<&'static str, u32>| trie.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>();
+
+    let (trie, stats) = extend_overlapping(ExtendPolicy::Overwrite);
+    assert_eq!(stats, expected_stats);
+    assert_eq!(
+        values(&trie),
+        vec![("a", 1), ("ab", 3), ("abc", 4), ("b", 5)]
+    );
+
+    let (trie, stats) = extend_overlapping(ExtendPolicy::KeepExisting);
+    assert_eq!(stats, expected_stats);
+    assert_eq!(
+        values(&trie),
+        vec![("a", 15), ("ab", 16), ("abc", 2), ("b", 5)]
+    );
+
+    let mut merged_keys = vec![];
+    let (trie, stats) = extend_overlapping(ExtendPolicy::Merge(Box::new(|&k, old, new| {
+        merged_keys.push(k);
+        old * 100 + new
+    })));
+    assert_eq!(stats, expected_stats);
+    assert_eq!(
+        values(&trie),
+        vec![("a", 1501), ("ab", 1603), ("abc", 204), ("b", 5)]
+    );
+    assert_eq!(merged_keys, vec!["a", "ab", "abc"]);
+}
+
+#[test]
+fn extend_by_reference() {
+    let source = test_trie();
+    let mut trie = Trie::new();
+    trie.extend(source.iter());
+    assert_eq!(trie, source);
+
+    let map: ::std::collections::BTreeMap<String, u32> =
+        vec![("x".to_string(), 1), ("ab".to_string(), 2)]
+            .into_iter()
+            .collect();
+    let mut trie: Trie<String, u32> = Trie::new();
+    trie.insert("ab".to_string(), 0);
+    trie.extend(map.iter());
+    assert_eq!(trie.len(), 2);
+    assert_eq!(trie.get("ab"), Some(&2));
+    assert_eq!(trie.get("x"), Some(&1));
+}
+
+#[test]
+fn into_iter() {
+    let trie = test_trie();
+    let expected: Vec<_> = trie.iter().map(|(&k, &v)| (k, v)).collect();
+    let mut iter = trie.into_iter();
+    assert_eq!(iter.len(), TEST_DATA.len());
+    iter.next();
+    assert_eq!(iter.len(), TEST_DATA.len() - 1);
+    assert_eq!(iter.collect::<Vec<_>>(), &expected[1..]);
+}
+
+#[test]
+fn view_at_node() {
+    let trie = namespace_trie();
+    let view = trie.view("app/config");
+    assert_eq!(view.len(), 3);
+    assert!(!view.is_empty());
+    assert_eq!(
+        view.keys().cloned().collect::<Vec<_>>(),
+        vec!["app/config", "app/config/db", "app/config/log"]
+    );
+    assert_eq!(view.get("app/config/db"), Some(&1));
+    assert_eq!(view.get("app/data"), None);
+    assert_eq!(view.get("app"), None);
+
+    let all = trie.view("");
+    assert_eq!(all.len(), trie.len());
+    assert!(all.iter().eq(trie.iter()));
+}
+
+#[test]
+fn view_mid_edge() {
+    let trie = namespace_trie();
+
+    // "lib/co" ends part way along the edge to "lib/core".
+    let view = trie.view("lib/co");
+    assert_eq!(view.len(), 1);
+    assert_eq!(view.values().cloned().collect::<Vec<_>>(), vec![5]);
+    assert_eq!(view.get("lib/core"), Some(&5));
+    assert_eq!(view.get("lib/util"), None);
+    assert!(trie.subtrie("lib/co").is_none());
+
+    // "app/con" ends part way along the edge to "app/config".
+    let view = trie.view("app/con");
+    assert_eq!(view.len(), 3);
+    assert_eq!(view.get("app/config/log"), Some(&2));
+    assert_eq!(view.get("app/con"), None);
+
+    // Nested views re-root on the inner prefix.
+    let nested = trie.view("app").view("app/config/l");
+    assert_eq!(
+        nested.iter().collect::<Vec<_>>(),
+        vec![(&"app/config/log", &2)]
+    );
+    assert_eq!(trie.view("app/config/").view("app").len(), 2);
+    assert_eq!(trie.view("a").view("ap").view("appl").len(), 1);
+}
+
+#[test]
+fn view_empty() {
+    let trie = namespace_trie();
+    for prefix in &["b", "app/configs", "lib/core/x", "apple/", "lib/cx"] {
+        let view = trie.view(prefix);
+        assert_eq!(view.len(), 0, "{}", prefix);
+        assert!(view.is_empty());
+        assert_eq!(view.iter().count(), 0);
+        assert_eq!(view.get("app/config"), None);
+        assert_eq!(view.get("lib/core"), None);
+        assert!(view.view(prefix).is_empty());
+    }
+    assert!(trie.view("app").view("lib").is_empty());
+
+    let empty: Trie<&str, u32> = Trie::new();
+    assert!(empty.view("").is_empty());
+    assert!(empty.view("a").is_empty());
+    assert_eq!(format!("{:?}", empty.view("a")), "{}");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Sum(u64);
+
+impl Aggregate<u32> for Sum {
+    fn identity() -> Sum {
+        Sum(0)
+    }
+
+    fn from_value(value: &u32) -> Sum {
+        Sum(u64::from(*value))
+    }
+
+    fn combine(&self, other: &Sum) -> Sum {
+        Sum(self.0 + other.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Max(Option<u32>);
+
+impl Aggregate<u32> for Max {
+    fn identity() -> Max {
+        Max(None)
+    }
+
+    fn from_value(value: &u32) -> Max {
+        Max(Some(*value))
+    }
+
+    fn combine(&self, other: &Max) -> Max {
+        Max(self.0.max(other.0))
+    }
+}
+
+#[test]
+fn aggregate_prefix() {
+    let mut sums: AugmentedTrie<&str, u32, Sum> = AugmentedTrie::from(test_trie());
+    assert_eq!(sums.aggregate_prefix(""), Sum(144));
+    assert_eq!(sums.aggregate_prefix("ab"), Sum(70));
+    assert_eq!(sums.aggregate_prefix("abc"), Sum(54));
+    // "abcdefg" ends part way along the edge to "abcdefgh".
+    assert_eq!(sums.aggregate_prefix("abcdefg"), Sum(19));
+    assert_eq!(sums.aggregate_prefix("abx"), Sum(0));
+    assert_eq!(sums.aggregate_prefix("abcdefghi"), Sum(0));
+
+    // Splits "acbdef", and adds a new leaf to the branch.
+    sums.insert("acx", 100);
+    assert_eq!(sums.aggregate_prefix("ac"), Sum(130));
+    assert_eq!(sums.aggregate_prefix("acb"), Sum(30));
+    assert_eq!(sums.aggregate_prefix("a"), Sum(215));
+
+    // Splits "bcdefgh" with a key that ends at the new branch.
+    sums.insert("bcd", 1);
+    assert_eq!(sums.aggregate_prefix("bc"), Sum(30));
+    assert_eq!(sums.aggregate_prefix("bcde"), Sum(29));
+
+    // Replaces a value without changing the structure.
+    assert_eq!(sums.insert("abcd", 7), Some(17));
+    assert_eq!(sums.aggregate_prefix("abc"), Sum(44));
+
+    // Removing "acx" merges "acbdef" back into a single node.
+    assert_eq!(sums.remove("acx"), Some(100));
+    assert_eq!(sums.aggregate_prefix("ac"), Sum(30));
+    assert_eq!(sums.aggregate_prefix("acbd"), Sum(30));
+    assert_eq!(sums.remove("acx"), None);
+
+    // Removing "bcd" merges with its only child.
+    assert_eq!(sums.remove("bcd"), Some(1));
+    assert_eq!(sums.aggregate_prefix("bcd"), Sum(29));
+
+    assert_eq!(sums.update_value("abcdef", |v| *v = 1000), Some(()));
+    assert_eq!(sums.update_value("abcdefx", |v| *v = 1000), None);
+    assert_eq!(sums.aggregate_prefix("abcde"), Sum(1019));
+    assert_eq!(sums.get("abcdef"), Some(&1000));
+    assert_eq!(sums.len(), 7);
+    assert!(sums.as_trie().check_integrity());
+
+    let mut maxes: AugmentedTrie<&str, u32, Max> = AugmentedTrie::new();
+    assert_eq!(maxes.aggregate_prefix(""), Max(None));
+    for &(key, value) in &TEST_DATA {
+        maxes.insert(key, value);
+    }
+    assert_eq!(maxes.aggregate_prefix("a"), Max(Some(30)));
+    assert_eq!(maxes.aggregate_prefix("ab"), Max(Some(19)));
+    maxes.remove("acbdef");
+    assert_eq!(maxes.aggregate_prefix(""), Max(Some(29)));
+    assert_eq!(maxes.into_trie().len(), 6);
+}
+
+#[test]
+fn lookup_cache_hits() {
+    let mut trie = test_trie().with_lookup_cache();
+    assert_eq!(trie.get("abcd"), Some(&17));
+    assert_eq!(trie.cache.borrow().len(), 1);
+    assert_eq!(trie.get("abcd"), Some(&17));
+    assert_eq!(trie.get("a"), Some(&15));
+    assert_eq!(trie.get("abc"), None);
+    assert_eq!(trie.cache.borrow().len(), 2);
+
+    *trie.get_mut("abcd").unwrap() += 1;
+    assert_eq!(trie.get("abcd"), Some(&18));
+    assert_eq!(trie.get_mut("abx"), None);
+
+    // Only the most recently used keys are kept.
+    for &(key, value) in &TEST_DATA {
+        assert_eq!(trie.get(key), Some(&if key == "abcd" { 18 } else { value }));
+    }
+    assert_eq!(trie.cache.borrow().len(), 4);
+    assert_eq!(trie.as_trie().len(), TEST_DATA.len());
+}
+
+#[test]
+fn lookup_cache_invalidation() {
+    let mut trie = test_trie().with_lookup_cache();
+    for &(key, _) in &TEST_DATA[..4] {
+        trie.get(key);
+    }
+
+    // Splitting "abcdef" moves "abcdefgh" and "abcdef" further from the root.
+    assert_eq!(trie.get("abcdefgh"), Some(&19));
+    trie.insert("abcde", 1);
+    assert_eq!(trie.cache.borrow().len(), 1);
+    assert_eq!(trie.get("abcdefgh"), Some(&19));
+    assert_eq!(trie.get("abcdef"), Some(&18));
+    assert_eq!(trie.get("abcde"), Some(&1));
+
+    // Removing "abcde" merges the nodes back together.
+    assert_eq!(trie.remove("abcde"), Some(1));
+    assert!(trie.cache.borrow().is_empty());
+    assert_eq!(trie.get("abcdef"), Some(&18));
+    assert_eq!(trie.get("abcde"), None);
+    assert_eq!(trie.get_mut("abcdefgh").map(|v| *v), Some(19));
+
+    // A path remembered before any change is only ever used for the key it was found for.
+    let stale = trie.cache.borrow()[0].clone();
+    trie.remove("abcdefgh");
+    trie.insert("abcdefgx", 2);
+    trie.cache.borrow_mut().push(stale);
+    assert_eq!(trie.get("abcdefgh"), None);
+    assert_eq!(trie.get_mut("abcdefgh"), None);
+    assert_eq!(trie.get("abcdefgx"), Some(&2));
+
+    trie.trie_mut().remove_subtrie("ab");
+    assert!(trie.cache.borrow().is_empty());
+    assert_eq!(trie.get("abcdef"), None);
+    assert_eq!(trie.get("a"), Some(&15));
+    assert!(trie.as_trie().check_integrity());
+    assert_eq!(trie.into_trie().len(), 3);
+}
+
+#[test]
+fn compression_report() {
+    // Every combination of four syllables, so that many words share each prefix.
+    let syllables = ["con", "de", "pre", "re"];
+    let mut words = vec![];
+    for a in &syllables {
+        for b in &syllables {
+            for c in &syllables {
+                words.push(format!("{}{}{}", a, b, c));
+                words.push(format!("{}{}", a, b));
+            }
+        }
+    }
+    let trie: Trie<String, usize> = words.iter().map(|w| (w.clone(), w.len())).collect();
+    let report = trie.compression_report();
+
+    assert_eq!(report.keys, trie.len());
+    assert_eq!(
+        report.flat_key_bytes,
+        trie.keys().map(|k| k.len()).sum::<usize>()
+    );
+    assert_eq!(report.flat_nibbles, 2 * report.flat_key_bytes);
+    assert!(report.shared_prefix_savings() > report.flat_nibbles / 2);
+
+    assert_eq!(report.levels[0].nodes, 1);
+    let levels = report.levels.iter();
+    assert_eq!(levels.clone().map(|l| l.nodes).sum::<usize>(), report.nodes);
+    assert_eq!(
+        levels.clone().map(|l| l.stored_nibbles).sum::<usize>(),
+        report.stored_nibbles
+    );
+    assert_eq!(
+        levels.map(|l| l.flat_nibbles).sum::<usize>(),
+        report.flat_nibbles
+    );
+
+    assert_eq!(report.top_prefixes.len(), 10);
+    assert!(report
+        .top_prefixes
+        .windows(2)
+        .all(|w| w[0].keys >= w[1].keys));
+    for shared in &report.top_prefixes {
+        let below = trie
+            .keys()
+            .filter(|k| is_prefix_of_nibbles(&shared.prefix, &k.encode()))
+            .count();
+        assert_eq!(shared.keys, below);
+    }
+    // "con" and "de" start with the same nibble, as do "pre" and "re".
+    assert_eq!(report.top_prefixes[0].keys, 40);
+    assert_eq!(report.top_prefixes[2].keys, 20);
+
+    let table = report.to_string();
+    assert!(table.starts_with(&format!("{} keys in {} nodes\n", report.keys, report.nodes)));
+    assert!(table.contains("depth    nodes     stored       flat   saved"));
+    assert!(table.contains("      40  \"\\x6_\"\n"));
+    assert!(table.contains("      20  \"con\"\n"));
+
+    let empty = Trie::<&str, ()>::new().compression_report();
+    assert_eq!(empty.shared_prefix_savings(), 0);
+    assert!(empty.top_prefixes.is_empty());
+    assert!(empty.to_string().contains("saved: 0 nibbles (-)"));
+}
+
+#[test]
+fn compression_report_empty_leaf() {
+    let mut trie = Trie::new();
+    trie.insert("abc", 1);
+    assert_eq!(trie.subtrie_mut("abc").unwrap().remove("abc"), Ok(Some(1)));
+    let report = trie.compression_report();
+    assert_eq!((report.keys, report.nodes), (0, 1));
+    assert_eq!(report.shared_prefix_savings(), 0);
+    assert!(report.to_string().contains("saved: 0 nibbles (-)"));
+
+    // With a key beside it, the empty leaf is left out of the node counts.
+    trie.insert("abd", 2);
+    let report = trie.compression_report();
+    assert_eq!((report.keys, report.nodes), (1, 3));
+    assert_eq!(report.stored_nibbles, report.flat_nibbles);
+    assert_eq!(report.shared_prefix_savings(), 0);
+    assert!(report.to_string().starts_with("1 keys in 3 nodes\n"));
+}
+
+fn is_prefix_of_nibbles(prefix: &NibbleVec, key: &NibbleVec) -> bool {
+    matches!(
+        match_keys(0, prefix, key),
+        KeyMatch::Full | KeyMatch::FirstPrefix
+    )
+}
+
+#[test]
+fn merge_shards() {
+    let a: Trie<&str, u32> = vec![("", 0), ("ab", 1), ("abc", 2), ("b", 3)]
+        .into_iter()
+        .collect();
+    let b: Trie<&str, u32> = vec![("a", 10), ("abc", 12), ("abcd", 13)]
+        .into_iter()
+        .collect();
+    let c: Trie<&str, u32> = Trie::new();
+    let d: Trie<&str, u32> = vec![("abc", 22), ("b", 23), ("bz", 24)]
+        .into_iter()
+        .collect();
+    let tries = [&a, &b, &c, &d];
+
+    let merged: Vec<(&str, Vec<u32>)> = merge(&tries)
+        .map(|(k, vs)| (*k, vs.into_iter().cloned().collect()))
+        .collect();
+    assert_eq!(
+        merged,
+        vec![
+            ("", vec![0]),
+            ("a", vec![10]),
+            ("ab", vec![1]),
+            ("abc", vec![2, 12, 22]),
+            ("abcd", vec![13]),
+            ("b", vec![3, 23]),
+            ("bz", vec![24]),
+        ]
+    );
+
+    assert_eq!(merge(&tries[2..3]).count(), 0);
+    assert_eq!(merge::<&str, u32>(&[]).count(), 0);
+}
+
+#[test]
+fn get_path_values() {
+    let trie: Trie<&str, &str> = vec![
+        ("/", "root"),
+        ("/org", "org"),
+        ("/org/team", "team"),
+        ("/org/team/repo", "repo"),
+        ("/org/other", "other"),
+    ]
+    .into_iter()
+    .collect();
+    let path = |key| -> Vec<&str> {
+        trie.get_path_values(key)
+            .into_iter()
+            .map(|(_, v)| *v)
+            .collect()
+    };
+
+    assert_eq!(path("/org/team/repo"), vec!["root", "org", "team", "repo"]);
+    assert_eq!(
+        trie.get_path_values("/org/team"),
+        vec![(&"/", &"root"), (&"/org", &"org"), (&"/org/team", &"team")]
+    );
+    // Misses part way along a node's key, and below the deepest key.
+    assert_eq!(path("/org/te"), vec!["root", "org"]);
+    assert_eq!(
+        path("/org/team/repo/src"),
+        vec!["root", "org", "team", "repo"]
+    );
+    assert_eq!(path("/org/teams"), vec!["root", "org", "team"]);
+    assert_eq!(path("/org/x"), vec!["root", "org"]);
+    assert_eq!(path("/other"), vec!["root"]);
+    assert!(path("org").is_empty());
+    assert!(path("").is_empty());
+}
+
+#[test]
+fn get_ancestor_below_branch() {
+    // The ancestor's key is not the whole path down to where the search stops.
+    let trie: Trie<&str, u32> = vec![("a", 1), ("abc1", 2), ("abc2", 3)]
+        .into_iter()
+        .collect();
+    let ancestor = trie.get_ancestor("abc3z").unwrap();
+    assert_eq!(ancestor.key(), Some(&"a"));
+    assert_eq!(ancestor.prefix, "a".encode());
+}
+
+#[test]
+fn with_value_closures() {
+    let mut trie = test_trie();
+    assert_eq!(trie.with_value("abcd", |v| v + 1), Some(18));
+    assert_eq!(trie.with_value("abc", |v| v + 1), None);
+    assert_eq!(
+        trie.with_value_mut("abcd", |v| std::mem::replace(v, 1)),
+        Some(17)
+    );
+    assert_eq!(trie.get("abcd"), Some(&1));
+    assert_eq!(trie.with_subtrie("abcd", |s| s.len()), Some(3));
+    assert_eq!(trie.with_subtrie("abc", |s| s.len()), None);
+
+    {
+        let subtrie = trie.subtrie("ab").unwrap();
+        assert_eq!(subtrie.with_value("abcdef", |v| *v), Ok(Some(18)));
+        assert_eq!(subtrie.with_value("abc", |v| *v), Ok(None));
+        assert_eq!(subtrie.with_value("a", |v| *v), Err(()));
+        assert_eq!(subtrie.with_subtrie("ab", |s| s.len()), Ok(Some(4)));
+        assert_eq!(
+            subtrie.with_subtrie("abcdef", |s| s.key().cloned()),
+            Ok(Some(Some("abcdef")))
+        );
+        assert_eq!(subtrie.with_subtrie("abcde", |s| s.len()), Ok(None));
+        assert_eq!(subtrie.with_subtrie("b", |s| s.len()), Err(()));
+    }
+
+    let mut subtrie = trie.subtrie_mut("ab").unwrap();
+    assert_eq!(subtrie.with_value("ab", |v| *v), Ok(Some(16)));
+    assert_eq!(subtrie.with_value_mut("ab", |v| *v *= 2), Ok(Some(())));
+    assert_eq!(
+        subtrie.with_value_mut("abcdefgh", |v| *v *= 2),
+        Ok(Some(()))
+    );
+    assert_eq!(subtrie.with_value_mut("abcdefg", |v| *v *= 2), Ok(None));
+    assert_eq!(subtrie.with_value_mut("bcdefgh", |v| *v *= 2), Err(()));
+    assert_eq!(
+        subtrie.with_subtrie("abcdef", |s| s.iter().map(|(_, v)| *v).sum::<u32>()),
+        Ok(Some(18 + 38))
+    );
+    assert_eq!(subtrie.with_subtrie("x", |s| s.len()), Err(()));
+    assert_eq!(trie.get("ab"), Some(&32));
+    assert_eq!(trie.get("abcdefgh"), Some(&38));
+    assert_eq!(trie.get("bcdefgh"), Some(&29));
+}
+
+#[test]
+fn keys_with_prefix() {
+    let trie = test_trie();
+    assert_eq!(
+        trie.keys_with_prefix("abcd"),
+        vec!["abcd", "abcdef", "abcdefgh"]
+    );
+    // The prefix ends part way along a node's key.
+    assert_eq!(trie.keys_with_prefix("abcde"), vec!["abcdef", "abcdefgh"]);
+    assert_eq!(trie.keys_with_prefix("a").len(), 6);
+    assert_eq!(trie.keys_with_prefix("").len(), TEST_DATA.len());
+    assert!(trie.keys_with_prefix("abd").is_empty());
+
+    let owned: Trie<String, u32> = TEST_DATA.iter().map(|&(k, v)| (k.to_string(), v)).collect();
+    let rows: Vec<String> = owned.keys_with_prefix("ab");
+    assert_eq!(rows, vec!["ab", "abcd", "abcdef", "abcdefgh"]);
+}
+
+#[test]
+fn collect_prefix() {
+    // Values that can't be cloned, so they can only be read through the closure.
+    struct Row(u32);
+
+    let trie: Trie<&str, Row> = TEST_DATA.iter().map(|&(k, v)| (k, Row(v))).collect();
+    let mut calls = 0;
+    let rows = trie.collect_prefix("abc", |k, row| {
+        calls += 1;
+        format!("{}={}", k, row.0)
+    });
+    assert_eq!(rows, vec!["abcd=17", "abcdef=18", "abcdefgh=19"]);
+    assert_eq!(calls, 3);
+
+    let lengths = trie.collect_prefix("b", |k, _| k.len());
+    assert_eq!(lengths, vec![7]);
+    assert!(trie.collect_prefix("c", |_, row| row.0).is_empty());
+}
+
+// Check that a trie iterates over `keys` in the order of their encodings, and (for ordered
+// key types) in their own order.
+fn assert_encoding_order<K>(keys: Vec<K>)
+where
+    K: TrieKey + Clone + ::std::fmt::Debug,
+{
+    let trie: Trie<K, ()> = keys.iter().cloned().map(|k| (k, ())).collect();
+    let mut sorted = keys;
+    sorted.sort_by(|a, b| compare_keys(&a.encode(), &b.encode()));
+    sorted.dedup();
+    assert_eq!(trie.keys().cloned().collect::<Vec<_>>(), sorted);
+    assert_eq!(trie.len(), sorted.len());
+}
+
+fn assert_ord_order<K>(keys: Vec<K>)
+where
+    K: OrderedTrieKey + Clone + ::std::fmt::Debug,
+{
+    let trie: Trie<K, ()> = keys.iter().cloned().map(|k| (k, ())).collect();
+    let mut sorted = keys.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(trie.keys().cloned().collect::<Vec<_>>(), sorted);
+    assert_encoding_order(keys);
+}
+
+#[test]
+fn iteration_order_ordered_keys() {
+    use std::ffi::{CString, OsString};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::time::{Duration, UNIX_EPOCH};
+    use OrderedKey;
+
+    let words = vec![
+        "b",
+        "",
+        "ab",
+        "a",
+        "abc",
+        "z",
+        "\u{0}",
+        "\u{ff}",
+        "ba",
+        "\u{10ffff}",
+        "aa",
+    ];
+    assert_ord_order(words.iter().map(|w| w.to_string()).collect());
+    assert_ord_order(words.clone());
+    let bytes: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0],
+        vec![0, 0],
+        vec![1],
+        vec![0xff],
+        vec![0x10, 1],
+    ];
+    assert_ord_order(bytes.clone());
+    assert_ord_order(bytes.iter().map(|b| &b[..]).collect());
+    assert_ord_order(bytes.iter().cloned().map(ByteKey).collect());
+    assert_ord_order(vec![ByteKey([2u8, 1]), ByteKey([1, 2]), ByteKey([1, 1])]);
+    assert_ord_order(
+        bytes
+            .iter()
+            .filter(|b| !b.contains(&0))
+            .map(|b| CString::new(b.clone()).unwrap())
+            .collect(),
+    );
+    #[cfg(unix)]
+    assert_ord_order(words.iter().map(OsString::from).collect());
+
+    assert_ord_order(vec![0u8, 255, 16, 1, 15]);
+    assert_ord_order(vec![0u16, 0xffff, 0x100, 0xff, 1]);
+    assert_ord_order(vec![0u32, u32::MAX, 0x1_0000, 0xffff, 7]);
+    assert_ord_order(vec![0u64, u64::MAX, 1 << 32, (1 << 32) - 1, 9]);
+    assert_ord_order(vec![0usize, usize::MAX, 256, 255]);
+    assert_ord_order(vec![
+        vec![1u16, 2],
+        vec![1],
+        vec![0x100],
+        vec![],
+        vec![1, 0xffff],
+    ]);
+    assert_ord_order(vec![vec![2u32], vec![1, 3], vec![1], vec![0x1_0000]]);
+    assert_ord_order(vec![vec![2u64], vec![1, 3], vec![1], vec![u64::MAX]]);
+    assert_ord_order(vec![vec![2usize], vec![1, 3], vec![1]]);
+
+    assert_ord_order(vec![OrderedKey(0u8), OrderedKey(200), OrderedKey(3)]);
+    assert_ord_order(vec![OrderedKey(300u16), OrderedKey(2)]);
+    assert_ord_order(vec![OrderedKey(70_000u32), OrderedKey(2)]);
+    assert_ord_order(vec![OrderedKey(u64::MAX), OrderedKey(2)]);
+    assert_ord_order(vec![
+        OrderedKey(u128::MAX),
+        OrderedKey(1 << 64),
+        OrderedKey(2),
+    ]);
+    assert_ord_order(vec![OrderedKey(usize::MAX), OrderedKey(2)]);
+    assert_ord_order(vec![
+        OrderedKey(-1i8),
+        OrderedKey(1),
+        OrderedKey(i8::MIN),
+        OrderedKey(0),
+    ]);
+    assert_ord_order(vec![OrderedKey(-300i16), OrderedKey(300), OrderedKey(-1)]);
+    assert_ord_order(vec![
+        OrderedKey(-70_000i32),
+        OrderedKey(70_000),
+        OrderedKey(-1),
+    ]);
+    assert_ord_order(vec![
+        OrderedKey(i64::MIN),
+        OrderedKey(i64::MAX),
+        OrderedKey(-1),
+    ]);
+    assert_ord_order(vec![OrderedKey(i128::MIN), OrderedKey(1), OrderedKey(-1)]);
+    assert_ord_order(vec![OrderedKey(isize::MIN), OrderedKey(1), OrderedKey(-1)]);
+
+    assert_ord_order(vec![
+        Duration::new(1, 0),
+        Duration::new(0, 999_999_999),
+        Duration::new(256, 1),
+        Duration::new(0, 0),
+    ]);
+    assert_ord_order(vec![
+        UNIX_EPOCH + Duration::new(5, 0),
+        UNIX_EPOCH - Duration::new(5, 0),
+        UNIX_EPOCH - Duration::new(1, 500),
+        UNIX_EPOCH,
+    ]);
+
+    let v4 = vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(9, 255, 0, 0)];
+    let v6 = vec![Ipv6Addr::LOCALHOST, Ipv6Addr::UNSPECIFIED];
+    assert_ord_order(v4.clone());
+    assert_ord_order(v6.clone());
+    let ips: Vec<IpAddr> = v4
+        .iter()
+        .map(|&ip| ip.into())
+        .chain(v6.iter().map(|&ip| ip.into()))
+        .collect();
+    assert_ord_order(ips);
+    let sockets4: Vec<SocketAddrV4> = v4
+        .iter()
+        .flat_map(|&ip| vec![SocketAddrV4::new(ip, 80), SocketAddrV4::new(ip, 443)])
+        .collect();
+    let sockets6: Vec<SocketAddrV6> = v6
+        .iter()
+        .flat_map(|&ip| {
+            vec![
+                SocketAddrV6::new(ip, 80, 0, 0),
+                SocketAddrV6::new(ip, 80, 1, 0),
+                SocketAddrV6::new(ip, 80, 0, 1),
+                SocketAddrV6::new(ip, 79, 2, 2),
+            ]
+        })
+        .collect();
+    assert_ord_order(sockets4.clone());
+    assert_ord_order(sockets6.clone());
+    assert_ord_order(
+        sockets4
+            .into_iter()
+            .map(SocketAddr::V4)
+            .chain(sockets6.into_iter().map(SocketAddr::V6))
+            .collect(),
+    );
+}
+
+#[test]
+fn iteration_order_unordered_keys() {
+    use endian_type::{BigEndian, LittleEndian};
+    use std::path::PathBuf;
+
+    // These still iterate in the order of their encodings, just not in their own order.
+    assert_encoding_order(vec![-1i8, 1, i8::MIN, 0]);
+    assert_encoding_order(vec![-1i16, 1, 256, 0]);
+    assert_encoding_order(vec![-1i32, 1, 1 << 20, 0]);
+    assert_encoding_order(vec![-1i64, 1, i64::MIN, 0]);
+    assert_encoding_order(vec![-1isize, 1, 0]);
+    assert_encoding_order(vec![vec![-1i16], vec![1, 2], vec![1]]);
+    assert_encoding_order(vec![vec![-1i32], vec![1, 2], vec![1]]);
+    assert_encoding_order(vec![vec![-1i64], vec![1, 2], vec![1]]);
+    assert_encoding_order(vec![vec![-1isize], vec![1, 2], vec![1]]);
+    assert_encoding_order(vec![
+        LittleEndian::from(256u32),
+        LittleEndian::from(1),
+        LittleEndian::from(2),
+    ]);
+    assert_encoding_order(vec![BigEndian::from(256u32), BigEndian::from(1)]);
+    assert_encoding_order(vec![
+        "a-b".into(),
+        PathBuf::from("a/b"),
+        PathBuf::from("a"),
+        PathBuf::from("/"),
+    ]);
+    assert_encoding_order(vec![
+        nibbles(&[1]),
+        nibbles(&[0, 15]),
+        nibbles(&[0]),
+        nibbles(&[]),
+        nibbles(&[0, 15, 0]),
+        nibbles(&[1, 0]),
+    ]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn iteration_order_serde_keys() {
+    use SerdeKey;
+
+    assert_encoding_order(vec![
+        SerdeKey((2u32, "b".to_string())),
+        SerdeKey((1, "zz".to_string())),
+        SerdeKey((2, "".to_string())),
+        SerdeKey((2, "a".to_string())),
+    ]);
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn iteration_order_normalized_keys() {
+    use Normalized;
+
+    let keys: Vec<Normalized<&str>> = vec!["e\u{301}", "f", "\u{e9}t\u{e9}", "e"]
+        .into_iter()
+        .map(Normalized::new)
+        .collect();
+    assert_encoding_order(keys);
+}
+
+#[test]
+fn interned_keys() {
+    let paths = ["/a/x/1", "/a/x/2", "/a/y", "/b", "c", "/a/x/"];
+    let mut pool = KeyPool::default();
+    let mut trie = TrieBuilder::<InternedKey<str>, usize>::new()
+        .build_interned(&mut pool, paths.iter().map(|&path| (path, path.len())))
+        .unwrap();
+    assert!(trie.check_integrity());
+    assert_eq!(trie.get(&InternedKey::from("/a/x/2")), Some(&6));
+    assert_eq!(trie.get(&InternedKey::from("/a/x")), None);
+    let keys: Vec<String> = trie.keys().map(|k| k.to_string()).collect();
+    assert_eq!(keys, vec!["/a/x/", "/a/x/1", "/a/x/2", "/a/y", "/b", "c"]);
+    assert_eq!(format!("{:?}", trie.keys().next().unwrap()), "\"/a/x/\"");
+
+    // "/", "a/" and "x/".
+    assert_eq!(
+        pool.stats(),
+        KeyPoolStats {
+            prefixes: 3,
+            prefix_bytes: 5,
+            garbage: 0,
+        }
+    );
+    let key = pool.intern("/a/x/3");
+    assert_eq!(key, InternedKey::from("/a/x/3"));
+    assert_eq!(pool.stats().prefixes, 3);
+    trie.insert(key, 6);
+
+    // Removing the last keys under "/a/x/" frees the prefix.
+    for path in &["/a/x/", "/a/x/1", "/a/x/2", "/a/x/3"] {
+        assert_eq!(trie.remove(&InternedKey::from(*path)), Some(path.len()));
+    }
+    assert_eq!(pool.stats().garbage, 1);
+    pool.compact();
+    assert_eq!(
+        pool.stats(),
+        KeyPoolStats {
+            prefixes: 2,
+            prefix_bytes: 3,
+            garbage: 0,
+        }
+    );
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 3);
+}
+
+#[test]
+fn interned_byte_keys() {
+    let keys: Vec<Vec<u8>> = vec![vec![1, 0, 2], vec![1, 0], vec![], vec![1, 0, 2], vec![0xff]];
+    let mut pool = KeyPool::new(0);
+    let trie = TrieBuilder::<InternedKey<[u8]>, usize>::new()
+        .build_interned(&mut pool, keys.iter().zip(0..))
+        .unwrap();
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), 4);
+    // The last value for a repeated key is kept.
+    assert_eq!(trie.get(&InternedKey::from(&[1, 0, 2][..])), Some(&3));
+    assert_eq!(trie.get(&InternedKey::from(&[][..])), Some(&2));
+    assert_eq!(pool.stats().prefixes, 1);
+
+    // Long last components are stored apart from the key.
+    let long = vec![7; 100];
+    assert_eq!(pool.intern(&long[..]).to_bytes(), long);
+    assert_eq!(std::mem::size_of::<InternedKey<[u8]>>(), 32);
+
+    let err = TrieBuilder::<InternedKey<[u8]>, ()>::new()
+        .prefix_free(true)
+        .build_interned(&mut pool, vec![(&[1][..], ()), (&[1, 2][..], ())])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        BuildError::NotPrefixFree(InternedKey::from(&[1][..]), InternedKey::from(&[1, 2][..]))
+    );
+}
+
+#[test]
+fn first_divergence() {
+    let empty: Trie<String, u32> = Trie::new();
+    assert_eq!(empty.first_divergence(&Trie::new()), None);
+
+    let keys: Vec<String> = (0..5000).map(|i| format!("{:04}/{}", i % 97, i)).collect();
+    let primary: Trie<String, u32> = keys.iter().cloned().zip(0..).collect();
+    let mut replica: Trie<String, u32> = keys.iter().cloned().zip(0..5000).rev().collect();
+    assert_eq!(primary.first_divergence(&primary), None);
+    assert_eq!(primary.first_divergence(&replica), None);
+
+    // The same entries, in tries of a different shape.
+    replica.insert("0042/x".to_string(), 0);
+    replica.insert("0042/".to_string(), 0);
+    replica.remove("0042/x");
+    replica.remove("0042/");
+    assert!(replica.check_integrity());
+    assert_eq!(primary.first_divergence(&replica), None);
+
+    *replica.get_mut("0096/4364").unwrap() = 1;
+    assert_eq!(
+        primary.first_divergence(&replica),
+        Some(Divergence {
+            key: &"0096/4364".to_string(),
+            left: Some(&4364),
+            right: Some(&1),
+        })
+    );
+
+    // Missing keys, on either side, are smaller than the changed one.
+    replica.remove("0050/50");
+    assert_eq!(
+        primary.first_divergence(&replica),
+        Some(Divergence {
+            key: &"0050/50".to_string(),
+            left: Some(&50),
+            right: None,
+        })
+    );
+    replica.insert("0050/4".to_string(), 7);
+    assert_eq!(
+        primary.first_divergence(&replica),
+        Some(Divergence {
+            key: &"0050/4".to_string(),
+            left: None,
+            right: Some(&7),
+        })
+    );
+    assert_eq!(
+        empty.first_divergence(&primary),
+        Some(Divergence {
+            key: &"0000/0".to_string(),
+            left: None,
+            right: Some(&0),
+        })
+    );
+}
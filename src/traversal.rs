@@ -1,22 +1,51 @@
 //! This module contains the core algorithms.
 
-use keys::{match_keys, KeyMatch};
+use augmented::{AggregateNode, Count};
+use keys::{check_keys, match_keys, KeyMatch, KeyRef};
 use std::borrow::Borrow;
 use trie_node::TrieNode;
-use {NibbleVec, TrieKey};
+use {BranchSummary, NibbleVec, SubTrie, TrieKey, BRANCH_FACTOR};
 
 use self::DescendantResult::*;
 
+type NodePair<'a, K, V> = (&'a TrieNode<K, V>, &'a TrieNode<K, V>);
+
 impl<K, V> TrieNode<K, V>
 where
     K: TrieKey,
 {
-    pub fn get(&self, nv: &NibbleVec) -> Option<&TrieNode<K, V>> {
-        iterative_get(self, nv)
+    pub fn get<'k, N: Into<KeyRef<'k>>>(&self, nv: N) -> Option<&TrieNode<K, V>> {
+        iterative_get(self, nv.into())
+    }
+
+    pub fn get_mut<'k, N: Into<KeyRef<'k>>>(&mut self, nv: N) -> Option<&mut TrieNode<K, V>> {
+        iterative_get_mut(self, nv.into())
+    }
+
+    /// Find the node for `nv`, recording the bucket of each node on the way down in `path`.
+    pub fn get_path(&self, nv: &NibbleVec, path: &mut Vec<u8>) -> Option<&TrieNode<K, V>> {
+        iterative_get_path(self, nv, path)
     }
 
-    pub fn get_mut(&mut self, nv: &NibbleVec) -> Option<&mut TrieNode<K, V>> {
-        iterative_get_mut(self, nv)
+    /// Follow a path of buckets found by `get_path`.
+    pub fn follow(&self, path: &[u8]) -> Option<&TrieNode<K, V>> {
+        path.iter().try_fold(self, |node, &bucket| {
+            node.children[bucket as usize].as_deref()
+        })
+    }
+
+    /// Follow a path of buckets found by `get_path`.
+    pub fn follow_mut(&mut self, path: &[u8]) -> Option<&mut TrieNode<K, V>> {
+        path.iter().try_fold(self, |node, &bucket| {
+            node.children[bucket as usize].as_deref_mut()
+        })
+    }
+
+    pub fn get_parent_mut(
+        &mut self,
+        nv: &NibbleVec,
+    ) -> Option<(&mut TrieNode<K, V>, Option<usize>)> {
+        iterative_get_parent_mut(self, nv)
     }
 
     pub fn insert(&mut self, key: K, value: V, nv: NibbleVec) -> Option<V> {
@@ -28,11 +57,128 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        recursive_remove(self, key)
+        self.remove_relative(&key.encode(), key)
+    }
+
+    /// Remove the value for `key`, whose encoding continues with `nv` after this node's key.
+    pub fn remove_relative<Q>(&mut self, nv: &NibbleVec, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        recursive_remove(self, nv, |node| node.take_value(key))
+    }
+
+    /// Remove the value whose key continues with `nv` after this node's key, whatever the key.
+    pub fn remove_nibbles(&mut self, nv: &NibbleVec) -> Option<V> {
+        recursive_remove(self, nv, |node| node.key_value.take().map(|kv| kv.value))
+    }
+
+    pub fn remove_if<Q, F>(&mut self, key: &Q, pred: F) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&V) -> bool,
+    {
+        recursive_remove(self, &key.encode(), |node| {
+            if node.value().is_some_and(pred) {
+                node.take_value(key)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn remove_all<I>(&mut self, keys: I) -> usize
+    where
+        I: IntoIterator<Item = K>,
+    {
+        let mut batch = RemoveBatch::new(keys.into_iter());
+        batch_remove(self, 0, &mut batch);
+        batch.removed
     }
 
-    pub fn get_ancestor(&self, nv: &NibbleVec) -> Option<(&TrieNode<K, V>, usize)> {
-        get_ancestor(self, nv)
+    /// Remove every value that doesn't start with one of `prefixes`, returning the number of
+    /// values removed. The prefixes must be non-empty, and none can be a prefix of another.
+    pub fn retain_prefixes(&mut self, prefixes: &[&NibbleVec]) -> usize {
+        retain_prefixes(self, 0, prefixes)
+    }
+
+    /// Remove the child in `bucket` and everything below it, returning the number of values
+    /// removed.
+    pub fn prune_child(&mut self, bucket: usize) -> usize {
+        let removed = self
+            .take_child(bucket)
+            .map_or(0, |child| child.compute_size());
+        merge_single_child(self);
+        removed
+    }
+
+    /// Remove the value of the child in `bucket`, keeping its descendants.
+    pub fn take_child_value(&mut self, bucket: usize) -> Option<V> {
+        let value = self.children[bucket]
+            .as_mut()
+            .and_then(|child| child.key_value.take())
+            .map(|kv| kv.value);
+        fix_child(self, bucket);
+        merge_single_child(self);
+        value
+    }
+
+    /// Merge this node with its only child, if it's a value-less, non-root node.
+    pub fn merge_single_child(&mut self) {
+        merge_single_child(self)
+    }
+
+    pub fn find_prefix_violation(&self) -> Option<NodePair<'_, K, V>> {
+        find_prefix_violation(self)
+    }
+
+    pub fn has_prefix_conflict(&self, nv: &NibbleVec) -> bool {
+        has_prefix_conflict(self, nv)
+    }
+
+    /// Find the values for several keys at once.
+    ///
+    /// `targets` holds the index in `values` to store each value at, and the key and its
+    /// encoding. It must be sorted by encoding, with no duplicates.
+    pub fn get_many_mut<'a, Q>(
+        &'a mut self,
+        targets: &[(usize, &NibbleVec, &Q)],
+        values: &mut [Option<&'a mut V>],
+    ) where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        get_many_mut(self, 0, targets, values)
+    }
+
+    pub fn next_after(&self, nv: &NibbleVec) -> Option<&TrieNode<K, V>> {
+        next_after(self, nv)
+    }
+
+    pub fn remove_subtrie(&mut self, nv: &NibbleVec) -> Option<Box<TrieNode<K, V>>> {
+        recursive_remove_subtrie(self, 0, nv)
+    }
+
+    /// Remove the first (or, if `last`, the last) key and value, in iteration order, among
+    /// those whose keys are proper extensions of `nv`.
+    pub fn pop_prefix(&mut self, nv: &NibbleVec, last: bool) -> Option<(K, V)> {
+        pop_prefix(self, 0, nv, last)
+    }
+
+    pub fn get_ancestor<'k, N: Into<KeyRef<'k>>>(&self, nv: N) -> Option<(&TrieNode<K, V>, usize)> {
+        get_ancestor(self, nv.into())
+    }
+
+    /// Call `visit` on each node with a value on the path to `nv`, from the root down, along
+    /// with the length of its key.
+    pub fn for_each_ancestor<'a, 'k, N, F>(&'a self, nv: N, visit: F)
+    where
+        N: Into<KeyRef<'k>>,
+        F: FnMut(&'a TrieNode<K, V>, usize),
+    {
+        for_each_ancestor(self, nv.into(), visit)
     }
 
     pub fn get_raw_ancestor(&self, nv: &NibbleVec) -> (&TrieNode<K, V>, usize) {
@@ -42,6 +188,37 @@ where
     pub fn get_raw_descendant<'a>(&'a self, nv: &NibbleVec) -> Option<DescendantResult<'a, K, V>> {
         get_raw_descendant(self, nv)
     }
+
+    /// Summarise the keys below this node, whose full key is `path`, by their nibbles
+    /// `start..end`, taking the number of keys below each node from `counts` if it's given.
+    pub fn branch_summaries<'a>(
+        &'a self,
+        counts: Option<&AggregateNode<Count>>,
+        path: &NibbleVec,
+        start: usize,
+        end: usize,
+        summaries: &mut Vec<BranchSummary<'a, V>>,
+    ) {
+        branch_summaries(self, counts, path, start, end, summaries)
+    }
+
+    /// Count the values below this node, stopping once there are `cap` of them, and adding
+    /// the number of nodes looked at to `visited`.
+    pub fn count_at_most(&self, cap: usize, visited: &mut usize) -> usize {
+        count_at_most(self, cap, visited)
+    }
+
+    /// Find the highest nodes below this one, whose full key is `path`, that are at least `end`
+    /// nibbles deep, along with the keys and values that end less than `end` nibbles deep.
+    pub fn prefix_groups<'a>(
+        &'a self,
+        path: &NibbleVec,
+        end: usize,
+        groups: &mut Vec<(Vec<u8>, SubTrie<'a, K, V>)>,
+        short: &mut Vec<(&'a K, &'a V)>,
+    ) {
+        prefix_groups(self, path, end, groups, short)
+    }
 }
 
 macro_rules! get_func {
@@ -50,7 +227,7 @@ macro_rules! get_func {
         trie_type: $trie_type:ty,
         mutability: $($mut_:tt)*
     ) => {id!{
-        fn $name<'a, K, V>(trie: $trie_type, nv: &NibbleVec) -> Option<$trie_type> {
+        fn $name<'a, K, V>(trie: $trie_type, nv: KeyRef) -> Option<$trie_type> {
             if nv.len() == 0 {
                 return Some(trie);
             }
@@ -62,6 +239,7 @@ macro_rules! get_func {
                 let bucket = nv.get(depth) as usize;
                 let current = prev;
                 if let Some(ref $($mut_)* child) = current.children[bucket] {
+                    record!(nodes_visited += 1);
                     match match_keys(depth, nv, &child.key) {
                         KeyMatch::Full => {
                             return Some(child);
@@ -85,6 +263,63 @@ macro_rules! get_func {
 get_func!(name: iterative_get, trie_type: &'a TrieNode<K, V>, mutability: );
 get_func!(name: iterative_get_mut, trie_type: &'a mut TrieNode<K, V>, mutability: mut);
 
+fn iterative_get_path<'a, K, V>(
+    trie: &'a TrieNode<K, V>,
+    nv: &NibbleVec,
+    path: &mut Vec<u8>,
+) -> Option<&'a TrieNode<K, V>> {
+    let mut node = trie;
+    let mut depth = 0;
+    while depth < nv.len() {
+        let bucket = nv.get(depth);
+        let child = node.children[bucket as usize].as_ref()?;
+        match match_keys(depth, nv, &child.key) {
+            KeyMatch::Full | KeyMatch::SecondPrefix => {
+                path.push(bucket);
+                depth += child.key.len();
+                node = child;
+            }
+            _ => return None,
+        }
+    }
+    Some(node)
+}
+
+// Find the parent of the node for `nv`, and the node's bucket within it.
+// The root has no parent, so is returned as-is, with no bucket.
+fn iterative_get_parent_mut<'a, K, V>(
+    trie: &'a mut TrieNode<K, V>,
+    nv: &NibbleVec,
+) -> Option<(&'a mut TrieNode<K, V>, Option<usize>)> {
+    if nv.is_empty() {
+        return Some((trie, None));
+    }
+
+    let mut prev = trie;
+    let mut depth = 0;
+
+    loop {
+        let bucket = nv.get(depth) as usize;
+        let current = prev;
+        let child_len = match current.children[bucket] {
+            Some(ref child) => match match_keys(depth, nv, &child.key) {
+                KeyMatch::Full => None,
+                KeyMatch::SecondPrefix => Some(child.key.len()),
+                _ => return None,
+            },
+            None => return None,
+        };
+
+        match child_len {
+            None => return Some((current, Some(bucket))),
+            Some(len) => {
+                depth += len;
+                prev = current.children[bucket].as_mut().unwrap();
+            }
+        }
+    }
+}
+
 fn iterative_insert<K, V>(
     trie: &mut TrieNode<K, V>,
     key: K,
@@ -105,6 +340,7 @@ where
         let bucket = nv.get(depth) as usize;
         let current = prev;
         if let Some(ref mut child) = current.children[bucket] {
+            record!(nodes_visited += 1);
             match match_keys(depth, &nv, &child.key) {
                 KeyMatch::Full => {
                     return child.replace_value(key, value);
@@ -115,6 +351,7 @@ where
 
                     // Insert the new key below the prefix node.
                     let new_key = nv.split(depth + idx);
+                    record!(nibble_vec_allocations += 1);
                     let new_key_bucket = new_key.get(0) as usize;
 
                     child.add_child(
@@ -136,6 +373,7 @@ where
             }
         } else {
             let node_key = nv.split(depth);
+            record!(nibble_vec_allocations += 1);
             current.add_child(
                 bucket,
                 Box::new(TrieNode::with_key_value(node_key, key, value)),
@@ -146,16 +384,16 @@ where
 }
 
 // TODO: clean this up and make it iterative.
-fn recursive_remove<K, Q: ?Sized, V>(trie: &mut TrieNode<K, V>, key: &Q) -> Option<V>
+//
+// The value is taken from the node for `nv` by `take`. If it doesn't take anything, the trie is
+// left unchanged.
+fn recursive_remove<K, V, F>(trie: &mut TrieNode<K, V>, nv: &NibbleVec, take: F) -> Option<V>
 where
     K: TrieKey,
-    K: Borrow<Q>,
-    Q: TrieKey,
+    F: FnOnce(&mut TrieNode<K, V>) -> Option<V>,
 {
-    let nv = key.encode();
-
     if nv.len() == 0 {
-        return trie.take_value(key);
+        return take(trie);
     }
 
     let bucket = nv.get(0) as usize;
@@ -164,9 +402,14 @@ where
 
     match child {
         Some(mut child) => {
-            match match_keys(0, &nv, &child.key) {
+            record!(nodes_visited += 1);
+            match match_keys(0, nv, &child.key) {
                 KeyMatch::Full => {
-                    let result = child.take_value(key);
+                    let result = take(&mut child);
+                    if result.is_none() {
+                        trie.add_child(bucket, child);
+                        return None;
+                    }
                     if child.child_count != 0 {
                         // If removing this node's value has made it a value-less node with a
                         // single child, then merge its child.
@@ -181,7 +424,7 @@ where
                 }
                 KeyMatch::SecondPrefix => {
                     let depth = child.key.len();
-                    rec_remove(trie, child, bucket, key, depth, &nv)
+                    rec_remove(trie, child, bucket, take, depth, nv)
                 }
                 KeyMatch::FirstPrefix | KeyMatch::Partial(_) => {
                     trie.add_child(bucket, child);
@@ -201,23 +444,24 @@ where
 
     // Join the child's key onto the existing one.
     child.key = trie.key.clone().join(&child.key);
+    record!(merges += 1);
+    record!(nibble_vec_allocations += 1);
 
     child
 }
 
 // Tail-recursive remove function used by `recursive_remove`.
-fn rec_remove<K, Q: ?Sized, V>(
+fn rec_remove<K, V, F>(
     parent: &mut TrieNode<K, V>,
     mut middle: Box<TrieNode<K, V>>,
     prev_bucket: usize,
-    key: &Q,
+    take: F,
     depth: usize,
     nv: &NibbleVec,
 ) -> Option<V>
 where
     K: TrieKey,
-    K: Borrow<Q>,
-    Q: TrieKey,
+    F: FnOnce(&mut TrieNode<K, V>) -> Option<V>,
 {
     let bucket = nv.get(depth) as usize;
 
@@ -227,9 +471,14 @@ where
     match child {
         Some(mut child) => {
             let middle = parent.children[prev_bucket].as_mut().unwrap();
+            record!(nodes_visited += 1);
             match match_keys(depth, nv, &child.key) {
                 KeyMatch::Full => {
-                    let result = child.take_value(key);
+                    let result = take(&mut child);
+                    if result.is_none() {
+                        middle.add_child(bucket, child);
+                        return None;
+                    }
 
                     // If this node has children, keep it.
                     if child.child_count != 0 {
@@ -252,7 +501,7 @@ where
                 }
                 KeyMatch::SecondPrefix => {
                     let new_depth = depth + child.key.len();
-                    rec_remove(middle, child, bucket, key, new_depth, nv)
+                    rec_remove(middle, child, bucket, take, new_depth, nv)
                 }
                 KeyMatch::FirstPrefix | KeyMatch::Partial(_) => {
                     middle.add_child(bucket, child);
@@ -264,45 +513,506 @@ where
     }
 }
 
-fn get_ancestor<'a, K, V>(
-    trie: &'a TrieNode<K, V>,
+// State for `batch_remove`: the key currently being removed, and how many nibbles it shares
+// with the key removed before it.
+struct RemoveBatch<K, I> {
+    keys: I,
+    current: Option<(K, NibbleVec)>,
+    shared: usize,
+    removed: usize,
+}
+
+impl<K, I> RemoveBatch<K, I>
+where
+    K: TrieKey,
+    I: Iterator<Item = K>,
+{
+    fn new(mut keys: I) -> Self {
+        let current = keys.next().map(|k| {
+            let nv = k.encode();
+            (k, nv)
+        });
+        RemoveBatch {
+            keys,
+            current,
+            shared: 0,
+            removed: 0,
+        }
+    }
+
+    fn advance(&mut self) {
+        let next = self.keys.next().map(|k| {
+            let nv = k.encode();
+            (k, nv)
+        });
+        self.shared = match (&self.current, &next) {
+            (&Some((_, ref prev)), &Some((_, ref nv))) => common_prefix_len(prev, nv),
+            _ => 0,
+        };
+        self.current = next;
+    }
+}
+
+fn common_prefix_len(first: &NibbleVec, second: &NibbleVec) -> usize {
+    match match_keys(0, first, second) {
+        KeyMatch::Partial(idx) => idx,
+        KeyMatch::FirstPrefix | KeyMatch::Full => first.len(),
+        KeyMatch::SecondPrefix => second.len(),
+    }
+}
+
+// Remove all the keys from `batch` that lie below `trie`, which has depth `depth`.
+//
+// The current key always lies below `trie` when this is called. Rather than returning to the
+// root after each key, we stay at the deepest node that the next key shares with the previous
+// one, so sorted input only descends each edge once. Child nodes are fixed up (deleted or
+// merged) once, on the way back up, rather than after every removal. For unsorted input this
+// degrades gracefully into one descent per key.
+fn batch_remove<K, V, I>(trie: &mut TrieNode<K, V>, depth: usize, batch: &mut RemoveBatch<K, I>)
+where
+    K: TrieKey,
+    I: Iterator<Item = K>,
+{
+    loop {
+        let child_depth = match batch.current {
+            None => return,
+            Some((ref key, ref nv)) if nv.len() == depth => {
+                if trie.take_value(key).is_some() {
+                    batch.removed += 1;
+                }
+                None
+            }
+            Some((_, ref nv)) => {
+                let bucket = nv.get(depth) as usize;
+                trie.children[bucket].as_ref().and_then(|child| {
+                    match match_keys(depth, nv, &child.key) {
+                        KeyMatch::Full | KeyMatch::SecondPrefix => {
+                            Some((bucket, depth + child.key.len()))
+                        }
+                        KeyMatch::FirstPrefix | KeyMatch::Partial(_) => None,
+                    }
+                })
+            }
+        };
+
+        match child_depth {
+            Some((bucket, child_depth)) => {
+                batch_remove(trie.children[bucket].as_mut().unwrap(), child_depth, batch);
+                fix_child(trie, bucket);
+            }
+            // Either removed from this node, or not in the trie at all.
+            None => batch.advance(),
+        }
+
+        if batch.shared < depth {
+            return;
+        }
+    }
+}
+
+// Remove every value below `trie`, whose key ends at `depth`, that doesn't start with one of
+// `prefixes`. The prefixes all extend past `depth`, so the node's own value always goes.
+fn retain_prefixes<K, V>(trie: &mut TrieNode<K, V>, depth: usize, prefixes: &[&NibbleVec]) -> usize
+where
+    K: TrieKey,
+{
+    let mut removed = if trie.key_value.take().is_some() {
+        1
+    } else {
+        0
+    };
+    for bucket in 0..BRANCH_FACTOR {
+        let (keep, deeper, child_depth) = match trie.children[bucket] {
+            Some(ref child) => {
+                let mut keep = false;
+                let mut deeper = vec![];
+                for &prefix in prefixes.iter().filter(|p| p.get(depth) as usize == bucket) {
+                    match match_keys(depth, prefix, &child.key) {
+                        KeyMatch::Full | KeyMatch::FirstPrefix => keep = true,
+                        KeyMatch::SecondPrefix => deeper.push(prefix),
+                        KeyMatch::Partial(_) => (),
+                    }
+                }
+                (keep, deeper, depth + child.key.len())
+            }
+            None => continue,
+        };
+
+        if keep {
+            continue;
+        } else if deeper.is_empty() {
+            removed += trie
+                .take_child(bucket)
+                .map_or(0, |child| child.compute_size());
+        } else {
+            let child = trie.children[bucket].as_mut().unwrap();
+            removed += retain_prefixes(child, child_depth, &deeper);
+            fix_child(trie, bucket);
+        }
+    }
+    removed
+}
+
+// Merge a value-less, non-root node with its only child.
+fn merge_single_child<K, V>(trie: &mut TrieNode<K, V>)
+where
+    K: TrieKey,
+{
+    if !trie.key.is_empty() && trie.key_value.is_none() && trie.child_count == 1 {
+        *trie = *get_merge_child(trie);
+    }
+}
+
+// Restore the trie invariants for a child node that may have lost values below it.
+fn fix_child<K, V>(trie: &mut TrieNode<K, V>, bucket: usize)
+where
+    K: TrieKey,
+{
+    let (has_value, child_count) = match trie.children[bucket] {
+        Some(ref child) => (child.key_value.is_some(), child.child_count),
+        None => return,
+    };
+    if has_value || child_count > 1 {
+        return;
+    }
+    let mut child = trie.take_child(bucket).unwrap();
+    if child_count == 1 {
+        trie.add_child(bucket, get_merge_child(&mut child));
+    }
+}
+
+// Detach the node for `nv` (which is at least `depth` long), merging any nodes left with a
+// single child along the way.
+fn recursive_remove_subtrie<K, V>(
+    trie: &mut TrieNode<K, V>,
+    depth: usize,
     nv: &NibbleVec,
-) -> Option<(&'a TrieNode<K, V>, usize)>
+) -> Option<Box<TrieNode<K, V>>>
 where
     K: TrieKey,
 {
-    if nv.len() == 0 {
-        return trie.as_value_node().map(|node| (node, 0));
+    let bucket = nv.get(depth) as usize;
+    let child_depth = match trie.children[bucket] {
+        Some(ref child) => match match_keys(depth, nv, &child.key) {
+            KeyMatch::Full => return trie.take_child(bucket),
+            KeyMatch::SecondPrefix => depth + child.key.len(),
+            KeyMatch::FirstPrefix | KeyMatch::Partial(_) => return None,
+        },
+        None => return None,
+    };
+
+    let removed =
+        recursive_remove_subtrie(trie.children[bucket].as_mut().unwrap(), child_depth, nv);
+    if removed.is_some() {
+        fix_child(trie, bucket);
+    }
+    removed
+}
+
+// Find the node whose keys are the proper extensions of `nv` below `trie` (whose key ends at
+// `depth`), and pop the first or last of them.
+fn pop_prefix<K, V>(
+    trie: &mut TrieNode<K, V>,
+    depth: usize,
+    nv: &NibbleVec,
+    last: bool,
+) -> Option<(K, V)>
+where
+    K: TrieKey,
+{
+    if depth == nv.len() {
+        return pop_below(trie, last);
+    }
+    let bucket = nv.get(depth) as usize;
+    let popped = {
+        let child = trie.children[bucket].as_mut()?;
+        match match_keys(depth, nv, &child.key) {
+            KeyMatch::Full => pop_below(child, last),
+            // The prefix ends part way along the child's key, so the child's own key is one
+            // of the extensions.
+            KeyMatch::FirstPrefix => pop_from(child, last),
+            KeyMatch::SecondPrefix => {
+                let child_depth = depth + child.key.len();
+                pop_prefix(child, child_depth, nv, last)
+            }
+            KeyMatch::Partial(_) => None,
+        }
+    };
+    if popped.is_some() {
+        fix_child(trie, bucket);
+    }
+    popped
+}
+
+// Pop the first or last key and value below `trie`, including its own.
+fn pop_from<K, V>(trie: &mut TrieNode<K, V>, last: bool) -> Option<(K, V)>
+where
+    K: TrieKey,
+{
+    if last {
+        if let Some(popped) = pop_below(trie, last) {
+            return Some(popped);
+        }
+    }
+    match trie.key_value.take() {
+        Some(kv) => Some((kv.key, kv.value)),
+        None if !last => pop_below(trie, last),
+        None => None,
+    }
+}
+
+// Pop the first or last key and value below `trie`, excluding its own.
+//
+// A child may be an empty leaf, as `SubTrieMut::remove` leaves behind, so this carries on to
+// the next child until one has a value to pop.
+fn pop_below<K, V>(trie: &mut TrieNode<K, V>, last: bool) -> Option<(K, V)>
+where
+    K: TrieKey,
+{
+    for i in 0..BRANCH_FACTOR {
+        let bucket = if last { BRANCH_FACTOR - 1 - i } else { i };
+        let popped = match trie.children[bucket] {
+            Some(ref mut child) => pop_from(child, last),
+            None => continue,
+        };
+        if popped.is_some() {
+            fix_child(trie, bucket);
+            return popped;
+        }
+    }
+    None
+}
+
+// Split the borrow of `trie` into its value and each child, handing each target the value it
+// needs. As the targets are sorted, each child's targets are contiguous, and in bucket order.
+fn get_many_mut<'a, K, V, Q>(
+    trie: &'a mut TrieNode<K, V>,
+    depth: usize,
+    mut targets: &[(usize, &NibbleVec, &Q)],
+    values: &mut [Option<&'a mut V>],
+) where
+    K: TrieKey + Borrow<Q>,
+    Q: ?Sized + TrieKey,
+{
+    let TrieNode {
+        ref mut key_value,
+        ref mut children,
+        ..
+    } = *trie;
+
+    // A target ending at this node would sort before all the others.
+    if let Some(&(idx, nv, key)) = targets.first() {
+        if nv.len() == depth {
+            values[idx] = key_value.as_mut().map(|kv| {
+                check_keys(kv.key.borrow(), key);
+                &mut kv.value
+            });
+            targets = &targets[1..];
+        }
     }
 
+    let mut buckets = children.iter_mut().enumerate();
+    while let Some(&(_, nv, _)) = targets.first() {
+        let bucket = nv.get(depth) as usize;
+        let group_len = targets
+            .iter()
+            .take_while(|&&(_, nv, _)| nv.get(depth) as usize == bucket)
+            .count();
+        let (group, rest) = targets.split_at(group_len);
+        targets = rest;
+
+        let child = match buckets.find(|&(i, _)| i == bucket) {
+            Some((_, &mut Some(ref mut child))) => child,
+            _ => continue,
+        };
+        let below: Vec<_> = group
+            .iter()
+            .filter(|&&(_, nv, _)| match match_keys(depth, nv, &child.key) {
+                KeyMatch::Full | KeyMatch::SecondPrefix => true,
+                KeyMatch::FirstPrefix | KeyMatch::Partial(_) => false,
+            })
+            .cloned()
+            .collect();
+        let child_depth = depth + child.key.len();
+        get_many_mut(child, child_depth, &below, values);
+    }
+}
+
+// Find the first node with a value whose key is at least `nv`.
+fn next_after<'a, K, V>(trie: &'a TrieNode<K, V>, nv: &NibbleVec) -> Option<&'a TrieNode<K, V>>
+where
+    K: TrieKey,
+{
+    // The nodes along `nv`, and the buckets that were followed out of them.
+    // The children in later buckets hold greater keys.
+    let mut path = vec![];
     let mut prev = trie;
-    // The ancestor is such that all nodes upto and including `prev` have
-    // already been considered.
-    let mut ancestor = prev.as_value_node();
     let mut depth = 0;
 
     loop {
+        if depth == nv.len() {
+            match leftmost_value(prev) {
+                Some(node) => return Some(node),
+                None => break,
+            }
+        }
+
         let bucket = nv.get(depth) as usize;
         let current = prev;
+        path.push((current, bucket));
         if let Some(ref child) = current.children[bucket] {
             match match_keys(depth, nv, &child.key) {
-                KeyMatch::Full => {
-                    return child
-                        .as_value_node()
-                        .map(|node| (node, depth + node.key.len()))
-                        .or_else(|| ancestor.map(|anc| (anc, depth)));
-                }
-                KeyMatch::FirstPrefix | KeyMatch::Partial(_) => {
-                    return ancestor.map(|anc| (anc, depth));
+                // All of the child's keys start with `nv`.
+                KeyMatch::Full | KeyMatch::FirstPrefix => {
+                    if let Some(node) = leftmost_value(child) {
+                        return Some(node);
+                    }
+                    break;
                 }
                 KeyMatch::SecondPrefix => {
                     depth += child.key.len();
-                    ancestor = child.as_value_node().or(ancestor);
                     prev = child;
                 }
+                // The child's keys are all greater than `nv`, or all less.
+                KeyMatch::Partial(idx) => {
+                    if child.key.get(idx) > nv.get(depth + idx) {
+                        if let Some(node) = leftmost_value(child) {
+                            return Some(node);
+                        }
+                    }
+                    break;
+                }
             }
         } else {
-            return ancestor.map(|anc| (anc, depth));
+            break;
+        }
+    }
+
+    // Everything below the last node visited is less than `nv`, so move to the next subtrie.
+    for (node, bucket) in path.into_iter().rev() {
+        let later = node.children[bucket + 1..]
+            .iter()
+            .filter_map(|c| c.as_ref());
+        for child in later {
+            if let Some(node) = leftmost_value(child) {
+                return Some(node);
+            }
+        }
+    }
+    None
+}
+
+// Find a node with a value that has a value below it, and the first such value.
+fn find_prefix_violation<K, V>(trie: &TrieNode<K, V>) -> Option<NodePair<'_, K, V>>
+where
+    K: TrieKey,
+{
+    let mut stack = vec![trie];
+    while let Some(node) = stack.pop() {
+        let children = node.children.iter().filter_map(|c| c.as_ref());
+        if node.key_value.is_some() {
+            if let Some(below) = children.clone().find_map(|child| leftmost_value(child)) {
+                return Some((node, below));
+            }
+        }
+        // Visit the children in order, so the first violation in iteration order is found.
+        stack.extend(children.rev().map(|child| &**child));
+    }
+    None
+}
+
+// Determine whether `nv` is a proper prefix of a key in the trie, or has one as a prefix.
+fn has_prefix_conflict<K, V>(trie: &TrieNode<K, V>, nv: &NibbleVec) -> bool
+where
+    K: TrieKey,
+{
+    let mut prev = trie;
+    let mut depth = 0;
+
+    loop {
+        // Children may be empty leaves, so look for values below them rather than children.
+        if depth == nv.len() {
+            return prev
+                .children
+                .iter()
+                .flatten()
+                .any(|child| leftmost_value(child).is_some());
+        }
+        if prev.key_value.is_some() {
+            return true;
+        }
+
+        let bucket = nv.get(depth) as usize;
+        let current = prev;
+        match current.children[bucket] {
+            Some(ref child) => match match_keys(depth, nv, &child.key) {
+                KeyMatch::Full | KeyMatch::SecondPrefix => {
+                    depth += child.key.len();
+                    prev = child;
+                }
+                KeyMatch::FirstPrefix => return leftmost_value(child).is_some(),
+                KeyMatch::Partial(_) => return false,
+            },
+            None => return false,
+        }
+    }
+}
+
+// Find the node with the least key in this subtrie that has a value.
+//
+// A child may be an empty leaf, as `SubTrieMut::remove` leaves behind, so the first child
+// that exists doesn't always have a value below it.
+fn leftmost_value<K, V>(trie: &TrieNode<K, V>) -> Option<&TrieNode<K, V>> {
+    if trie.key_value.is_some() {
+        return Some(trie);
+    }
+    trie.children
+        .iter()
+        .flatten()
+        .find_map(|child| leftmost_value(child))
+}
+
+fn get_ancestor<'a, K, V>(
+    trie: &'a TrieNode<K, V>,
+    nv: KeyRef,
+) -> Option<(&'a TrieNode<K, V>, usize)>
+where
+    K: TrieKey,
+{
+    let mut ancestor = None;
+    for_each_ancestor(trie, nv, |node, depth| ancestor = Some((node, depth)));
+    ancestor
+}
+
+// Call `visit` on each node with a value whose key is a prefix of `nv` (including `nv`
+// itself), from the root down, along with the length of its key.
+fn for_each_ancestor<'a, K, V, F>(trie: &'a TrieNode<K, V>, nv: KeyRef, mut visit: F)
+where
+    K: TrieKey,
+    F: FnMut(&'a TrieNode<K, V>, usize),
+{
+    if let Some(node) = trie.as_value_node() {
+        visit(node, 0);
+    }
+
+    let mut prev = trie;
+    let mut depth = 0;
+    while depth < nv.len() {
+        let bucket = nv.get(depth) as usize;
+        let child = match prev.children[bucket] {
+            Some(ref child) => child,
+            None => return,
+        };
+        record!(nodes_visited += 1);
+        match match_keys(depth, nv, &child.key) {
+            KeyMatch::Full | KeyMatch::SecondPrefix => {
+                depth += child.key.len();
+                if let Some(node) = child.as_value_node() {
+                    visit(node, depth);
+                }
+                prev = child;
+            }
+            KeyMatch::FirstPrefix | KeyMatch::Partial(_) => return,
         }
     }
 }
@@ -389,3 +1099,102 @@ fn get_raw_descendant<'a, K, V>(
         }
     }
 }
+
+fn branch_summaries<'a, K, V>(
+    trie: &'a TrieNode<K, V>,
+    counts: Option<&AggregateNode<Count>>,
+    path: &NibbleVec,
+    start: usize,
+    end: usize,
+    summaries: &mut Vec<BranchSummary<'a, V>>,
+) where
+    K: TrieKey,
+{
+    // Every key below a node at or past the boundary shares the same continuation. The node
+    // may have no keys below it at all, if it's a leaf left empty by `SubTrieMut::remove`.
+    if path.len() >= end {
+        let count = counts.map_or_else(|| trie.compute_size(), |counts| counts.aggregate().0);
+        if count > 0 {
+            summaries.push(BranchSummary {
+                continuation: nibble_range(path, start, end),
+                count,
+                value: if path.len() == end {
+                    trie.value()
+                } else {
+                    None
+                },
+            });
+        }
+        return;
+    }
+
+    // Keys ending before the boundary get a summary of their own, with a short continuation.
+    if path.len() > start {
+        if let Some(value) = trie.value() {
+            summaries.push(BranchSummary {
+                continuation: nibble_range(path, start, path.len()),
+                count: 1,
+                value: Some(value),
+            });
+        }
+    }
+
+    for (bucket, child) in trie.children.iter().enumerate() {
+        if let Some(ref child) = *child {
+            let child_counts = counts.and_then(|counts| counts.child(bucket));
+            let child_path = path.clone().join(&child.key);
+            branch_summaries(child, child_counts, &child_path, start, end, summaries);
+        }
+    }
+}
+
+fn count_at_most<K, V>(trie: &TrieNode<K, V>, cap: usize, visited: &mut usize) -> usize {
+    if cap == 0 {
+        return 0;
+    }
+    *visited += 1;
+    let mut count = if trie.key_value.is_some() { 1 } else { 0 };
+    for child in trie.children.iter().flatten() {
+        if count == cap {
+            break;
+        }
+        count += count_at_most(child, cap - count, visited);
+    }
+    count
+}
+
+fn prefix_groups<'a, K, V>(
+    trie: &'a TrieNode<K, V>,
+    path: &NibbleVec,
+    end: usize,
+    groups: &mut Vec<(Vec<u8>, SubTrie<'a, K, V>)>,
+    short: &mut Vec<(&'a K, &'a V)>,
+) where
+    K: TrieKey,
+{
+    if path.len() >= end {
+        // A node can have no values below it: the root of an empty trie, or a leaf left empty
+        // by `SubTrieMut::remove`. There's nothing in it to group.
+        if leftmost_value(trie).is_some() {
+            groups.push((nibble_range(path, 0, end), trie.as_subtrie(path.clone())));
+        }
+        return;
+    }
+
+    if let Some(ref kv) = trie.key_value {
+        short.push((&kv.key, &kv.value));
+    }
+
+    for child in trie.children.iter().filter_map(|c| c.as_ref()) {
+        let child_path = path.clone().join(&child.key);
+        prefix_groups(child, &child_path, end, groups, short);
+    }
+}
+
+fn nibble_range(nv: &NibbleVec, start: usize, end: usize) -> Vec<u8> {
+    let mut range = NibbleVec::new();
+    for i in start..end {
+        range.push(nv.get(i));
+    }
+    range.into_bytes()
+}
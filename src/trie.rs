@@ -1,7 +1,12 @@
+use augmented::{check_counts, count_prefix, descendant_counts, reset_counts, sync_counts};
+use iter::Iter;
+use keys::{compare_keys, match_keys, with_encoding, KeyMatch};
 use std::borrow::Borrow;
+use std::error;
+use std::fmt;
 use traversal::DescendantResult::*;
 use trie_node::TrieNode;
-use {NibbleVec, SubTrie, SubTrieMut, Trie, TrieCommon, TrieKey};
+use {BranchSummary, NibbleVec, PrefixConflict, SubTrie, SubTrieMut, Trie, TrieCommon, TrieKey};
 
 impl<K, V> Trie<K, V>
 where
@@ -12,6 +17,9 @@ where
         Trie {
             length: 0,
             node: TrieNode::new(),
+            counts: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         }
     }
 
@@ -24,10 +32,20 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        let key_fragments = key.encode();
-        self.node
-            .get(&key_fragments)
-            .and_then(|t| t.value_checked(key))
+        metrics_scope!(self);
+        with_encoding(key, |nv| self.node.get(nv)).and_then(|t| t.value_checked(key))
+    }
+
+    /// Determine whether the trie contains a value for the given key.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.get(key).is_some()
     }
 
     /// Fetch a mutable reference to the given key's corresponding value, if any.
@@ -39,16 +57,152 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        let key_fragments = key.encode();
-        self.node
-            .get_mut(&key_fragments)
-            .and_then(|t| t.value_checked_mut(key))
+        metrics_scope!(self);
+        let node = &mut self.node;
+        with_encoding(key, move |nv| node.get_mut(nv)).and_then(|t| t.value_checked_mut(key))
+    }
+
+    /// Fetch mutable references to the values for several keys at once.
+    ///
+    /// Return `None` if any of the keys is missing, or if any two of them are the same.
+    /// The trie is only traversed once, however many keys there are.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_many_mut<Q, const N: usize>(&mut self, keys: [&Q; N]) -> Option<[&mut V; N]>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let encoded: Vec<NibbleVec> = keys.iter().map(|key| key.encode()).collect();
+        let mut targets: Vec<_> = keys
+            .iter()
+            .zip(&encoded)
+            .enumerate()
+            .map(|(idx, (&key, nv))| (idx, nv, key))
+            .collect();
+        targets.sort_by_cached_key(|&(_, nv, _)| nibbles(nv));
+        if targets.windows(2).any(|pair| pair[0].1 == pair[1].1) {
+            return None;
+        }
+
+        let mut values = [(); N].map(|_| None);
+        self.node.get_many_mut(&targets, &mut values);
+        if values.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(values.map(Option::unwrap))
+    }
+
+    /// Call `f` on the given key's corresponding value, if any, and return its result.
+    ///
+    /// This is the recommended way to read a value in a trie that's embedded in another
+    /// struct. Unlike the reference from `get`, the result doesn't borrow the trie, so the
+    /// rest of the struct can be changed straight away. For example, this doesn't compile,
+    /// because `name` keeps `self` borrowed while `self.log` is changed:
+    ///
+    /// ```compile_fail,E0502
+    /// # use radix_trie::Trie;
+    /// struct Users {
+    ///     names: Trie<u32, String>,
+    ///     log: Vec<String>,
+    /// }
+    ///
+    /// impl Users {
+    ///     fn names(&self) -> &Trie<u32, String> {
+    ///         &self.names
+    ///     }
+    ///
+    ///     fn greet(&mut self, id: u32) {
+    ///         if let Some(name) = self.names().get(&id) {
+    ///             self.log.push(format!("greeted {}", id));
+    ///             println!("hello {}", name);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// whereas scoping the borrow to a closure does:
+    ///
+    /// ```
+    /// # use radix_trie::Trie;
+    /// # struct Users {
+    /// #     names: Trie<u32, String>,
+    /// #     log: Vec<String>,
+    /// # }
+    /// impl Users {
+    ///     fn names(&self) -> &Trie<u32, String> {
+    ///         &self.names
+    ///     }
+    ///
+    ///     fn greet(&mut self, id: u32) {
+    ///         if let Some(greeting) = self.names().with_value(&id, |name| format!("hello {}", name)) {
+    ///             self.log.push(format!("greeted {}", id));
+    ///             println!("{}", greeting);
+    ///         }
+    ///     }
+    /// }
+    /// # let mut users = Users { names: Trie::new(), log: vec![] };
+    /// # users.names.insert(7, "ada".to_string());
+    /// # users.greet(7);
+    /// # assert_eq!(users.log, ["greeted 7"]);
+    /// ```
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn with_value<Q, F, R>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&V) -> R,
+    {
+        self.get(key).map(f)
+    }
+
+    /// Call `f` on a mutable reference to the given key's corresponding value, if any, and
+    /// return its result.
+    ///
+    /// See `with_value`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn with_value_mut<Q, F, R>(&mut self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&mut V) -> R,
+    {
+        self.get_mut(key).map(f)
+    }
+
+    /// Call `f` on the subtrie for the given key, if the key is in the trie, and return its
+    /// result.
+    ///
+    /// See `with_value` and `subtrie`.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn with_subtrie<Q, F, R>(&self, prefix: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(SubTrie<'_, K, V>) -> R,
+    {
+        self.subtrie(prefix).map(f)
     }
 
     /// Insert the given key-value pair, returning any previous value associated with the key.
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        metrics_scope!(self);
         let key_fragments = key.encode();
-        let result = self.node.insert(key, value, key_fragments);
+        let result = match self.counts {
+            Some(_) => {
+                let result = self.node.insert(key, value, key_fragments.clone());
+                sync_counts(&mut self.counts, &self.node, &key_fragments);
+                result
+            }
+            None => self.node.insert(key, value, key_fragments),
+        };
         if result.is_none() {
             self.length += 1;
         }
@@ -64,10 +218,96 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
+        metrics_scope!(self);
         let removed = self.node.remove(key);
         if removed.is_some() {
             self.length -= 1;
+            if self.counts.is_some() {
+                sync_counts(&mut self.counts, &self.node, &key.encode());
+            }
+        }
+        removed
+    }
+
+    /// Remove the value associated with the given key, if `pred` returns true for it.
+    ///
+    /// Unlike a `get` followed by a `remove`, this only encodes the key and descends the trie
+    /// once. `pred` is only called if the key is present, which tells an absent key apart from
+    /// one that was kept. If `pred` returns false, the trie is left unchanged.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn remove_if<Q, F>(&mut self, key: &Q, pred: F) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnOnce(&V) -> bool,
+    {
+        metrics_scope!(self);
+        let removed = self.node.remove_if(key, pred);
+        if removed.is_some() {
+            self.length -= 1;
+            if self.counts.is_some() {
+                sync_counts(&mut self.counts, &self.node, &key.encode());
+            }
+        }
+        removed
+    }
+
+    /// Remove all of the given keys, returning the number of keys that were actually removed.
+    ///
+    /// This is equivalent to calling `remove` for each key, but much faster when the keys
+    /// are sorted (in the order of their encodings, which is the trie's iteration order),
+    /// as keys with shared prefixes don't have to repeat the descent from the root.
+    /// Unsorted keys work too, they just don't benefit from the fast path.
+    pub fn remove_all<I>(&mut self, keys: I) -> usize
+    where
+        I: IntoIterator<Item = K>,
+    {
+        reset_counts(&mut self.counts);
+        let removed = self.node.remove_all(keys);
+        self.length -= removed;
+        removed
+    }
+
+    /// Remove every key that doesn't start with one of the given prefixes, returning the
+    /// number of keys removed.
+    ///
+    /// The prefixes are encoded once, and the trie is walked once: subtries below a prefix
+    /// are kept, and subtries that no prefix leads into are dropped, without looking at the
+    /// keys in either. Prefixes may overlap or repeat.
+    ///
+    /// The prefixes may be any borrowed form of the trie's key type, but TrieKey on the
+    /// borrowed form *must* match those for the key type
+    pub fn retain_prefixes<'a, I, Q>(&mut self, prefixes: I) -> usize
+    where
+        I: IntoIterator<Item = &'a Q>,
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey + 'a,
+    {
+        let mut encoded: Vec<NibbleVec> = prefixes.into_iter().map(|p| p.encode()).collect();
+        encoded.sort_by(compare_keys);
+
+        // A prefix that extends another comes after it, and adds nothing.
+        let mut minimal: Vec<&NibbleVec> = vec![];
+        for prefix in &encoded {
+            let redundant = minimal.last().is_some_and(|last| {
+                matches!(
+                    match_keys(0, *last, prefix),
+                    KeyMatch::Full | KeyMatch::FirstPrefix
+                )
+            });
+            if !redundant {
+                minimal.push(prefix);
+            }
+        }
+        if minimal.first().is_some_and(|prefix| prefix.is_empty()) {
+            return 0;
         }
+
+        reset_counts(&mut self.counts);
+        let removed = self.node.retain_prefixes(&minimal);
+        self.length -= removed;
         removed
     }
 
@@ -85,10 +325,24 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
+        metrics_scope!(self);
         let key_fragments = key.encode();
         self.node
             .get(&key_fragments)
-            .map(|node| node.as_subtrie(key_fragments))
+            .map(|node| self.counted(node.as_subtrie(key_fragments)))
+    }
+
+    /// Get the child subtrie of the root whose key starts with `nibble` (`0..16`).
+    ///
+    /// This steps down one node, as for `SubTrie::child`.
+    pub fn child(&self, nibble: u8) -> Option<SubTrie<'_, K, V>> {
+        let child = self.node.children.get(nibble as usize)?.as_ref()?;
+        Some(child.as_subtrie(self.node.key.clone().join(&child.key)))
+    }
+
+    /// Number of child subtries of the root.
+    pub fn child_count(&self) -> usize {
+        self.node.child_count
     }
 
     /// Fetch a mutable reference to the subtrie for a given key.
@@ -100,11 +354,86 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
+        reset_counts(&mut self.counts);
         let key_fragments = key.encode();
         let length_ref = &mut self.length;
         self.node
-            .get_mut(&key_fragments)
-            .map(move |node| node.as_subtrie_mut(key_fragments, length_ref))
+            .get_parent_mut(&key_fragments)
+            .map(move |(parent, bucket)| parent.as_subtrie_mut(bucket, key_fragments, length_ref))
+    }
+
+    /// Remove the subtrie for a given key, and return it as a new, independent trie.
+    ///
+    /// This is the moving counterpart of `SubTrie::to_trie`: the nodes are detached rather
+    /// than cloned, and the remaining trie is restructured as if each of the subtrie's keys
+    /// had been removed.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn remove_subtrie<Q>(&mut self, key: &Q) -> Option<Trie<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        reset_counts(&mut self.counts);
+        let key_fragments = key.encode();
+        if key_fragments.is_empty() {
+            let mut rest = Trie::new();
+            rest.counts = self.counts.clone();
+            return Some(::std::mem::replace(self, rest));
+        }
+        let subtrie = self
+            .node
+            .remove_subtrie(&key_fragments)?
+            .into_trie(key_fragments);
+        self.length -= subtrie.length;
+        Some(subtrie)
+    }
+
+    /// Remove and return the first key and value, in iteration order, among those whose keys
+    /// start with `prefix`, e.g. the oldest entry of a queue keyed by a prefix followed by a
+    /// big-endian sequence number.
+    ///
+    /// The key equal to `prefix` itself, if there is one, is never removed: only the keys that
+    /// extend the prefix with at least one more nibble count. The entry is found and removed
+    /// in one traversal, restructuring the trie as `remove` would.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn pop_prefix_min<Q>(&mut self, prefix: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.pop_prefix(prefix, false)
+    }
+
+    /// Remove and return the last key and value, in iteration order, among those whose keys
+    /// start with `prefix`.
+    ///
+    /// As for `pop_prefix_min`, the key equal to `prefix` itself is never removed.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn pop_prefix_max<Q>(&mut self, prefix: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.pop_prefix(prefix, true)
+    }
+
+    fn pop_prefix<Q>(&mut self, prefix: &Q, last: bool) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        reset_counts(&mut self.counts);
+        let popped = self.node.pop_prefix(&prefix.encode(), last);
+        if popped.is_some() {
+            self.length -= 1;
+        }
+        popped
     }
 
     /// Fetch a reference to the closest ancestor node of the given key.
@@ -122,13 +451,27 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        let mut key_fragments = key.encode();
-        self.node
-            .get_ancestor(&key_fragments)
-            .map(|(node, node_key_len)| {
-                key_fragments.split(node_key_len);
-                node.as_subtrie(key_fragments)
-            })
+        metrics_scope!(self);
+        self.ancestor_of(key.encode())
+    }
+
+    /// Fetch a reference to the closest ancestor node of the given encoded key, which may
+    /// have any number of nibbles.
+    ///
+    /// This is the low-level companion to `get_ancestor`, for keys that don't correspond to
+    /// a value of the key type, e.g. paths of hex digits with an odd length.
+    ///
+    /// Invariant: `result.is_some() => result.key_value.is_some()`.
+    pub fn ancestor_nibbles(&self, key: &NibbleVec) -> Option<SubTrie<'_, K, V>> {
+        self.ancestor_of(key.clone())
+    }
+
+    fn ancestor_of(&self, mut nv: NibbleVec) -> Option<SubTrie<'_, K, V>> {
+        metrics_scope!(self);
+        self.node.get_ancestor(&nv).map(|(node, node_key_len)| {
+            nv.split(node_key_len);
+            self.counted(node.as_subtrie(nv))
+        })
     }
 
     /// Fetch the closest ancestor *value* for a given key.
@@ -142,7 +485,50 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        self.get_ancestor(key).and_then(|t| t.node.value())
+        metrics_scope!(self);
+        with_encoding(key, |nv| self.node.get_ancestor(nv)).and_then(|(node, _)| node.value())
+    }
+
+    /// Find a key in the trie that's different from the given key but has the same encoding,
+    /// which would make `insert` panic.
+    ///
+    /// This is a way to test a candidate key against the trie first, e.g. to find out which
+    /// keys a broken `TrieKey` impl confuses.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn find_encoding_collision<Q>(&self, key: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        metrics_scope!(self);
+        with_encoding(key, |nv| self.node.get(nv))
+            .and_then(|node| node.key())
+            .filter(|stored| (*stored).borrow() != key)
+    }
+
+    /// Fetch the keys and values of every ancestor of the given key, including the key
+    /// itself if it's in the trie, from the shortest to the longest.
+    ///
+    /// This is the eager form of stepping through the ancestors with `get_ancestor`, found in
+    /// one pass.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get_path_values<Q>(&self, key: &Q) -> Vec<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        metrics_scope!(self);
+        let mut values = vec![];
+        with_encoding(key, |nv| {
+            self.node.for_each_ancestor(nv, |node, _| {
+                values.extend(node.key().zip(node.value()));
+            })
+        });
+        values
     }
 
     /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
@@ -155,7 +541,7 @@ where
         let mut nv = key.encode();
         let (ancestor_node, depth) = self.node.get_raw_ancestor(&nv);
         nv.split(depth);
-        ancestor_node.as_subtrie(nv)
+        self.counted(ancestor_node.as_subtrie(nv))
     }
 
     /// Fetch the closest descendant for a given key.
@@ -169,7 +555,23 @@ where
         K: Borrow<Q>,
         Q: TrieKey,
     {
-        let mut nv = key.encode();
+        metrics_scope!(self);
+        self.descendant_of(key.encode())
+    }
+
+    /// Fetch the subtrie of all the keys whose encoding starts with `prefix`, which may have
+    /// any number of nibbles.
+    ///
+    /// This is the low-level companion to `subtrie` and `get_raw_descendant`, for prefixes
+    /// that don't correspond to a value of the key type, e.g. the first three hex digits of
+    /// a hash. If the prefix ends part way along a node's key the subtrie starts at that
+    /// node, which still holds exactly the keys that start with the prefix.
+    pub fn subtrie_nibbles(&self, prefix: &NibbleVec) -> Option<SubTrie<'_, K, V>> {
+        self.descendant_of(prefix.clone())
+    }
+
+    fn descendant_of(&self, mut nv: NibbleVec) -> Option<SubTrie<'_, K, V>> {
+        metrics_scope!(self);
         self.node.get_raw_descendant(&nv).map(|desc| {
             let (node, prefix) = match desc {
                 NoModification(node) => (node, nv),
@@ -178,10 +580,124 @@ where
                     (node, nv.join(extension))
                 }
             };
-            node.as_subtrie(prefix)
+            self.counted(node.as_subtrie(prefix))
         })
     }
 
+    // Give a subtrie of this trie its length, if the trie counts the values below each node.
+    fn counted<'a>(&self, mut subtrie: SubTrie<'a, K, V>) -> SubTrie<'a, K, V> {
+        subtrie.length = count_prefix(self, &subtrie.prefix);
+        subtrie
+    }
+
+    /// Summarise the keys below `prefix`, grouped by their next `granularity_bytes` bytes.
+    ///
+    /// Unlike `children`, which follows the trie's nodes and so can jump many bytes at a time,
+    /// this splits the keys at fixed-width boundaries, for expanding the keyspace one level
+    /// at a time. Each continuation comes with the number of keys below it, and the value
+    /// stored at exactly that continuation, if any. Keys that end less than
+    /// `granularity_bytes` past the prefix get a summary of their own with a shorter
+    /// continuation, so every key strictly below `prefix` is counted exactly once.
+    ///
+    /// Summaries are in the order of their continuations. If the trie was built with
+    /// `TrieBuilder::count_subtrees`, the number of keys in each comes from its counts,
+    /// rather than by counting them.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn children_at<Q>(&self, prefix: &Q, granularity_bytes: usize) -> Vec<BranchSummary<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let nv = prefix.encode();
+        let start = nv.len();
+        let end = start + 2 * granularity_bytes;
+        let mut summaries = vec![];
+        let counts = descendant_counts(self, &nv);
+        if let Some(descendant) = self.descendant_of(nv) {
+            descendant.node.branch_summaries(
+                counts,
+                &descendant.prefix,
+                start,
+                end,
+                &mut summaries,
+            );
+        }
+        summaries
+    }
+
+    /// Split the trie into groups of keys that share their first `depth_bytes` bytes.
+    ///
+    /// Each group is the distinct prefix, paired with a subtrie of all the keys that start
+    /// with it. Where the prefix ends part way along a node's key, the subtrie is for that
+    /// node, so its own prefix is longer. Keys shorter than `depth_bytes` don't have a prefix
+    /// that long, so they aren't in any group: `short_keys` gives them separately.
+    ///
+    /// Groups are in the order of their prefixes.
+    pub fn group_by_prefix(&self, depth_bytes: usize) -> Vec<(Vec<u8>, SubTrie<'_, K, V>)> {
+        let mut groups = vec![];
+        self.node
+            .prefix_groups(&NibbleVec::new(), 2 * depth_bytes, &mut groups, &mut vec![]);
+        groups
+    }
+
+    /// Fetch the keys (and values) shorter than `depth_bytes` bytes, which `group_by_prefix`
+    /// leaves out, in iteration order.
+    pub fn short_keys(&self, depth_bytes: usize) -> Vec<(&K, &V)> {
+        let mut short = vec![];
+        self.node
+            .prefix_groups(&NibbleVec::new(), 2 * depth_bytes, &mut vec![], &mut short);
+        short
+    }
+
+    /// Fetch the first key and value whose key is greater than or equal to the given key.
+    ///
+    /// Keys are compared by their encodings, as in the trie's iteration order. The given key
+    /// doesn't have to be in the trie, which makes this useful for resuming iteration from
+    /// somewhere in the middle.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn next_after<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        self.node
+            .next_after(&key.encode())
+            .and_then(|node| node.key_value.as_ref())
+            .map(|kv| (&kv.key, &kv.value))
+    }
+
+    /// Determine whether no key in the trie is a proper prefix of another.
+    pub fn is_prefix_free(&self) -> bool {
+        self.find_prefix_violation().is_none()
+    }
+
+    /// Find a pair of keys where the first is a proper prefix of the second, if there is one.
+    pub fn find_prefix_violation(&self) -> Option<(&K, &K)> {
+        self.node
+            .find_prefix_violation()
+            .and_then(|(prefix, key)| prefix.key().and_then(|p| key.key().map(|k| (p, k))))
+    }
+
+    /// Insert a key-value pair, unless it would stop the trie from being prefix-free.
+    ///
+    /// If the key is a proper prefix of a key in the trie, or has one as a proper prefix, the
+    /// trie is left unchanged and the key and value are returned in the error. Otherwise,
+    /// this is the same as `insert`, and replaces any existing value for the key.
+    pub fn insert_prefix_free(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, PrefixConflict<K, V>> {
+        if self.node.has_prefix_conflict(&key.encode()) {
+            return Err(PrefixConflict { key, value });
+        }
+        Ok(self.insert(key, value))
+    }
+
     /// Take a function `f` and apply it to the value stored at `key`.
     ///
     /// If no value is stored at `key`, store `default`.
@@ -203,10 +719,42 @@ where
     #[doc(hidden)]
     pub fn check_integrity(&self) -> bool {
         let (ok, length) = self.node.check_integrity_recursive(&NibbleVec::new());
-        ok && length == self.length
+        ok && length == self.length && check_counts(self)
+    }
+}
+
+// The nibbles of `nv`, which sort in the trie's iteration order.
+fn nibbles(nv: &NibbleVec) -> Vec<u8> {
+    (0..nv.len()).map(|i| nv.get(i)).collect()
+}
+
+/// Formats the trie's contents as a map, in iteration order.
+impl<K, V> fmt::Debug for Trie<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(Iter::new(&self.node)).finish()
+    }
+}
+
+impl<K, V> fmt::Display for PrefixConflict<K, V>
+where
+    K: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key {:?} conflicts with a prefix-free trie", self.key)
     }
 }
 
+impl<K, V> error::Error for PrefixConflict<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+}
+
 impl<K, V> PartialEq for Trie<K, V>
 where
     K: TrieKey,
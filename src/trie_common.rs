@@ -26,6 +26,20 @@ where
         self.len() == 0
     }
 
+    /// Determine if the trie contains 0 key-value pairs, stopping at the first value found
+    /// rather than counting them all.
+    // Like the other methods, this takes `self` by value because it's implemented for references.
+    #[allow(clippy::wrong_self_convention)]
+    fn is_empty_fast(self) -> bool {
+        self.count_at_most(1) == 0
+    }
+
+    /// Count the key-value pairs in the trie, up to `cap`: the result is `cap` if there are
+    /// `cap` or more, and is found without looking any further.
+    fn count_at_most(self, cap: usize) -> usize {
+        self.trie_node().count_at_most(cap, &mut 0)
+    }
+
     /// Determine if the trie is a leaf node (has no children).
     fn is_leaf(self) -> bool {
         self.trie_node().child_count == 0
@@ -36,6 +50,29 @@ where
         Iter::new(self.trie_node())
     }
 
+    /// Return an iterator over the keys and values that have no other values stored
+    /// beneath them, i.e. the keys that aren't a prefix of any other key in the Trie.
+    fn iter_leaves(self) -> Leaves<'a, K, V> {
+        Leaves::new(self.trie_node())
+    }
+
+    /// Return an iterator over the keys and values that have other values stored beneath
+    /// them, i.e. the keys that are a prefix of some other key in the Trie.
+    fn iter_internal(self) -> Internal<'a, K, V> {
+        Internal::new(self.trie_node())
+    }
+
+    /// Return an iterator over the keys and values of the Trie, with each (full) key given
+    /// as the bytes of its encoding.
+    ///
+    /// The bytes are those that `TrieKey::encode_bytes` returns for the key, whatever the key
+    /// type, so this suits generic code that exports or hands the keys to other languages.
+    /// A key that `encode` gives an odd number of nibbles has its last byte padded with a
+    /// zero nibble.
+    fn iter_encoded(self) -> EncodedIter<'a, K, V> {
+        EncodedIter::new(self.iter())
+    }
+
     /// Return an iterator over the keys of the Trie.
     fn keys(self) -> Keys<'a, K, V> {
         Keys::new(self.iter())
@@ -100,8 +137,10 @@ impl<'a: 'b, 'b, K: 'a, V: 'a> TrieCommon<'a, K, V> for &'b SubTrie<'a, K, V>
 where
     K: TrieKey,
 {
+    /// Takes constant time if the trie was built with `TrieBuilder::count_subtrees`, and
+    /// the subtrie came straight from it; otherwise this counts the values.
     fn len(self) -> usize {
-        self.node.compute_size()
+        self.length.unwrap_or_else(|| self.node.compute_size())
     }
 
     fn children(self) -> Children<'a, K, V> {
@@ -115,7 +154,8 @@ where
     K: TrieKey,
 {
     fn trie_node(self) -> &'a TrieNode<K, V> {
-        self.node
+        let parent: &'a TrieNode<K, V> = self.parent;
+        parent.child_or_self(self.bucket)
     }
 }
 
@@ -125,11 +165,12 @@ where
 {
     /// **Computes** from scratch.
     fn len(self) -> usize {
-        self.node.compute_size()
+        self.trie_node().compute_size()
     }
 
     fn children(self) -> Children<'a, K, V> {
-        Children::new(self.prefix.clone(), self.node)
+        let prefix = self.prefix.clone();
+        Children::new(prefix, self.trie_node())
     }
 }
 
@@ -139,7 +180,7 @@ where
     K: TrieKey,
 {
     fn trie_node(self) -> &'b TrieNode<K, V> {
-        self.node
+        self.parent.child_or_self(self.bucket)
     }
 }
 
@@ -148,10 +189,10 @@ where
     K: TrieKey,
 {
     fn len(self) -> usize {
-        self.node.compute_size()
+        self.trie_node().compute_size()
     }
 
     fn children(self) -> Children<'b, K, V> {
-        Children::new(self.prefix.clone(), self.node)
+        Children::new(self.prefix.clone(), self.trie_node())
     }
 }
@@ -1,7 +1,8 @@
 use keys::*;
 use std::borrow::Borrow;
 use std::default::Default;
-use {NibbleVec, SubTrie, SubTrieMut, BRANCH_FACTOR};
+use std::mem;
+use {NibbleVec, SubTrie, SubTrieMut, Trie, BRANCH_FACTOR};
 
 #[derive(Debug, Clone)]
 pub struct TrieNode<K, V> {
@@ -109,6 +110,7 @@ where
 
     /// Compute the number of keys and values in this node's subtrie.
     pub fn compute_size(&self) -> usize {
+        record!(nodes_visited += 1);
         let mut size = if self.key_value.is_some() { 1 } else { 0 };
 
         for child in &self.children {
@@ -121,6 +123,76 @@ where
         size
     }
 
+    /// Make this node, whose full key is `prefix`, the only content of a new trie.
+    pub fn into_trie(mut self, prefix: NibbleVec) -> Trie<K, V> {
+        let length = self.compute_size();
+        let node = if prefix.is_empty() {
+            self
+        } else {
+            let mut root = TrieNode::new();
+            self.key = prefix;
+            root.add_child(self.key.get(0) as usize, Box::new(self));
+            root
+        };
+        Trie {
+            length,
+            node,
+            counts: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }
+    }
+
+    /// Build a trie from entries that are sorted in iteration order, without duplicates.
+    ///
+    /// Each entry is the length of the common prefix of its encoded key with the previous
+    /// entry's, followed by the encoded key, the key and the value.
+    pub fn from_sorted<I>(entries: I) -> TrieNode<K, V>
+    where
+        I: IntoIterator<Item = (usize, NibbleVec, K, V)>,
+    {
+        // The nodes along the path to the last entry, with the lengths of their full keys.
+        let mut stack = vec![(TrieNode::new(), 0)];
+
+        for (common, mut nv, key, value) in entries {
+            // Finish the nodes that are too deep to be ancestors of this entry, splitting the
+            // last one if this entry branches off part way along its key.
+            while stack[stack.len() - 1].1 > common {
+                let (mut node, end) = stack.pop().unwrap();
+                let parent_end = stack[stack.len() - 1].1;
+                if parent_end >= common {
+                    stack.last_mut().unwrap().0.add_sorted_child(node);
+                } else {
+                    let tail = node.key.split(common - (end - node.key.len()));
+                    let mut branch = TrieNode::new();
+                    branch.key = mem::replace(&mut node.key, tail);
+                    branch.add_sorted_child(node);
+                    stack.push((branch, common));
+                }
+            }
+
+            let (parent, end) = stack.last_mut().unwrap();
+            let len = nv.len();
+            if len == *end {
+                // Only the root can be reached this way, as a key sorts before its extensions.
+                parent.add_key_value(key, value);
+            } else {
+                let suffix = nv.split(*end);
+                stack.push((TrieNode::with_key_value(suffix, key, value), len));
+            }
+        }
+
+        while stack.len() > 1 {
+            let (node, _) = stack.pop().unwrap();
+            stack.last_mut().unwrap().0.add_sorted_child(node);
+        }
+        stack.pop().unwrap().0
+    }
+
+    fn add_sorted_child(&mut self, child: TrieNode<K, V>) {
+        self.add_child(child.key.get(0) as usize, Box::new(child));
+    }
+
     /// Add a child at the given index, given that none exists there already.
     pub fn add_child(&mut self, idx: usize, node: Box<TrieNode<K, V>>) {
         debug_assert!(self.children[idx].is_none());
@@ -188,6 +260,9 @@ where
     /// Split a node at a given index in its key, transforming it into a prefix node of its
     /// previous self.
     pub fn split(&mut self, idx: usize) {
+        record!(splits += 1);
+        record!(nibble_vec_allocations += 1);
+
         // Extract all the parts of the suffix node, starting with the key.
         let key = self.key.split(idx);
 
@@ -221,18 +296,24 @@ where
         SubTrie {
             prefix: prefix,
             node: self,
+            length: None,
         }
     }
 
+    /// Create a mutable subtrie for the child in `bucket`, or for this node if `bucket` is
+    /// `None`.
     pub fn as_subtrie_mut<'a>(
         &'a mut self,
+        bucket: Option<usize>,
         prefix: NibbleVec,
         length: &'a mut usize,
     ) -> SubTrieMut<'a, K, V> {
         SubTrieMut {
+            scope: prefix.len(),
             prefix: prefix,
             length: length,
-            node: self,
+            parent: self,
+            bucket,
         }
     }
 
@@ -297,6 +378,24 @@ where
     }
 }
 
+impl<K, V> TrieNode<K, V> {
+    /// Get the child in `bucket`, or this node if `bucket` is `None`.
+    pub fn child_or_self(&self, bucket: Option<usize>) -> &TrieNode<K, V> {
+        match bucket {
+            Some(idx) => self.children[idx].as_ref().unwrap(),
+            None => self,
+        }
+    }
+
+    /// Get the child in `bucket`, or this node if `bucket` is `None`.
+    pub fn child_or_self_mut(&mut self, bucket: Option<usize>) -> &mut TrieNode<K, V> {
+        match bucket {
+            Some(idx) => self.children[idx].as_mut().unwrap(),
+            None => self,
+        }
+    }
+}
+
 impl<K: TrieKey, V> Default for TrieNode<K, V> {
     fn default() -> Self {
         Self::new()
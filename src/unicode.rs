@@ -0,0 +1,215 @@
+//! Unicode-normalizing key wrapper (requires the `unicode` feature).
+
+extern crate unicode_normalization;
+
+use self::unicode_normalization::{is_nfc, is_nfkc, UnicodeNormalization};
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use TrieKey;
+
+/// A Unicode normalization form, used to parameterise `Normalized`.
+pub trait NormalizationForm {
+    /// Check whether a string is already in this form.
+    fn is_normalized(s: &str) -> bool;
+
+    /// Convert a string to this form.
+    fn normalize(s: &str) -> String;
+
+    /// Check whether two strings are equal once converted to this form.
+    fn normalized_eq(a: &str, b: &str) -> bool;
+}
+
+/// Canonical composition (NFC), the default for `Normalized`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nfc;
+
+/// Compatibility composition (NFKC), which also folds compatibility characters like
+/// ligatures and full-width forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nfkc;
+
+impl NormalizationForm for Nfc {
+    fn is_normalized(s: &str) -> bool {
+        is_nfc(s)
+    }
+
+    fn normalize(s: &str) -> String {
+        s.nfc().collect()
+    }
+
+    fn normalized_eq(a: &str, b: &str) -> bool {
+        a.nfc().eq(b.nfc())
+    }
+}
+
+impl NormalizationForm for Nfkc {
+    fn is_normalized(s: &str) -> bool {
+        is_nfkc(s)
+    }
+
+    fn normalize(s: &str) -> String {
+        s.nfkc().collect()
+    }
+
+    fn normalized_eq(a: &str, b: &str) -> bool {
+        a.nfkc().eq(b.nfkc())
+    }
+}
+
+/// String key wrapper that treats strings as equal if they have the same Unicode normalization.
+///
+/// Keys are encoded as the UTF-8 bytes of their normalized form (NFC by default, or NFKC
+/// with `Normalized<S, Nfkc>`), and compared for equality by their normalized forms, so that
+/// differently composed spellings of the same text are the *same* key. The original string is
+/// kept as-is, and is what you get back from the trie's iterators.
+///
+/// Strings that are already normalized (such as all ASCII strings) are encoded and compared
+/// without normalizing them first.
+#[derive(Debug, Clone)]
+pub struct Normalized<S, F = Nfc> {
+    inner: S,
+    form: PhantomData<F>,
+}
+
+impl<S, F> Normalized<S, F>
+where
+    S: AsRef<str>,
+    F: NormalizationForm,
+{
+    /// Wrap a string.
+    pub fn new(inner: S) -> Normalized<S, F> {
+        Normalized {
+            inner,
+            form: PhantomData,
+        }
+    }
+
+    /// Get a reference to the original, unnormalized string.
+    pub fn original(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwrap the original, unnormalized string.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Get the normalized form of the string, only allocating if it isn't already normalized.
+    pub fn normalized(&self) -> Cow<'_, str> {
+        let s = self.inner.as_ref();
+        if F::is_normalized(s) {
+            Cow::Borrowed(s)
+        } else {
+            Cow::Owned(F::normalize(s))
+        }
+    }
+}
+
+impl<S, F> From<S> for Normalized<S, F>
+where
+    S: AsRef<str>,
+    F: NormalizationForm,
+{
+    fn from(inner: S) -> Normalized<S, F> {
+        Normalized::new(inner)
+    }
+}
+
+impl<S, F> PartialEq for Normalized<S, F>
+where
+    S: AsRef<str>,
+    F: NormalizationForm,
+{
+    fn eq(&self, other: &Normalized<S, F>) -> bool {
+        let (a, b) = (self.inner.as_ref(), other.inner.as_ref());
+        a == b || F::normalized_eq(a, b)
+    }
+}
+
+impl<S, F> Eq for Normalized<S, F>
+where
+    S: AsRef<str>,
+    F: NormalizationForm,
+{
+}
+
+impl<S, F> TrieKey for Normalized<S, F>
+where
+    S: AsRef<str>,
+    F: NormalizationForm,
+{
+    fn encode_bytes(&self) -> Vec<u8> {
+        match self.normalized() {
+            Cow::Borrowed(s) => s.as_bytes().to_vec(),
+            Cow::Owned(s) => s.into_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Nfc, Nfkc, Normalized};
+    use std::borrow::Cow;
+    use {Trie, TrieCommon, TrieKey};
+
+    const COMPOSED: &str = "caf\u{e9}";
+    const DECOMPOSED: &str = "cafe\u{301}";
+
+    #[test]
+    fn decomposed_insert_composed_lookup() {
+        let mut trie = Trie::new();
+        trie.insert(Normalized::<_>::new(DECOMPOSED.to_string()), 1);
+        trie.insert(Normalized::new("cafeteria".to_string()), 2);
+        assert!(trie.check_integrity());
+
+        let composed = Normalized::new(COMPOSED.to_string());
+        assert_eq!(trie.get(&composed), Some(&1));
+
+        // The stored key is the original spelling.
+        let stored = trie.subtrie(&composed).unwrap().key().unwrap();
+        assert_eq!(stored.original(), DECOMPOSED);
+
+        // Re-inserting the other spelling replaces the value, rather than panicking.
+        assert_eq!(trie.insert(composed.clone(), 3), Some(1));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(
+            trie.remove(&Normalized::new(DECOMPOSED.to_string())),
+            Some(3)
+        );
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[test]
+    fn encoding_is_normalized() {
+        let composed: Normalized<&str> = Normalized::new(COMPOSED);
+        let decomposed: Normalized<&str> = Normalized::new(DECOMPOSED);
+        assert_eq!(composed, decomposed);
+        assert_eq!(composed.encode_bytes(), COMPOSED.as_bytes().to_vec());
+        assert_eq!(decomposed.encode_bytes(), COMPOSED.as_bytes().to_vec());
+        assert_ne!(Normalized::<&str>::new("cafe"), composed);
+    }
+
+    #[test]
+    fn already_normalized_unchanged() {
+        for &s in &["", "hello", "caf\u{e9}", "\u{1f600} emoji", "\u{fb01}"] {
+            let key: Normalized<&str, Nfc> = Normalized::new(s);
+            match key.normalized() {
+                Cow::Borrowed(n) => assert_eq!(n, s),
+                Cow::Owned(_) => panic!("{:?} was needlessly normalized", s),
+            }
+            assert_eq!(key.encode_bytes(), s.as_bytes().to_vec());
+        }
+    }
+
+    #[test]
+    fn nfkc_folds_compatibility_characters() {
+        let mut trie = Trie::new();
+        trie.insert(Normalized::<_, Nfkc>::new("\u{fb01}le"), 1);
+        assert_eq!(trie.get(&Normalized::new("file")), Some(&1));
+
+        // NFC keeps the ligature distinct.
+        let mut trie = Trie::new();
+        trie.insert(Normalized::<_, Nfc>::new("\u{fb01}le"), 1);
+        assert_eq!(trie.get(&Normalized::new("file")), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Views of all the keys with a given prefix, whether or not it ends at a node.
+
+use iter::{Iter, Keys, Values};
+use keys::*;
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::fmt;
+use traversal::DescendantResult::*;
+use {NibbleVec, SubTrie, Trie, TrieCommon, TrieView};
+
+impl<K, V> Trie<K, V>
+where
+    K: TrieKey,
+{
+    /// Get a read-only view of the keys that start with `prefix`.
+    ///
+    /// This always succeeds: if the prefix ends part way along a node's key the view starts
+    /// at that node, and if no keys start with the prefix the view is empty.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn view<Q>(&self, prefix: &Q) -> TrieView<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        TrieView::new(
+            prefix.encode(),
+            Some(self.node.as_subtrie(NibbleVec::new())),
+        )
+    }
+
+    /// Copy the keys that start with `prefix`, including `prefix` itself if it's in the trie,
+    /// in iteration order.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn keys_with_prefix<Q>(&self, prefix: &Q) -> Vec<K>
+    where
+        K: Borrow<Q> + Clone,
+        Q: ?Sized + TrieKey,
+    {
+        self.collect_prefix(prefix, |key, _| key.clone())
+    }
+
+    /// Apply `f` to each key that starts with `prefix` and its value, in iteration order,
+    /// and collect the results.
+    ///
+    /// This builds owned results in one pass over the keys, e.g. to hand them across an FFI
+    /// boundary.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn collect_prefix<Q, F, T>(&self, prefix: &Q, mut f: F) -> Vec<T>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+        F: FnMut(&K, &V) -> T,
+    {
+        self.view(prefix).iter().map(|(k, v)| f(k, v)).collect()
+    }
+}
+
+impl<'a, K, V> TrieView<'a, K, V>
+where
+    K: TrieKey,
+{
+    // Make the view of the keys starting with `prefix` below `subtrie`.
+    fn new(prefix: NibbleVec, subtrie: Option<SubTrie<'a, K, V>>) -> TrieView<'a, K, V> {
+        let subtrie = subtrie.and_then(|subtrie| descend(subtrie, &prefix));
+        TrieView {
+            prefix,
+            subtrie,
+            length: Cell::new(None),
+        }
+    }
+
+    /// Get a view of the keys in this view that also start with `prefix`.
+    ///
+    /// The prefix is a whole key, not a continuation of this view's prefix.
+    pub fn view<Q>(&self, prefix: &Q) -> TrieView<'a, K, V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        let prefix = prefix.encode();
+        match match_keys(0, &self.prefix, &prefix) {
+            KeyMatch::Full | KeyMatch::SecondPrefix => self.clone(),
+            KeyMatch::FirstPrefix => TrieView::new(prefix, self.subtrie.clone()),
+            KeyMatch::Partial(_) => TrieView::new(prefix, None),
+        }
+    }
+
+    /// Look up the value for the given key, if it's in the view.
+    ///
+    /// The key may be any borrowed form of the trie's key type, but TrieKey on the borrowed
+    /// form *must* match those for the key type
+    pub fn get<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + TrieKey,
+    {
+        // Every key that starts with the subtrie's prefix starts with the view's too.
+        let subtrie = self.subtrie.as_ref()?;
+        let mut key_enc = key.encode();
+        match match_keys(0, &subtrie.prefix, &key_enc) {
+            KeyMatch::Full => subtrie.node.value_checked(key),
+            KeyMatch::FirstPrefix => {
+                let rest = key_enc.split(subtrie.prefix.len());
+                subtrie
+                    .node
+                    .get(&rest)
+                    .and_then(|node| node.value_checked(key))
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of key/value pairs in the view.
+    ///
+    /// **Computes** from scratch the first time, and then remembers the result.
+    pub fn len(&self) -> usize {
+        if let Some(length) = self.length.get() {
+            return length;
+        }
+        let length = self.subtrie.as_ref().map_or(0, |subtrie| subtrie.len());
+        self.length.set(Some(length));
+        length
+    }
+
+    /// Determine if the view contains 0 key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return an iterator over the keys and values in the view.
+    pub fn iter(&self) -> Iter<'a, K, V> {
+        self.subtrie
+            .as_ref()
+            .map_or_else(Iter::default, |subtrie| subtrie.iter())
+    }
+
+    /// Return an iterator over the keys in the view.
+    pub fn keys(&self) -> Keys<'a, K, V> {
+        Keys::new(self.iter())
+    }
+
+    /// Return an iterator over the values in the view.
+    pub fn values(&self) -> Values<'a, K, V> {
+        Values::new(self.iter())
+    }
+}
+
+// Find the highest node below `subtrie` whose keys all start with `prefix`.
+fn descend<'a, K, V>(subtrie: SubTrie<'a, K, V>, prefix: &NibbleVec) -> Option<SubTrie<'a, K, V>>
+where
+    K: TrieKey,
+{
+    match match_keys(0, prefix, &subtrie.prefix) {
+        KeyMatch::Full | KeyMatch::FirstPrefix => Some(subtrie),
+        KeyMatch::SecondPrefix => {
+            let depth = subtrie.prefix.len();
+            let rest = prefix.clone().split(depth);
+            subtrie
+                .node
+                .get_raw_descendant(&rest)
+                .map(|desc| match desc {
+                    NoModification(node) => node.as_subtrie(prefix.clone()),
+                    ExtendKey(node, idx, extension) => {
+                        let mut full = prefix.clone();
+                        full.split(depth + idx);
+                        node.as_subtrie(full.join(extension))
+                    }
+                })
+        }
+        KeyMatch::Partial(_) => None,
+    }
+}
+
+impl<'a, K, V> Clone for TrieView<'a, K, V> {
+    fn clone(&self) -> Self {
+        TrieView {
+            prefix: self.prefix.clone(),
+            subtrie: self.subtrie.clone(),
+            length: self.length.clone(),
+        }
+    }
+}
+
+/// Formats the view's contents as a map, in iteration order.
+impl<'a, K, V> fmt::Debug for TrieView<'a, K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let iter = match self.subtrie {
+            Some(ref subtrie) => Iter::new(subtrie.node),
+            None => Iter::default(),
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
@@ -0,0 +1,103 @@
+//! Lookups by string and byte-slice keys shouldn't allocate, and builds shouldn't allocate
+//! more than they have to.
+
+extern crate radix_trie;
+
+use radix_trie::{Trie, TrieBuilder};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations made by each thread, so that tests running in parallel don't see
+// each other's.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// The number of allocations made by `f`.
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(|n| n.get());
+    f();
+    ALLOCATIONS.with(|n| n.get()) - before
+}
+
+const KEYS: &[&str] = &[
+    "a",
+    "ab",
+    "abc",
+    "abd",
+    "b",
+    "bcdefghijklmnopqrstuvwxyz",
+    "c",
+];
+
+#[test]
+fn str_lookups() {
+    let mut trie: Trie<String, usize> = KEYS.iter().map(|k| k.to_string()).zip(0..).collect();
+
+    let n = allocations(|| {
+        for (i, k) in KEYS.iter().enumerate() {
+            assert_eq!(trie.get(*k), Some(&i));
+            assert!(trie.contains_key(*k));
+        }
+        assert_eq!(trie.get("abcd"), None);
+        assert!(!trie.contains_key("bc"));
+        assert_eq!(trie.get_ancestor_value("abcd"), Some(&2));
+        assert_eq!(trie.get_ancestor_value("d"), None);
+    });
+    assert_eq!(n, 0);
+
+    let n = allocations(|| *trie.get_mut("abd").unwrap() += 10);
+    assert_eq!(n, 0);
+    assert_eq!(trie.get("abd"), Some(&13));
+}
+
+#[test]
+fn byte_slice_lookups() {
+    let trie: Trie<Vec<u8>, usize> = KEYS
+        .iter()
+        .map(|k| k.as_bytes().to_vec())
+        .zip(0..)
+        .collect();
+
+    let n = allocations(|| {
+        for (i, k) in KEYS.iter().enumerate() {
+            assert_eq!(trie.get(k.as_bytes()), Some(&i));
+        }
+        assert!(!trie.contains_key(&b"abcd"[..]));
+        assert_eq!(trie.get_ancestor_value(&b"abcd"[..]), Some(&2));
+    });
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn expected_keys() {
+    // A filter doesn't know how many entries it'll yield, so without a hint the builder
+    // grows its buffer of them as it goes.
+    let entries = || (0..2000u32).filter(|k| k % 2 == 0).map(|k| (k, ()));
+    let unhinted = allocations(|| {
+        TrieBuilder::new().build_from_sorted(entries()).unwrap();
+    });
+    let hinted = allocations(|| {
+        TrieBuilder::new()
+            .expected_keys(1000)
+            .build_from_sorted(entries())
+            .unwrap();
+    });
+    assert!(hinted + 5 < unhinted, "{} vs {}", hinted, unhinted);
+}
@@ -0,0 +1,137 @@
+//! Interned keys should take less memory than owned ones, for a trie of file paths.
+
+extern crate radix_trie;
+
+use radix_trie::{InternedKey, KeyPool, Trie, TrieBuilder, TrieCommon};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations and live bytes of each thread, so that tests running in parallel
+// don't see each other's.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        LIVE_BYTES.with(|n| n.set(n.get() + layout.size() as isize));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.with(|n| n.set(n.get() - layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// The result of `f`, and the allocations it made and the bytes it left allocated.
+fn measure<T, F: FnOnce() -> T>(f: F) -> (T, usize, isize) {
+    let allocations = ALLOCATIONS.with(|n| n.get());
+    let bytes = LIVE_BYTES.with(|n| n.get());
+    let result = f();
+    (
+        result,
+        ALLOCATIONS.with(|n| n.get()) - allocations,
+        LIVE_BYTES.with(|n| n.get()) - bytes,
+    )
+}
+
+// The paths of the files in a tree of source directories.
+fn paths() -> Vec<String> {
+    let mut paths = vec![];
+    for project in 0..10 {
+        for module in 0..20 {
+            for file in 0..25 {
+                paths.push(format!(
+                    "/home/user/src/project-{:02}/src/module_{:02}/file_{:03}.rs",
+                    project, module, file
+                ));
+            }
+        }
+    }
+    paths
+}
+
+#[test]
+fn interned_paths_take_less_memory() {
+    let paths = paths();
+    let key_bytes: usize = paths.iter().map(|path| path.len()).sum();
+
+    let (_owned, owned_allocations, owned_bytes) = measure(|| {
+        TrieBuilder::<String, usize>::new()
+            .build_from_sorted(paths.iter().cloned().zip(0..))
+            .unwrap()
+    });
+    let ((interned, pool), interned_allocations, interned_bytes) = measure(|| {
+        let mut pool = KeyPool::default();
+        let trie = TrieBuilder::<InternedKey<str>, usize>::new()
+            .build_interned(&mut pool, paths.iter().zip(0..))
+            .unwrap();
+        (trie, pool)
+    });
+    assert_eq!(interned.len(), paths.len());
+
+    // "/", "home/", "user/", "src/", ten projects and their "src/" directories, and 200
+    // modules.
+    let stats = pool.stats();
+    assert_eq!(stats.prefixes, 4 + 2 * 10 + 200);
+
+    // Each key's own bytes fit inside it, and the prefixes and the pool's table of them are
+    // much smaller than the bytes they save.
+    assert!(interned_allocations + paths.len() <= owned_allocations + 4 * stats.prefixes);
+    assert!(interned_bytes + (key_bytes / 2) as isize <= owned_bytes);
+}
+
+#[test]
+fn inserted_keys_share_prefixes() {
+    let paths = paths();
+    let mut pool = KeyPool::default();
+    let mut trie: Trie<InternedKey<str>, usize> = Trie::new();
+    for (i, path) in paths.iter().enumerate() {
+        trie.insert(pool.intern(path), i);
+    }
+    assert_eq!(pool.stats().prefixes, 4 + 2 * 10 + 200);
+
+    // Keys in existing directories only need the trie's own allocations.
+    let (key, allocations, _) =
+        measure(|| pool.intern("/home/user/src/project-03/src/module_07/new.rs"));
+    assert_eq!(allocations, 0);
+    trie.insert(key, 0);
+    assert_eq!(pool.stats().prefixes, 4 + 2 * 10 + 200);
+}
+
+#[test]
+fn removal_leaves_garbage_until_compacted() {
+    let paths = paths();
+    let mut pool = KeyPool::default();
+    let mut trie = TrieBuilder::<InternedKey<str>, usize>::new()
+        .build_interned(&mut pool, paths.iter().zip(0..))
+        .unwrap();
+
+    // Remove the whole of one project.
+    for path in paths.iter().filter(|path| path.contains("project-04")) {
+        assert!(trie.remove(&InternedKey::from(path.as_str())).is_some());
+    }
+    assert!(trie.check_integrity());
+    assert_eq!(trie.len(), paths.len() - 500);
+    let stats = pool.stats();
+    assert_eq!(stats.prefixes, 4 + 2 * 9 + 180);
+    assert_eq!(stats.garbage, 2 + 20);
+
+    let ((), _, freed) = measure(|| pool.compact());
+    assert!(freed < 0);
+    assert_eq!(pool.stats().garbage, 0);
+    assert_eq!(pool.stats().prefixes, stats.prefixes);
+
+    // A removed prefix is made again when it's needed.
+    let key = pool.intern("/home/user/src/project-04/src/module_00/file_000.rs");
+    trie.insert(key, 0);
+    assert_eq!(pool.stats().prefixes, stats.prefixes + 3);
+}
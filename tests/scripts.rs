@@ -0,0 +1,95 @@
+//! Regression scripts: operation sequences that exercise splitting and merging nodes, each
+//! checked step by step against a model by `Trie::apply_script`.
+//!
+//! To add a case found by the fuzz target, replay its input with `replay`.
+
+extern crate radix_trie;
+
+use radix_trie::Op::*;
+use radix_trie::{script_from_bytes, Op, Oracle, Trie};
+
+fn run(ops: &[Op<&'static str, u32>]) {
+    Trie::new().apply_script(ops, &mut Oracle::new());
+}
+
+fn replay(data: &[u8]) {
+    Trie::new().apply_script(&script_from_bytes(data), &mut Oracle::new());
+}
+
+#[test]
+fn split_then_merge() {
+    run(&[
+        Insert("abcdef", 1),
+        // Splits "abcdef" at "abc", then at "ab".
+        Insert("abcxyz", 2),
+        Insert("ab", 3),
+        SubtrieCheck("abc"),
+        SubtrieCheck("abcd"),
+        // Removing the branch keys merges the nodes back together.
+        Remove("ab"),
+        Remove("abcxyz"),
+        SubtrieCheck("abc"),
+        SubtrieCheck("abcdef"),
+        GetCheck("abcdef"),
+        Insert("abc", 4),
+        Remove("abcdef"),
+        GetCheck("abc"),
+    ]);
+}
+
+#[test]
+fn removals_collapse_chains() {
+    run(&[
+        Insert("a", 1),
+        Insert("ab", 2),
+        Insert("abc", 3),
+        Insert("abcd", 4),
+        Insert("abce", 5),
+        Remove("abc"),
+        Remove("ab"),
+        Remove("a"),
+        SubtrieCheck("a"),
+        SubtrieCheck("abc"),
+        Remove("abce"),
+        SubtrieCheck("abcd"),
+        Remove("abcd"),
+        Remove("abcd"),
+        SubtrieCheck(""),
+    ]);
+}
+
+#[test]
+fn remove_prefix_part_way_along_a_node() {
+    run(&[
+        Insert("abcdef", 1),
+        Insert("abcdxy", 2),
+        Insert("b", 3),
+        // There's no node for "ab", so nothing is removed.
+        RemovePrefix("ab"),
+        RemovePrefix("abcd"),
+        GetCheck("abcdef"),
+        Insert("abcd", 4),
+        Insert("abcdef", 5),
+        RemovePrefix("abcdef"),
+        RemovePrefix("abcd"),
+        RemovePrefix(""),
+        Insert("", 6),
+        GetCheck(""),
+    ]);
+}
+
+#[test]
+fn replayed_inputs() {
+    replay(&[]);
+    // Two keys whose encodings share three nibbles, so that they split a node part way along
+    // a byte, then a removal that merges it back, and a trailing partial op.
+    replay(&[
+        0, 2, 0, 0, 1, // Insert([0x00, 0x00], 1)
+        0, 2, 0, 1, 2, // Insert([0x00, 0x01], 2)
+        3, 1, 0, // SubtrieCheck([0x00])
+        1, 2, 0, 0, // Remove([0x00, 0x00])
+        4, 1, 0, // RemovePrefix([0x00])
+        2, 2, 0, 1, // GetCheck([0x00, 0x01])
+        0,
+    ]);
+}